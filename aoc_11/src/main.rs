@@ -5,7 +5,7 @@ use std::sync::mpsc::{self, Sender, Receiver};
 use std::thread;
 use std::collections::HashMap;
 use structopt::StructOpt;
-use intcode::{io, program::IntcodeProgram};
+use intcode::{io, program::IntcodeProgram, stats::Stats};
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -13,6 +13,8 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 struct Cli {
     #[structopt(short = "f", parse(from_os_str))]
     file: PathBuf,
+    #[structopt(long = "stats")]
+    stats: bool,
 }
 
 #[derive(Clone, Copy, Hash, PartialEq, Eq)]
@@ -79,6 +81,7 @@ fn paint_squares(
 fn run_robot_and_paint(
     program: &String,
     start_square_color: bool,
+    stats: &mut Stats,
 ) -> Result<HashMap<Position, bool>> {
 
     let mut program = IntcodeProgram::from_raw_input(&program)?;
@@ -87,18 +90,22 @@ fn run_robot_and_paint(
     let (robot_in_tx, robot_in_rx): (Sender<i64>, Receiver<i64>) = mpsc::channel();
 
     program.replace_input(io::ChannelInputDevice::new(robot_in_rx));
-    program.replace_output(io::ChannelOutputDevice::new(robot_out_tx));
+    // If the painter stops listening the robot should fail rather than run on alone
+    program.replace_output(io::ChannelOutputDevice::with_policy(robot_out_tx, io::ClosedPolicy::Error));
 
-    let robot_thread = thread::spawn(move || {
-        program.execute().unwrap()
+    let robot_thread = thread::spawn(move || -> std::result::Result<Stats, String> {
+        program.execute().map_err(|e| e.to_string())?;
+        Ok(program.stats())
     });
 
     let mut paint_state = HashMap::new();
     paint_state.insert(Position{ x: 0, y: 0 }, start_square_color);
     let painted_squares = paint_squares(paint_state, Position{ x: 0, y: 0 }, robot_out_rx, robot_in_tx);
 
-    if robot_thread.join().is_err() {
-        return Err(From::from("Robot thread panicked"));
+    match robot_thread.join() {
+        Err(_) => return Err(From::from("Robot thread panicked")),
+        Ok(Err(e)) => return Err(From::from(format!("Robot stopped early: {}", e))),
+        Ok(Ok(robot_stats)) => stats.merge(&robot_stats),
     }
 
     Ok(painted_squares)
@@ -135,10 +142,13 @@ fn main() -> Result<()> {
     reader.read_to_string(&mut contents)?;
 
     // Part 1
-    let painted_squares = run_robot_and_paint(&contents, false)?;
+    let mut stats = Stats::new();
+    let painted_squares = run_robot_and_paint(&contents, false, &mut stats)?;
     println!("Num squares painted first round: {}", painted_squares.len());
 
     // Part 2
-    let painted_squares = run_robot_and_paint(&contents, true)?;
-    Ok(print_painted_squares(painted_squares))
+    let painted_squares = run_robot_and_paint(&contents, true, &mut stats)?;
+    print_painted_squares(painted_squares);
+    if opt.stats { eprintln!("{}", stats); }
+    Ok(())
 }
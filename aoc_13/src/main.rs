@@ -7,6 +7,7 @@ use std::time;
 use std::thread;
 use structopt::StructOpt;
 use intcode::program::{Event, IntcodeProgram};
+use intcode::stats::Stats;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -16,6 +17,8 @@ struct Cli {
     file: PathBuf,
     #[structopt(short = "d")]
     display: bool,
+    #[structopt(long = "stats")]
+    stats: bool,
 }
 
 #[derive(PartialEq)]
@@ -82,10 +85,11 @@ impl Screen {
     }
 }
 
-fn part1(input: &String) -> Result<()> {
+fn part1(input: &str, stats: &mut Stats) -> Result<()> {
     let mut program = IntcodeProgram::from_raw_input(input)?;
     let mut screen = Screen{ tiles: HashMap::new() };
     program.execute()?;
+    stats.merge(&program.stats());
 
     for draw_vals in program.get_all_output().chunks(3) {
         if draw_vals.len() != 3 { return Err(From::from("# outputs not divisible by 3")); }
@@ -96,11 +100,9 @@ fn part1(input: &String) -> Result<()> {
         .fold(0, |acc, (_, v)| acc + if *v == TileID::Block { 1 } else { 0 })))
 }
 
-fn part2(input: &String, display: bool) -> Result<()> {
-    let mut memory = IntcodeProgram::raw_to_memory(input)?;
-    memory[0] = 2; // 2 quarters
-
-    let mut program = IntcodeProgram::from_memory(memory);
+fn part2(input: &str, display: bool, stats: &mut Stats) -> Result<()> {
+    let mut program = IntcodeProgram::from_raw_input(input)?;
+    program.write(0, 2)?; // 2 quarters
     let mut screen = Screen{ tiles: HashMap::new() };
     let mut score = 0;
     let mut outputs = vec![];
@@ -125,10 +127,11 @@ fn part2(input: &String, display: bool) -> Result<()> {
                     outputs.clear();
                 }
             },
-            Event::Exited => break
+            Event::Exited | Event::PeerClosed => break
         }
     }
 
+    stats.merge(&program.stats());
     Ok(println!("Final score: {}", score))
 }
 
@@ -140,6 +143,9 @@ fn main() -> Result<()> {
     let mut contents = String::new();
     reader.read_to_string(&mut contents)?;
 
-    part1(&contents)?;
-    part2(&contents, opt.display)
+    let mut stats = Stats::new();
+    part1(&contents, &mut stats)?;
+    part2(&contents, opt.display, &mut stats)?;
+    if opt.stats { eprintln!("{}", stats); }
+    Ok(())
 }
\ No newline at end of file
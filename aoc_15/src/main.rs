@@ -4,7 +4,9 @@ use std::io::{prelude::*, BufReader};
 use std::path::PathBuf;
 use std::collections::{HashMap, HashSet, VecDeque};
 use structopt::StructOpt;
-use intcode::program::{Event, IntcodeProgram};
+use intcode::explore::{explore_with_stats, Strategy};
+use intcode::program::IntcodeProgram;
+use intcode::stats::Stats;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -12,6 +14,8 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 struct Cli {
     #[structopt(short = "f", parse(from_os_str))]
     file: PathBuf,
+    #[structopt(long = "stats")]
+    stats: bool,
 }
 
 #[derive(Copy, Clone)]
@@ -29,12 +33,12 @@ impl Dir {
         }
     }
 
-    fn reverse(&self) -> Dir {
-        match self {
-            Dir::North => Dir::South,
-            Dir::South => Dir::North,
-            Dir::East => Dir::West,
-            Dir::West => Dir::East,
+    fn from_input(input: i64) -> Dir {
+        match input {
+            1 => Dir::North,
+            2 => Dir::South,
+            3 => Dir::West,
+            _ => Dir::East,
         }
     }
 
@@ -56,11 +60,6 @@ enum Tile {
 struct Mapper {
     program: IntcodeProgram,
     map: HashMap<(i64, i64), Tile>,
-    curr_loc: (i64, i64),
-    curr_dir: Dir,
-    move_stack: Vec<Dir>,
-    option_stack: Vec<Vec<Dir>>,
-    reversing: bool,
 }
 
 impl Mapper {
@@ -68,55 +67,24 @@ impl Mapper {
         let mut mapper = Mapper{
             program: IntcodeProgram::from_raw_input(program_raw)?,
             map: HashMap::new(),
-            curr_loc: (0, 0),
-            curr_dir: Dir::North,
-            move_stack: vec![],
-            option_stack: vec![Dir::get_all()],
-            reversing: false,
         };
         mapper.map.insert((0, 0), Tile::Empty);
         Ok(mapper)
     }
 
-    fn map_section(&mut self) -> Result<()> {
-        loop {
-            match self.program.execute_until_event()? {
-                Event::Exited => return Ok(()),
-                Event::InputRequired => {
-                    if let Some(dir) = self.option_stack.last_mut().unwrap().pop() {
-                        self.reversing = false;
-                        self.program.give_input(dir as i64);
-                        self.curr_dir = dir;
-                    } else if let Some(dir) = self.move_stack.pop() {
-                        // Go back to previous square
-                        self.option_stack.pop();
-                        self.reversing = true;
-                        self.curr_dir = dir.reverse();
-                        self.program.give_input(self.curr_dir as i64);
-                        self.curr_loc = self.curr_dir.apply(self.curr_loc);
-                    } else {
-                        return Ok(())
-                    }
-                },
-                Event::ProducedOutput => {
-                    let output = self.program.get_output().unwrap();
-                    if self.reversing { continue }
-                    match output {
-                        0 => { // Hit a wall
-                            self.map.insert(self.curr_dir.apply(self.curr_loc), Tile::Wall);
-                        },
-                        x => {
-                            self.curr_loc = self.curr_dir.apply(self.curr_loc);
-                            self.map.insert(self.curr_loc, if x == 1 { Tile::Empty } else { Tile::Oxygen });
-                            self.move_stack.push(self.curr_dir);
-                            self.option_stack.push(Dir::get_all().iter().filter_map(|x| { // Push unmapped positions
-                                if self.map.contains_key(&(x.apply(self.curr_loc))) { None } else { Some(*x) }
-                            }).collect());
-                        },
-                    }
-                }
+    // Forks the droid at every move instead of walking it back, so no backtracking is needed
+    fn map_section(&mut self, stats: &mut Stats) -> Result<()> {
+        let map = &mut self.map;
+        let moves = Dir::get_all().iter().map(|dir| *dir as i64).collect::<Vec<i64>>();
+        explore_with_stats(&self.program, (0, 0), &moves, Strategy::BreadthFirst, |loc, input, outputs| {
+            let next = Dir::from_input(input).apply(*loc);
+            match outputs.first() {
+                Some(0) => { map.insert(next, Tile::Wall); None }, // Hit a wall
+                Some(x) => { map.insert(next, if *x == 1 { Tile::Empty } else { Tile::Oxygen }); Some(next) },
+                None => None,
             }
-        }
+        }, |loc| *loc, stats)?;
+        Ok(())
     }
 
     fn to_grid(&self) -> (Vec<Vec<Tile>>, (i64, i64)) {
@@ -189,8 +157,11 @@ fn main() -> Result<()> {
     reader.read_to_string(&mut contents)?;
     
     let mut mapper = Mapper::init(&contents)?;
-    mapper.map_section()?;
+    let mut stats = Stats::new();
+    mapper.map_section(&mut stats)?;
     let (map, start) = mapper.to_grid();
     let oxygen_location = part1(&map, start)?;
-    part2(&map, oxygen_location)
+    part2(&map, oxygen_location)?;
+    if opt.stats { eprintln!("{}", stats); }
+    Ok(())
 }
@@ -4,6 +4,7 @@ use std::io::{prelude::*, BufReader};
 use std::path::PathBuf;
 use structopt::StructOpt;
 use intcode::program::IntcodeProgram;
+use intcode::stats::Stats;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -11,6 +12,8 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 struct Cli {
     #[structopt(short = "f", parse(from_os_str))]
     file: PathBuf,
+    #[structopt(long = "stats")]
+    stats: bool,
 }
 
 struct RobotInput {
@@ -264,14 +267,15 @@ fn get_robot_input(
     None
 }
 
-fn input_line(program: &mut IntcodeProgram, line: &String) {
+fn input_line(program: &mut IntcodeProgram, line: &str) {
     for c in line.chars() { program.give_input((c as u8) as i64); }
     program.give_input(10);
 }
 
-fn part1(input: &String) -> Result<Vec<Vec<char>>> {
+fn part1(input: &str, stats: &mut Stats) -> Result<Vec<Vec<char>>> {
     let mut program = IntcodeProgram::from_raw_input(input)?;
     program.execute()?;
+    stats.merge(&program.stats());
 
     let (mut grid, mut curr_line) = (vec![], vec![]);
     for c in program.get_all_output() {
@@ -294,11 +298,10 @@ fn part1(input: &String) -> Result<Vec<Vec<char>>> {
     Ok(grid)
 }
 
-fn part2(input: &String, grid: &mut Vec<Vec<char>>) -> Result<()> {
+fn part2(input: &str, grid: &mut Vec<Vec<char>>, stats: &mut Stats) -> Result<()> {
     if let Some(robot_input) = get_robot_input(&get_path(&grid)) {
-        let mut memory = IntcodeProgram::raw_to_memory(input)?;
-        memory[0] = 2;
-        let mut program = IntcodeProgram::from_memory(memory);
+        let mut program = IntcodeProgram::from_raw_input(input)?;
+        program.write(0, 2)?;
 
         // Input main movement routine and functions, decline video feed
         input_line(&mut program, &robot_input.sequence);
@@ -308,6 +311,7 @@ fn part2(input: &String, grid: &mut Vec<Vec<char>>) -> Result<()> {
         input_line(&mut program, &"n".to_owned());
 
         program.execute()?;
+        stats.merge(&program.stats());
         if let Some(dust_collected) = program.get_all_output().last() {
             Ok(println!("Dust collected: {}", dust_collected))
         } else {
@@ -326,7 +330,9 @@ fn main() -> Result<()> {
     let mut contents = String::new();
     reader.read_to_string(&mut contents)?;
 
-    let mut grid = part1(&contents)?;
-    part2(&contents, &mut grid)?;
+    let mut stats = Stats::new();
+    let mut grid = part1(&contents, &mut stats)?;
+    part2(&contents, &mut grid, &mut stats)?;
+    if opt.stats { eprintln!("{}", stats); }
     Ok(())
 }
\ No newline at end of file
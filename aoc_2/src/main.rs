@@ -3,6 +3,7 @@ use std::io::{prelude::*, BufReader};
 use std::path::PathBuf;
 use structopt::StructOpt;
 use intcode::program::IntcodeProgram;
+use intcode::stats::Stats;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -16,15 +17,18 @@ struct Cli {
     noun: Option<i64>,
     #[structopt(short = "v")]
     verb: Option<i64>,
+    #[structopt(long = "stats")]
+    stats: bool,
 }
 
-fn run_intcode_for_noun_verb(memory: &Vec<i64>, noun: i64, verb: i64) -> Result<i64> {
+fn run_intcode_for_noun_verb(memory: &Vec<i64>, noun: i64, verb: i64, stats: &mut Stats) -> Result<i64> {
     let mut new_memory = memory.clone();
     new_memory[1] = noun;
     new_memory[2] = verb;
 
     let mut program = IntcodeProgram::from_memory(new_memory);
     program.execute()?;
+    stats.merge(&program.stats());
     Ok(program.load_position(0))
 }
 
@@ -37,12 +41,13 @@ fn main() -> Result<()> {
     reader.read_to_string(&mut contents).unwrap();
 
     let original_memory = IntcodeProgram::raw_to_memory(&contents)?;
+    let mut stats = Stats::new();
 
     if let Some(desired_output) = opt.desired {
         // Scan noun/verb 0-99 to find desired output at location to examine
         for noun in 0..100 {
             for verb in 0..100 {
-                if run_intcode_for_noun_verb(&original_memory, noun, verb)? == desired_output {
+                if run_intcode_for_noun_verb(&original_memory, noun, verb, &mut stats)? == desired_output {
                     println!(
                         "Found values [noun: {}, verb: {}] that produce {} at location 0 after execution!",
                         noun,
@@ -56,11 +61,12 @@ fn main() -> Result<()> {
     } else if let (Some(noun), Some(verb)) = (opt.noun, opt.verb) {
         println!(
             "Value in memory location 0 after executing intcode: {}",
-            run_intcode_for_noun_verb(&original_memory, noun, verb)?
+            run_intcode_for_noun_verb(&original_memory, noun, verb, &mut stats)?
         );
     } else {
         return Err(From::from("Was not provided with noun & verb or desired output!"))
     }
 
+    if opt.stats { eprintln!("{}", stats); }
     Ok(())
 }
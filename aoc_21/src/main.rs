@@ -3,6 +3,7 @@ use std::io::{prelude::*, BufReader};
 use std::path::PathBuf;
 use structopt::StructOpt;
 use intcode::program::IntcodeProgram;
+use intcode::stats::Stats;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -10,6 +11,8 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 struct Cli {
     #[structopt(short = "f", parse(from_os_str))]
     file: PathBuf,
+    #[structopt(long = "stats")]
+    stats: bool,
 }
 
 fn input_line(program: &mut IntcodeProgram, line: &str) {
@@ -29,7 +32,7 @@ fn print_ascii_output(program: &mut IntcodeProgram) {
     }
 }
 
-fn part1(input: &String) -> Result<()> {
+fn part1(input: &str, stats: &mut Stats) -> Result<()> {
     let mut program = IntcodeProgram::from_raw_input(input)?;
     let swift_script = vec![
         // !c && d
@@ -48,10 +51,11 @@ fn part1(input: &String) -> Result<()> {
         input_line(&mut program, line);
     }
     program.execute()?;
+    stats.merge(&program.stats());
     Ok(println!("Hull damage: {}", program.get_all_output().last().unwrap_or(&0)))
 }
 
-fn part2(input: &String) -> Result<()> {
+fn part2(input: &str, stats: &mut Stats) -> Result<()> {
     let mut program = IntcodeProgram::from_raw_input(input)?;
     let swift_script = vec![
         // !c && d && (!f || h)
@@ -79,6 +83,7 @@ fn part2(input: &String) -> Result<()> {
         input_line(&mut program, line);
     }
     program.execute()?;
+    stats.merge(&program.stats());
     Ok(println!("Hull damage: {}", program.get_all_output().last().unwrap_or(&0)))
 }
 
@@ -90,7 +95,9 @@ fn main() -> Result<()> {
     let mut contents = String::new();
     reader.read_to_string(&mut contents)?;
 
-    part1(&contents)?;
-    part2(&contents)?;
+    let mut stats = Stats::new();
+    part1(&contents, &mut stats)?;
+    part2(&contents, &mut stats)?;
+    if opt.stats { eprintln!("{}", stats); }
     Ok(())
 }
\ No newline at end of file
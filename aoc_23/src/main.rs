@@ -2,8 +2,12 @@ use std::fs::File;
 use std::io::{prelude::*, BufReader};
 use std::path::PathBuf;
 use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
 use structopt::StructOpt;
+use intcode::chrome::{ChromeTrace, Flow};
+use intcode::network::{Network, Policy};
 use intcode::program::{Event, IntcodeProgram};
+use intcode::stats::Stats;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -11,44 +15,81 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 struct Cli {
     #[structopt(short = "f", parse(from_os_str))]
     file: PathBuf,
+    #[structopt(long = "stats")]
+    stats: bool,
+    /// Write a Chrome trace of the network to this file
+    #[structopt(long = "trace", parse(from_os_str))]
+    trace: Option<PathBuf>,
+    /// Shuffle the computers' turns and cut them short at random, seeded with this
+    #[structopt(long = "seed")]
+    seed: Option<u64>,
+    /// Longest turn in instructions when turns are cut short at random
+    #[structopt(long = "max-quantum", default_value = "1000")]
+    max_quantum: usize,
+    /// Also run with this many following seeds, checking they give the same answers
+    #[structopt(long = "interleavings", default_value = "1")]
+    interleavings: u64,
 }
 
-fn run(input: &String) -> Result<()> {
+// The Y values of the first packet the NAT sends and the first it sends twice in a row
+fn run(input: &str, policy: Policy, stats: &mut Stats, trace: Option<&Arc<ChromeTrace>>) -> Result<(i64, i64)> {
     let memory = IntcodeProgram::raw_to_memory(input)?;
-    let mut programs = (0..50).map(|addr| {
+    let mut network = Network::new(policy);
+    for addr in 0..50 {
         let mut program = IntcodeProgram::from_memory(memory.clone());
         program.give_input(addr as i64);
-        program
-    }).collect::<Vec<IntcodeProgram>>();
+        network.add(program);
+    }
+
+    // The NAT gets the track after the computers'
+    let nat = 50;
+    if let Some(trace) = trace {
+        for addr in 0..50 { trace.add_machine(&format!("computer{}", addr)); }
+        trace.add_machine("NAT");
+    }
+    network.set_trace(trace.cloned());
 
     let mut buffered_out: Vec<Vec<i64>> = vec![vec![]; 50];
-    let mut ready_packets: HashMap<usize, VecDeque<(i64, i64)>> = HashMap::new();
+    let mut ready_packets: HashMap<usize, VecDeque<(i64, i64, Option<Flow>)>> = HashMap::new();
+    // A computer is idle from when it finds no packet waiting until it next sends something
+    let mut idle = [false; 50];
     let (mut last_nat_packet, mut nat_packet): (Option<(i64, i64)>, Option<(i64, i64)>) = (None, None);
+    let mut first_nat_y = None;
     loop {
-        let mut idle = true;
-        for (idx, program) in programs.iter_mut().enumerate() {
-            match program.execute_until_event()? {
-                Event::Exited => () /* An exited program will continue to emit this event */,
+        network.tick(|idx, program, event| {
+            match event {
+                Event::Exited | Event::PeerClosed => (),
                 Event::InputRequired => {
                     let entry = ready_packets.entry(idx).or_insert(VecDeque::new());
-                    if let Some((x, y)) = entry.pop_back() {
-                        idle = false;
+                    if let Some((x, y, flow)) = entry.pop_back() {
+                        idle[idx] = false;
                         program.give_input(x);
                         program.give_input(y);
+                        if let Some(trace) = trace {
+                            trace.input(idx, x);
+                            trace.input(idx, y);
+                            if let Some(flow) = flow { trace.receive(idx, flow); }
+                        }
                     } else {
+                        idle[idx] = true;
                         program.give_input(-1);
                     }
                 },
                 Event::ProducedOutput => {
-                    idle = false;
-                    buffered_out[idx].push(program.get_output().unwrap());
+                    idle[idx] = false;
+                    let value = program.get_output().unwrap();
+                    if let Some(trace) = trace { trace.output(idx, value); }
+                    buffered_out[idx].push(value);
                     if buffered_out[idx].len() == 3 {
                         let (addr, x, y) = (buffered_out[idx][0] as usize, buffered_out[idx][1], buffered_out[idx][2]);
                         buffered_out[idx].clear();
+                        let flow = trace.map(|trace| trace.send(idx));
                         if addr < 50 {
                             let entry = ready_packets.entry(addr).or_insert(VecDeque::new());
-                            entry.push_front((x, y));
+                            entry.push_front((x, y, flow));
                         } else if addr == 255 {
+                            // The NAT's track shows the packets it received when it next wakes
+                            if let (Some(trace), Some(flow)) = (trace, flow) { trace.receive(nat, flow); }
                             nat_packet = Some((x, y));
                         } else {
                             return Err(From::from(format!("Invalid addr: {}", addr)));
@@ -56,24 +97,33 @@ fn run(input: &String) -> Result<()> {
                     }
                 },
             }
-        }
+            Ok(())
+        })?;
 
-        if idle && nat_packet.is_some() {
+        let network_idle = idle.iter().all(|&idle| idle) && ready_packets.values().all(|packets| packets.is_empty());
+        if network_idle && nat_packet.is_some() {
             let packet = nat_packet.unwrap();
             if last_nat_packet.is_some() && packet.1 == last_nat_packet.unwrap().1 {
-                println!("First repeated Y value sent by NAT: {}", packet.1);
-                break
+                for program in network.programs() { stats.merge(&program.stats()); }
+                return Ok((first_nat_y.unwrap(), packet.1))
             } else if last_nat_packet.is_none() {
-                println!("Y value of first packet sent to NAT: {}", packet.1);
+                first_nat_y = Some(packet.1);
             }
 
+            let flow = trace.map(|trace| {
+                trace.resume(nat);
+                trace.input(nat, packet.0);
+                trace.input(nat, packet.1);
+                let flow = trace.send(nat);
+                trace.pause(nat);
+                flow
+            });
             let entry = ready_packets.entry(0).or_insert(VecDeque::new());
-            entry.push_front(packet);
+            entry.push_front((packet.0, packet.1, flow));
             last_nat_packet = nat_packet;
             nat_packet = None;
         }
     }
-    Ok(())
 }
 
 fn main() -> Result<()> {
@@ -84,5 +134,30 @@ fn main() -> Result<()> {
     let mut contents = String::new();
     reader.read_to_string(&mut contents)?;
 
-    run(&contents)
+    let policy = match opt.seed {
+        Some(seed) => Policy::random(seed, opt.max_quantum),
+        None => Policy::round_robin(),
+    };
+    let mut stats = Stats::new();
+    let trace = opt.trace.as_ref().map(|_| ChromeTrace::new());
+    let (first, repeated) = run(&contents, policy, &mut stats, trace.as_ref())?;
+    if let (Some(path), Some(trace)) = (opt.trace, trace) { trace.write(File::create(path)?)?; }
+    println!("Y value of first packet sent to NAT: {}", first);
+    println!("First repeated Y value sent by NAT: {}", repeated);
+
+    // The answers shouldn't depend on the order the computers happen to run in
+    let start = opt.seed.unwrap_or(0);
+    let mut differing = 0;
+    for seed in (start + 1..).take(opt.interleavings.saturating_sub(1) as usize) {
+        let answers = run(&contents, Policy::random(seed, opt.max_quantum), &mut stats, None)?;
+        if answers != (first, repeated) {
+            println!("Seed {} gives different answers: {} and {}", seed, answers.0, answers.1);
+            differing += 1;
+        }
+    }
+    if opt.stats { eprintln!("{}", stats); }
+    if differing > 0 {
+        return Err(From::from(format!("{} of {} interleavings gave different answers", differing, opt.interleavings)));
+    }
+    Ok(())
 }
@@ -3,6 +3,7 @@ use std::io::{self, prelude::*, BufReader};
 use std::path::PathBuf;
 use structopt::StructOpt;
 use intcode::program::{Event, IntcodeProgram};
+use intcode::stats::Stats;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -10,6 +11,8 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 struct Cli {
     #[structopt(short = "f", parse(from_os_str))]
     file: PathBuf,
+    #[structopt(long = "stats")]
+    stats: bool,
 }
 
 fn get_input_line(program: &mut IntcodeProgram) -> Result<()> {
@@ -22,12 +25,12 @@ fn get_input_line(program: &mut IntcodeProgram) -> Result<()> {
     Ok(())
 }
 
-fn run_text_adventure(input: &String) -> Result<()> {
+fn run_text_adventure(input: &str, stats: &mut Stats) -> Result<()> {
     let mut program = IntcodeProgram::from_raw_input(input)?;
     let mut output_buffer = vec![];
     loop {
         match program.execute_until_event()? {
-            Event::Exited => break,
+            Event::Exited | Event::PeerClosed => break,
             Event::InputRequired => get_input_line(&mut program)?,
             Event::ProducedOutput => {
                 match program.get_output().unwrap() {
@@ -40,6 +43,7 @@ fn run_text_adventure(input: &String) -> Result<()> {
             },
         }
     }
+    stats.merge(&program.stats());
     Ok(())
 }
 
@@ -52,5 +56,8 @@ fn main() -> Result<()> {
     reader.read_to_string(&mut contents)?;
     
     // Weight required: food ration, space law space brochure, mutex, mouse, asterisk
-    run_text_adventure(&contents)
+    let mut stats = Stats::new();
+    run_text_adventure(&contents, &mut stats)?;
+    if opt.stats { eprintln!("{}", stats); }
+    Ok(())
 }
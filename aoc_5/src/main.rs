@@ -10,6 +10,8 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 struct Cli {
     #[structopt(short = "f", parse(from_os_str))]
     file: PathBuf,
+    #[structopt(long = "stats")]
+    stats: bool,
 }
 
 fn main() -> Result<()> {
@@ -22,5 +24,7 @@ fn main() -> Result<()> {
 
     let mut program = IntcodeProgram::from_raw_input(&contents)?;
     program.execute()?;
-    Ok(println!("Output: {}", program.get_all_output().last().unwrap()))
+    println!("Output: {}", program.get_all_output().last().unwrap());
+    if opt.stats { eprintln!("{}", program.stats()); }
+    Ok(())
 }
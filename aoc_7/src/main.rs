@@ -1,4 +1,3 @@
-use std::cmp;
 use std::fs::File;
 use std::io::{prelude::*, BufReader};
 use std::path::PathBuf;
@@ -8,6 +7,8 @@ use std::sync::{Arc, Mutex};
 use std::thread;
 use intcode::program::IntcodeProgram;
 use intcode::io;
+use intcode::chrome::ChromeTrace;
+use intcode::stats::Stats;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -21,25 +22,64 @@ struct Cli {
     upper_phase_setting: usize,
     #[structopt(short = "g")]
     use_feedback: bool,
+    #[structopt(long = "stats")]
+    stats: bool,
+    /// Write a Chrome trace of the best phase settings' run to this file
+    #[structopt(long = "trace", parse(from_os_str))]
+    trace: Option<PathBuf>,
 }
 
-fn run_amplifier_chain(program: &String, phase_settings: Vec<i64>, use_feedback: bool) -> Result<i64> {
+fn run_amplifier_chain(program: &str, phase_settings: Vec<i64>, use_feedback: bool, stats: &mut Stats,
+    trace: Option<&Arc<ChromeTrace>>) -> Result<i64> {
     let mut amplifiers: Vec<IntcodeProgram> = phase_settings.iter()
         .map(|_| IntcodeProgram::from_raw_input(program)).collect::<Result<Vec<IntcodeProgram>>>()?;
 
     let num_amplifiers = amplifiers.len();
         
-    // Connect non-boundary programs with channels
+    // A traced run also records the first amplifier's inputs and the last one's outputs
+    if let Some(trace) = trace {
+        for i in 0..num_amplifiers {
+            trace.add_machine(&format!("amplifier{}", i));
+        }
+        amplifiers[0].replace_input(trace.wrap_input(0, io::DefaultInputDevice::new()));
+        amplifiers[num_amplifiers - 1].replace_output(trace.wrap_output(num_amplifiers - 1, None, io::DefaultOutputDevice::new()));
+    }
+
+    // Connect non-boundary programs with channels. An amplifier whose neighbour has
+    // crashed reports an error instead of waiting or writing into the void.
     for i in 0..(num_amplifiers - 1) {
-        let (tx, rx): (Sender<i64>, Receiver<i64>) = mpsc::channel();
-        amplifiers[i].replace_output(io::ChannelOutputDevice::new(tx));
-        amplifiers[i + 1].replace_input(io::ChannelInputDevice::new(rx));
+        match trace {
+            Some(trace) => {
+                let (output, input) = trace.connect(i, i + 1, None, io::ClosedPolicy::Error);
+                amplifiers[i].replace_output(output);
+                amplifiers[i + 1].replace_input(input);
+            },
+            None => {
+                let (output, input) = io::channel_pair(None, io::ClosedPolicy::Error);
+                amplifiers[i].replace_output(output);
+                amplifiers[i + 1].replace_input(input);
+            },
+        }
     }
 
+    // Feedback goes through this thread so the final signal isn't lost once the first amplifier exits
+    let mut feedback = None;
     if use_feedback {
-        let (tx, rx): (Sender<i64>, Receiver<i64>) = mpsc::channel();
-        amplifiers[num_amplifiers - 1].replace_output(io::ChannelOutputDevice::new(tx));
-        amplifiers[0].replace_input(io::ChannelInputDevice::new(rx));
+        let (from_last_tx, from_last_rx): (Sender<i64>, Receiver<i64>) = mpsc::channel();
+        let (to_first_tx, to_first_rx): (Sender<i64>, Receiver<i64>) = mpsc::channel();
+        let output = io::ChannelOutputDevice::with_policy(from_last_tx, io::ClosedPolicy::Error);
+        let input = io::ChannelInputDevice::new(to_first_rx);
+        match trace {
+            Some(trace) => {
+                amplifiers[num_amplifiers - 1].replace_output(trace.wrap_output(num_amplifiers - 1, Some(0), output));
+                amplifiers[0].replace_input(trace.wrap_input(0, input));
+            },
+            None => {
+                amplifiers[num_amplifiers - 1].replace_output(output);
+                amplifiers[0].replace_input(input);
+            },
+        }
+        feedback = Some((from_last_rx, to_first_tx));
     }
 
     // Give each amplifier its phase setting
@@ -56,18 +96,34 @@ fn run_amplifier_chain(program: &String, phase_settings: Vec<i64>, use_feedback:
     let result: Arc<Mutex<Option<i64>>> = Arc::new(Mutex::new(None));
     for mut amplifier in amplifiers {
         let res = result.clone();
-        threads.push(thread::Builder::new().name(format!("amplifier{}", idx)).spawn(move || {
-            amplifier.execute().unwrap();
-            if idx == num_amplifiers - 1 {
-                *res.lock().unwrap() = Some(amplifier.get_output().unwrap());
+        let trace = trace.cloned();
+        threads.push(thread::Builder::new().name(format!("amplifier{}", idx)).spawn(move || -> std::result::Result<Stats, String> {
+            if let Some(trace) = &trace { trace.resume(idx); }
+            let executed = amplifier.execute();
+            if let Some(trace) = &trace { trace.pause(idx); }
+            executed.map_err(|e| format!("amplifier{}: {}", idx, e))?;
+            if idx == num_amplifiers - 1 && !use_feedback {
+                *res.lock().unwrap() = amplifier.get_output();
             }
+            Ok(amplifier.stats())
         })?);
         idx += 1;
     }
 
+    // Relay signals until the last amplifier exits. The first may already have exited, so
+    // the final signal has nowhere to go.
+    if let Some((from_last, to_first)) = feedback {
+        for signal in from_last {
+            *result.lock().unwrap() = Some(signal);
+            to_first.send(signal).ok();
+        }
+    }
+
     for thread in threads {
-        if let Err(_) = thread.join() {
-            return Err(From::from("Amplifier thread panicked"));
+        match thread.join() {
+            Err(_) => return Err(From::from("Amplifier thread panicked")),
+            Ok(Err(e)) => return Err(From::from(e)),
+            Ok(Ok(amplifier_stats)) => stats.merge(&amplifier_stats),
         }
     }
 
@@ -87,16 +143,30 @@ fn main() -> Result<()> {
     let num_settings = upper - lower + 1;
     let num_inputs_to_try: usize = (1..=num_settings).fold(1, |acc, x| acc * x);
     let mut max_power_found: i64 = std::i64::MIN;
+    let mut best_settings = vec![];
+    let mut stats = Stats::new();
 
     for input in (0..num_inputs_to_try).map(|mut idx| {
         // Calculate next permutation of phase settings
         let mut options: Vec<usize> = (lower..=upper).collect();
         std::iter::repeat_with(|| { let tmp = idx % options.len(); idx /= options.len(); options.remove(tmp) as i64 })
-            .take(5).collect()
+            .take(5).collect::<Vec<i64>>()
     }) {
-        max_power_found = cmp::max(max_power_found, run_amplifier_chain(&contents, input, opt.use_feedback)?);
+        let power = run_amplifier_chain(&contents, input.clone(), opt.use_feedback, &mut stats, None)?;
+        if power > max_power_found {
+            max_power_found = power;
+            best_settings = input;
+        }
+    }
+
+    // Run the best settings again, tracing the amplifiers as they pass the signal along
+    if let Some(path) = opt.trace {
+        let trace = ChromeTrace::new();
+        run_amplifier_chain(&contents, best_settings, opt.use_feedback, &mut Stats::new(), Some(&trace))?;
+        trace.write(File::create(path)?)?;
     }
 
     println!("Max possible power: {}", max_power_found);
+    if opt.stats { eprintln!("{}", stats); }
     Ok(())
 }
@@ -10,6 +10,8 @@ type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 struct Cli {
     #[structopt(short = "f", parse(from_os_str))]
     file: PathBuf,
+    #[structopt(long = "stats")]
+    stats: bool,
 }
 
 fn main() -> Result<()> {
@@ -22,6 +24,8 @@ fn main() -> Result<()> {
 
     let mut program = IntcodeProgram::from_raw_input(&contents)?;
     program.execute()?;
-    Ok(println!("{:?}", program.get_all_output()))
+    println!("{:?}", program.get_all_output());
+    if opt.stats { eprintln!("{}", program.stats()); }
+    Ok(())
 }
 
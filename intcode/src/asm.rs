@@ -0,0 +1,471 @@
+// Macro assembler and linker for Intcode.
+//
+//     .global main                ; exported to other modules when linking
+//     .const NEWLINE 10
+//
+//     main:
+//         arb stack               ; rb is the stack pointer, growing upwards
+//         in [count]
+//     loop:
+//         push [count]
+//         call print
+//         pop [count]
+//         add [count], -1, [count]
+//         jnz [count], loop
+//         hlt
+//     print:
+//         out [rb - 2]
+//         out NEWLINE
+//         ret
+//     count: .data 0
+//     stack:
+//
+// Mnemonics match the disassembler (add, mul, in, out, jnz, jez, lt, eq,
+// arb, hlt) with operands in encoding order. An operand is an immediate
+// expression (`5`, `loop`, `table + 2`), a position (`[count]`) or relative
+// to the relative base (`[rb + 3]`, `[rb - 1]`, `[rb]`). Expressions sum
+// integers, labels and constants.
+//
+// Directives are `.data <expr>, ...`, `.zero <n>`, `.const <name> <expr>`,
+// `.global <name>` and `.macro <name> <params>` ... `.endm`. Inside a macro
+// body, parameters are substituted by name and `\@` is replaced with a number
+// unique to each expansion, for making local labels. The builtin macros
+// `push x`, `pop x`, `call target`, `ret` and `jmp target` implement a stack
+// on the relative base: rb points at the next free slot, `call` pushes the
+// return address and `ret` pops it and jumps there.
+//
+// Each source module is assembled to an Object whose labels are relative to
+// the module's start. Linking lays the objects out one after another,
+// relocating labels and resolving references to other modules' globals.
+
+use std::collections::{BTreeMap, HashMap};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// A sum of signed terms, evaluated once every symbol has an address
+#[derive(Clone, Debug)]
+struct Expr {
+    terms: Vec<(i64, Term)>,
+}
+
+#[derive(Clone, Debug)]
+enum Term {
+    Num(i64),
+    Symbol(String),
+}
+
+#[derive(Clone, Debug)]
+struct Word {
+    expr: Expr,
+    line: usize,
+}
+
+#[derive(Clone, Debug)]
+enum Symbol {
+    Label(usize),
+    Const(Expr, usize),
+}
+
+// A single assembled module, with addresses relative to its start
+pub struct Object {
+    name: String,
+    words: Vec<Word>,
+    symbols: HashMap<String, Symbol>,
+    globals: Vec<(String, usize)>,
+    instructions: Vec<(usize, usize)>,
+}
+
+// A linked image, along with the source line each instruction was assembled from
+pub struct Linked {
+    pub memory: Vec<i64>,
+    pub source_map: BTreeMap<usize, (String, usize)>,
+    // Address of every global label, for a host calling into the image
+    pub symbols: HashMap<String, usize>,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<String>,
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    chars.next().is_some_and(|c| c.is_alphabetic() || c == '_' || c == '.')
+        && chars.all(|c| c.is_alphanumeric() || c == '_' || c == '.')
+}
+
+fn parse_expr(raw: &str) -> std::result::Result<Expr, String> {
+    let mut terms = vec![];
+    let mut sign = 1;
+    let mut rest = raw.trim();
+    if rest.is_empty() { return Err("expected expression".to_owned()); }
+
+    let mut expect_term = true;
+    while !rest.is_empty() {
+        let c = rest.chars().next().unwrap();
+        if c == '+' || c == '-' {
+            if c == '-' { sign = -sign; }
+            expect_term = true;
+            rest = rest[1..].trim_start();
+            continue;
+        }
+        if !expect_term { return Err(format!("expected '+' or '-' in expression: {}", raw)); }
+
+        let len = rest.find(|c: char| c == '+' || c == '-' || c.is_whitespace()).unwrap_or(rest.len());
+        let token = &rest[..len];
+        let term = if let Ok(n) = token.parse::<i64>() {
+            Term::Num(n)
+        } else if is_identifier(token) {
+            Term::Symbol(token.to_owned())
+        } else {
+            return Err(format!("invalid term '{}' in expression: {}", token, raw));
+        };
+        terms.push((sign, term));
+        sign = 1;
+        expect_term = false;
+        rest = rest[len..].trim_start();
+    }
+
+    if expect_term { return Err(format!("expression ends with an operator: {}", raw)); }
+    Ok(Expr{ terms })
+}
+
+// Returns the parameter mode and the word for an operand
+fn parse_operand(raw: &str) -> std::result::Result<(i64, Expr), String> {
+    let raw = raw.trim();
+    if raw.starts_with('[') && raw.ends_with(']') {
+        let inner = raw[1..raw.len() - 1].trim();
+        if inner == "rb" {
+            Ok((2, Expr{ terms: vec![(1, Term::Num(0))] }))
+        } else if inner.starts_with("rb") && inner[2..].trim_start().starts_with(['+', '-']) {
+            Ok((2, parse_expr(&inner[2..])?))
+        } else {
+            Ok((0, parse_expr(inner)?))
+        }
+    } else {
+        Ok((1, parse_expr(raw)?))
+    }
+}
+
+fn opcode(mnemonic: &str) -> Option<(i64, usize)> {
+    match mnemonic {
+        "add" => Some((1, 3)),
+        "mul" => Some((2, 3)),
+        "in" => Some((3, 1)),
+        "out" => Some((4, 1)),
+        "jnz" => Some((5, 2)),
+        "jez" => Some((6, 2)),
+        "lt" => Some((7, 3)),
+        "eq" => Some((8, 3)),
+        "arb" => Some((9, 1)),
+        "hlt" => Some((99, 0)),
+        _ => None,
+    }
+}
+
+// Index of the operand written by the instruction, which can't be immediate
+fn write_operand(opcode: i64) -> Option<usize> {
+    match opcode {
+        1 | 2 | 7 | 8 => Some(2),
+        3 => Some(0),
+        _ => None,
+    }
+}
+
+fn split_operands(raw: &str) -> Vec<String> {
+    if raw.trim().is_empty() { return vec![]; }
+    raw.split(',').map(|s| s.trim().to_owned()).collect()
+}
+
+fn builtin_arity(name: &str) -> Option<usize> {
+    match name {
+        "push" | "pop" | "call" | "jmp" => Some(1),
+        "ret" => Some(0),
+        _ => None,
+    }
+}
+
+fn builtin_macro(name: &str, args: &[String], unique: usize) -> Option<Vec<String>> {
+    match (name, args.len()) {
+        ("push", 1) => Some(vec![format!("add {}, 0, [rb]", args[0]), "arb 1".to_owned()]),
+        ("pop", 1) => Some(vec!["arb -1".to_owned(), format!("add [rb], 0, {}", args[0])]),
+        ("call", 1) => Some(vec![
+            format!("add __ret_{}, 0, [rb]", unique),
+            "arb 1".to_owned(),
+            format!("jnz 1, {}", args[0]),
+            format!("__ret_{}:", unique),
+        ]),
+        ("ret", 0) => Some(vec!["arb -1".to_owned(), "jez 0, [rb]".to_owned()]),
+        ("jmp", 1) => Some(vec![format!("jnz 1, {}", args[0])]),
+        _ => None,
+    }
+}
+
+fn substitute(line: &str, params: &[String], args: &[String], unique: usize) -> String {
+    let line = line.replace("\\@", &unique.to_string());
+    let mut result = String::new();
+    let mut word = String::new();
+    for c in line.chars().chain(std::iter::once('\n')) {
+        if c.is_alphanumeric() || c == '_' || c == '.' {
+            word.push(c);
+            continue;
+        }
+        match params.iter().position(|p| *p == word) {
+            Some(idx) => result.push_str(&args[idx]),
+            None => result.push_str(&word),
+        }
+        word.clear();
+        if c != '\n' { result.push(c); }
+    }
+    result
+}
+
+struct Assembler {
+    object: Object,
+    macros: HashMap<String, Macro>,
+    expansions: usize,
+}
+
+impl Assembler {
+    fn error<T>(&self, line: usize, message: &str) -> Result<T> {
+        Err(From::from(format!("{}:{}: {}", self.object.name, line, message)))
+    }
+
+    fn define(&mut self, name: &str, symbol: Symbol, line: usize) -> Result<()> {
+        if !is_identifier(name) || name == "rb" {
+            return self.error(line, &format!("invalid symbol name '{}'", name));
+        }
+        if self.object.symbols.insert(name.to_owned(), symbol).is_some() {
+            return self.error(line, &format!("duplicate symbol '{}'", name));
+        }
+        Ok(())
+    }
+
+    fn emit(&mut self, expr: Expr, line: usize) {
+        self.object.words.push(Word{ expr, line });
+    }
+
+    fn assemble_line(&mut self, raw: &str, line: usize, depth: usize) -> Result<()> {
+        let mut text = raw.find(';').map_or(raw, |comment| &raw[..comment]).trim();
+
+        // Any number of labels may precede the statement
+        while let Some(idx) = text.find(':') {
+            let label = text[..idx].trim();
+            if !is_identifier(label) { break; }
+            self.define(label, Symbol::Label(self.object.words.len()), line)?;
+            text = text[idx + 1..].trim();
+        }
+        if text.is_empty() { return Ok(()); }
+
+        let (head, rest) = match text.find(char::is_whitespace) {
+            Some(idx) => (&text[..idx], text[idx..].trim()),
+            None => (text, ""),
+        };
+
+        match head {
+            ".data" => {
+                for item in split_operands(rest) {
+                    let expr = parse_expr(&item).or_else(|e| self.error(line, &e))?;
+                    self.emit(expr, line);
+                }
+            },
+            ".zero" => {
+                let count = rest.parse::<usize>().or_else(|_| self.error(line, "expected count for .zero"))?;
+                for _ in 0..count { self.emit(Expr{ terms: vec![] }, line); }
+            },
+            ".const" => {
+                let (name, value) = match rest.find(char::is_whitespace) {
+                    Some(idx) => (&rest[..idx], &rest[idx..]),
+                    None => return self.error(line, "expected .const <name> <expr>"),
+                };
+                let expr = parse_expr(value).or_else(|e| self.error(line, &e))?;
+                self.define(name, Symbol::Const(expr, line), line)?;
+            },
+            ".global" => {
+                for name in split_operands(rest) {
+                    if !is_identifier(&name) { return self.error(line, &format!("invalid symbol name '{}'", name)); }
+                    self.object.globals.push((name, line));
+                }
+            },
+            _ => {
+                let args = split_operands(rest);
+                if let Some((opcode, arity)) = opcode(head) {
+                    self.assemble_instruction(head, opcode, arity, &args, line)?;
+                } else if let Some(body) = self.expand_macro(head, &args, line)? {
+                    if depth > 64 { return self.error(line, "macro expansion is too deeply nested"); }
+                    for expanded in body {
+                        self.assemble_line(&expanded, line, depth + 1)?;
+                    }
+                } else {
+                    return self.error(line, &format!("unknown instruction or macro '{}'", head));
+                }
+            },
+        }
+        Ok(())
+    }
+
+    fn assemble_instruction(&mut self, mnemonic: &str, opcode: i64, arity: usize, args: &[String], line: usize) -> Result<()> {
+        if args.len() != arity {
+            return self.error(line, &format!("'{}' takes {} operand(s) but {} were given", mnemonic, arity, args.len()));
+        }
+        let operands = args.iter().map(|arg| parse_operand(arg))
+            .collect::<std::result::Result<Vec<(i64, Expr)>, String>>()
+            .or_else(|e| self.error(line, &e))?;
+        if let Some(idx) = write_operand(opcode) {
+            if operands[idx].0 == 1 {
+                return self.error(line, &format!("'{}' can't write to an immediate operand", mnemonic));
+            }
+        }
+
+        let modes = operands.iter().enumerate().fold(0, |acc, (idx, (mode, _))| acc + mode * 10i64.pow(idx as u32));
+        self.object.instructions.push((self.object.words.len(), line));
+        self.emit(Expr{ terms: vec![(1, Term::Num(modes * 100 + opcode))] }, line);
+        for (_, expr) in operands { self.emit(expr, line); }
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str, args: &[String], line: usize) -> Result<Option<Vec<String>>> {
+        self.expansions += 1;
+        let arity = match self.macros.get(name) {
+            Some(m) => Some(m.params.len()),
+            None => builtin_arity(name),
+        };
+        match arity {
+            Some(arity) if arity != args.len() => return self.error(line,
+                &format!("macro '{}' takes {} argument(s) but {} were given", name, arity, args.len())),
+            Some(_) => (),
+            None => return Ok(None),
+        }
+        if let Some(m) = self.macros.get(name) {
+            return Ok(Some(m.body.iter().map(|l| substitute(l, &m.params, args, self.expansions)).collect()));
+        }
+        Ok(builtin_macro(name, args, self.expansions))
+    }
+}
+
+// Assembles one source module into a relocatable object. Errors are reported as `<name>:<line>: <message>`.
+pub fn assemble_module(name: &str, source: &str) -> Result<Object> {
+    let mut assembler = Assembler{
+        object: Object{
+            name: name.to_owned(),
+            words: vec![],
+            symbols: HashMap::new(),
+            globals: vec![],
+            instructions: vec![],
+        },
+        macros: HashMap::new(),
+        expansions: 0,
+    };
+
+    let mut lines = source.lines().enumerate().map(|(idx, l)| (idx + 1, l));
+    while let Some((line, text)) = lines.next() {
+        let trimmed = text.find(';').map_or(text, |comment| &text[..comment]).trim();
+        if let Some(definition) = trimmed.strip_prefix(".macro") {
+            let mut parts = definition.trim().splitn(2, char::is_whitespace);
+            let macro_name = parts.next().unwrap_or("").to_owned();
+            if !is_identifier(&macro_name) { return assembler.error(line, "expected macro name"); }
+            let params = split_operands(parts.next().unwrap_or(""));
+            let mut body = vec![];
+            loop {
+                match lines.next() {
+                    Some((_, l)) if l.trim() == ".endm" => break,
+                    Some((_, l)) => body.push(l.to_owned()),
+                    None => return assembler.error(line, &format!("macro '{}' is missing .endm", macro_name)),
+                }
+            }
+            assembler.macros.insert(macro_name, Macro{ params, body });
+        } else {
+            assembler.assemble_line(text, line, 0)?;
+        }
+    }
+
+    for (global, line) in &assembler.object.globals {
+        if !assembler.object.symbols.contains_key(global) {
+            return assembler.error(*line, &format!("global '{}' is never defined", global));
+        }
+    }
+    Ok(assembler.object)
+}
+
+struct LinkState<'a> {
+    objects: &'a [Object],
+    bases: Vec<usize>,
+    globals: HashMap<&'a str, usize>,
+}
+
+impl<'a> LinkState<'a> {
+    fn resolve(&self, module: usize, name: &str, line: usize, depth: usize) -> Result<i64> {
+        let object = &self.objects[module];
+        let (module, symbol) = match object.symbols.get(name) {
+            Some(symbol) => (module, symbol),
+            None => match self.globals.get(name) {
+                Some(&other) => (other, &self.objects[other].symbols[name]),
+                None => return Err(From::from(format!("{}:{}: undefined symbol '{}'", object.name, line, name))),
+            },
+        };
+        match symbol {
+            Symbol::Label(offset) => Ok((self.bases[module] + offset) as i64),
+            Symbol::Const(expr, line) => {
+                if depth > 64 {
+                    return Err(From::from(format!("{}:{}: constant '{}' is defined in terms of itself",
+                        self.objects[module].name, line, name)));
+                }
+                self.evaluate(module, expr, *line, depth + 1)
+            },
+        }
+    }
+
+    fn evaluate(&self, module: usize, expr: &Expr, line: usize, depth: usize) -> Result<i64> {
+        expr.terms.iter().try_fold(0i64, |acc, (sign, term)| {
+            let value = match term {
+                Term::Num(n) => *n,
+                Term::Symbol(name) => self.resolve(module, name, line, depth)?,
+            };
+            value.checked_mul(*sign).and_then(|value| acc.checked_add(value)).ok_or_else(|| {
+                From::from(format!("{}:{}: expression overflows a 64-bit word", self.objects[module].name, line))
+            })
+        })
+    }
+}
+
+// Lays out the objects in order and resolves every symbol to an absolute address
+pub fn link(objects: &[Object]) -> Result<Linked> {
+    let mut bases = vec![];
+    let mut next_base = 0;
+    for object in objects {
+        bases.push(next_base);
+        next_base += object.words.len();
+    }
+
+    let mut globals: HashMap<&str, usize> = HashMap::new();
+    for (module, object) in objects.iter().enumerate() {
+        for (global, line) in &object.globals {
+            if let Some(other) = globals.insert(global, module) {
+                return Err(From::from(format!("{}:{}: global '{}' is already defined in {}",
+                    object.name, line, global, objects[other].name)));
+            }
+        }
+    }
+
+    let state = LinkState{ objects, bases, globals };
+    let mut linked = Linked{ memory: vec![], source_map: BTreeMap::new(), symbols: HashMap::new() };
+    for (module, object) in objects.iter().enumerate() {
+        for word in &object.words {
+            linked.memory.push(state.evaluate(module, &word.expr, word.line, 0)?);
+        }
+        for (offset, line) in &object.instructions {
+            linked.source_map.insert(state.bases[module] + offset, (object.name.clone(), *line));
+        }
+    }
+    for (&name, &module) in &state.globals {
+        if let Symbol::Label(offset) = objects[module].symbols[name] {
+            linked.symbols.insert(name.to_owned(), state.bases[module] + offset);
+        }
+    }
+    Ok(linked)
+}
+
+// Assembles and links a single self-contained source module
+pub fn assemble(source: &str) -> Result<Vec<i64>> {
+    Ok(link(&[assemble_module("<source>", source)?])?.memory)
+}
@@ -0,0 +1,292 @@
+use super::program::{Event, IntcodeInstruction, IntcodeProgram, Parameter, ParameterMode};
+use std::collections::{BTreeMap, BTreeSet};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Clone, Debug)]
+pub struct Condition {
+    pub predicate: Parameter,
+    // True for jnz, false for jez
+    pub nonzero: bool,
+}
+
+#[derive(Clone, Debug)]
+pub enum Exit {
+    // The next instruction starts another block
+    Fallthrough(usize),
+    Jump(usize),
+    Branch { condition: Condition, target: usize, fallthrough: usize },
+    // A jump preceded by a store of the address following it
+    Call { target: usize, return_to: usize },
+    // An unconditional jump through a relative-mode cell
+    Return,
+    Indirect { condition: Option<Condition>, target: Parameter, fallthrough: Option<usize> },
+    Halt,
+    // The word at this address isn't a valid instruction
+    Invalid(usize),
+}
+
+#[derive(Clone, Debug)]
+pub struct BasicBlock {
+    pub start: usize,
+    pub instructions: Vec<(usize, IntcodeInstruction)>,
+    pub exit: Exit,
+}
+
+impl Exit {
+    pub fn successors(&self) -> Vec<usize> {
+        match self {
+            Exit::Fallthrough(next) | Exit::Jump(next) => vec![*next],
+            Exit::Branch{ target, fallthrough, .. } => vec![*target, *fallthrough],
+            Exit::Call{ target, return_to } => vec![*target, *return_to],
+            Exit::Indirect{ fallthrough, .. } => fallthrough.iter().cloned().collect(),
+            Exit::Return | Exit::Halt | Exit::Invalid(_) => vec![],
+        }
+    }
+}
+
+impl BasicBlock {
+    pub fn successors(&self) -> Vec<usize> {
+        self.exit.successors()
+    }
+}
+
+pub struct ControlFlowGraph {
+    pub blocks: BTreeMap<usize, BasicBlock>,
+    // The entry point and every call target
+    pub functions: BTreeSet<usize>,
+}
+
+fn is_immediate(p: &Parameter) -> bool {
+    p.mode == ParameterMode::Immediate
+}
+
+// Returns whether a jump is always, never or only sometimes taken
+fn jump_condition(predicate: &Parameter, nonzero: bool) -> Option<bool> {
+    if is_immediate(predicate) { Some((predicate.param != 0) == nonzero) } else { None }
+}
+
+// Whether the instruction stores the immediate value `value`, as a call does with its return address
+fn stores_immediate(instruction: &IntcodeInstruction, value: i64) -> bool {
+    match instruction {
+        IntcodeInstruction::Add{o1, o2, ..} => {
+            (is_immediate(o1) && o1.param == value && is_immediate(o2) && o2.param == 0) ||
+            (is_immediate(o2) && o2.param == value && is_immediate(o1) && o1.param == 0)
+        },
+        IntcodeInstruction::Mul{o1, o2, ..} => {
+            (is_immediate(o1) && o1.param == value && is_immediate(o2) && o2.param == 1) ||
+            (is_immediate(o2) && o2.param == value && is_immediate(o1) && o1.param == 1)
+        },
+        _ => false,
+    }
+}
+
+// Classifies the instruction at `address`, returning None if execution simply continues past it
+fn classify(address: usize, instruction: &IntcodeInstruction, preceding: &[(usize, IntcodeInstruction)]) -> Option<Exit> {
+    let next = address + instruction.length();
+    let (predicate, target, nonzero) = match instruction {
+        IntcodeInstruction::JumpIfTrue{predicate, target} => (predicate, target, true),
+        IntcodeInstruction::JumpIfFalse{predicate, target} => (predicate, target, false),
+        IntcodeInstruction::Exit => return Some(Exit::Halt),
+        _ => return None,
+    };
+
+    let condition = Condition{ predicate: predicate.clone(), nonzero };
+    match (jump_condition(predicate, nonzero), is_immediate(target)) {
+        (Some(false), _) => None,
+        (Some(true), true) => {
+            let target = target.param as usize;
+            if preceding.iter().any(|(_, i)| stores_immediate(i, next as i64)) {
+                Some(Exit::Call{ target, return_to: next })
+            } else {
+                Some(Exit::Jump(target))
+            }
+        },
+        (None, true) => Some(Exit::Branch{ condition, target: target.param as usize, fallthrough: next }),
+        (Some(true), false) if target.mode == ParameterMode::Relative => Some(Exit::Return),
+        (Some(true), false) => Some(Exit::Indirect{ condition: None, target: target.clone(), fallthrough: None }),
+        (None, false) => Some(Exit::Indirect{ condition: Some(condition), target: target.clone(), fallthrough: Some(next) }),
+    }
+}
+
+// Partitions the code reachable from address 0 (and any extra entry points) into basic blocks.
+// Only instructions reachable through direct jumps are decoded, so data is left alone.
+pub fn build(program: &IntcodeProgram, extra_entries: &[usize]) -> ControlFlowGraph {
+    let mut instructions: BTreeMap<usize, IntcodeInstruction> = BTreeMap::new();
+    let mut exits: BTreeMap<usize, Exit> = BTreeMap::new();
+    let mut leaders: BTreeSet<usize> = BTreeSet::new();
+    let mut functions: BTreeSet<usize> = BTreeSet::new();
+    functions.insert(0);
+
+    let mut worklist: Vec<usize> = vec![0];
+    worklist.extend(extra_entries);
+    while let Some(entry) = worklist.pop() {
+        // An entry into code that's already decoded only splits the block it lands in
+        leaders.insert(entry);
+        if instructions.contains_key(&entry) || exits.contains_key(&entry) { continue }
+
+        // Decode linearly until the run ends in a terminator or joins already-decoded code
+        let mut address = entry;
+        let mut run: Vec<(usize, IntcodeInstruction)> = vec![];
+        loop {
+            if address != entry && instructions.contains_key(&address) {
+                leaders.insert(address);
+                break;
+            }
+            let instruction = match program.decode(address) {
+                Ok(instruction) => instruction,
+                Err(_) => {
+                    exits.insert(address, Exit::Invalid(address));
+                    break;
+                },
+            };
+            instructions.insert(address, instruction.clone());
+            let exit = classify(address, &instruction, &run[run.len().saturating_sub(4)..]);
+            run.push((address, instruction.clone()));
+            match exit {
+                None => address += instruction.length(),
+                Some(exit) => {
+                    if let Exit::Call{ target, .. } = &exit { functions.insert(*target); }
+                    worklist.extend(exit.successors());
+                    exits.insert(address, exit);
+                    break;
+                },
+            }
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+    for &start in &leaders {
+        let mut block = BasicBlock{ start, instructions: vec![], exit: Exit::Invalid(start) };
+        let mut address = start;
+        loop {
+            if address != start && leaders.contains(&address) {
+                block.exit = Exit::Fallthrough(address);
+                break;
+            }
+            let instruction = match instructions.get(&address) {
+                Some(instruction) => instruction.clone(),
+                None => break, // Invalid(address)
+            };
+            let next = address + instruction.length();
+            block.instructions.push((address, instruction));
+            if let Some(exit) = exits.get(&address) {
+                block.exit = exit.clone();
+                break;
+            }
+            block.exit = Exit::Invalid(next);
+            address = next;
+        }
+        blocks.insert(start, block);
+    }
+
+    ControlFlowGraph{ blocks, functions }
+}
+
+fn jump_target(instruction: &IntcodeInstruction) -> Option<&Parameter> {
+    match instruction {
+        IntcodeInstruction::JumpIfTrue{target, ..} | IntcodeInstruction::JumpIfFalse{target, ..} => Some(target),
+        _ => None,
+    }
+}
+
+// The value a parameter reads in the program's current state, or None if it's out of memory
+fn parameter_value(program: &IntcodeProgram, p: &Parameter) -> Option<i64> {
+    let address = match p.mode {
+        ParameterMode::Immediate => return Some(p.param),
+        ParameterMode::Position => p.param,
+        ParameterMode::Relative => program.relative_base().checked_add(p.param)?,
+    };
+    Some(address).filter(|&address| address >= 0).map(|address| program.load_position(address as usize))
+}
+
+// Whether the instruction is a jump through a non-immediate target that's about to be taken
+fn takes_indirect_jump(program: &IntcodeProgram, instruction: &IntcodeInstruction) -> bool {
+    let (predicate, target, nonzero) = match instruction {
+        IntcodeInstruction::JumpIfTrue{predicate, target} => (predicate, target, true),
+        IntcodeInstruction::JumpIfFalse{predicate, target} => (predicate, target, false),
+        _ => return false,
+    };
+    !is_immediate(target) && parameter_value(program, predicate).is_some_and(|value| (value != 0) == nonzero)
+}
+
+// Runs the program until it exits, needs input it hasn't been given or exhausts the
+// budget, recording where each jump through a non-immediate target actually went
+pub fn observe_indirect_jumps(program: &mut IntcodeProgram, budget: usize) -> Result<BTreeMap<usize, BTreeSet<usize>>> {
+    let mut observed: BTreeMap<usize, BTreeSet<usize>> = BTreeMap::new();
+    for _ in 0..budget {
+        let address = program.ip();
+        // Decided before the step, so a jump to the next instruction still counts as taken
+        let taken = takes_indirect_jump(program, &program.decode(address)?);
+        match program.step()? {
+            Some(Event::InputRequired) | Some(Event::Exited) | Some(Event::PeerClosed) => break,
+            _ => (),
+        }
+        if taken {
+            observed.entry(address).or_default().insert(program.ip());
+        }
+    }
+    Ok(observed)
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+impl ControlFlowGraph {
+    // Builds the graph with the observed targets of indirect jumps as extra entry points
+    pub fn with_observed(program: &IntcodeProgram, observed: &BTreeMap<usize, BTreeSet<usize>>) -> ControlFlowGraph {
+        let targets: Vec<usize> = observed.values().flatten().cloned().collect();
+        build(program, &targets)
+    }
+
+    // Renders the graph in Graphviz DOT format. Each block is labelled with its disassembly,
+    // and observed indirect jumps are drawn as dashed edges.
+    pub fn to_dot(&self, observed: &BTreeMap<usize, BTreeSet<usize>>) -> String {
+        let mut out = String::new();
+        out.push_str("digraph intcode {\n");
+        out.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+        for block in self.blocks.values() {
+            let mut label: String = block.instructions.iter()
+                .map(|(address, instruction)| format!("{:>6} : {}\\l", address, escape(&instruction.to_string())))
+                .collect();
+            let indirect = block.instructions.last()
+                .and_then(|(_, instruction)| jump_target(instruction))
+                .filter(|target| !is_immediate(target));
+            if let Some(target) = indirect {
+                label.push_str(&format!("indirect jump via {}\\l", escape(&target.to_string())));
+            }
+            if let Exit::Invalid(address) = block.exit {
+                label.push_str(&format!("invalid instruction at {}\\l", address));
+            }
+            let style = if indirect.is_some() { ", color=red" } else if self.functions.contains(&block.start) { ", peripheries=2" } else { "" };
+            out.push_str(&format!("    b{} [label=\"{}\"{}];\n", block.start, label, style));
+
+            let edges: Vec<(usize, &str)> = match &block.exit {
+                Exit::Fallthrough(next) => vec![(*next, "")],
+                Exit::Jump(target) => vec![(*target, "jump")],
+                Exit::Branch{ target, fallthrough, .. } => vec![(*target, "taken"), (*fallthrough, "fallthrough")],
+                Exit::Call{ target, return_to } => vec![(*target, "call"), (*return_to, "return to")],
+                Exit::Indirect{ fallthrough: Some(next), .. } => vec![(*next, "fallthrough")],
+                _ => vec![],
+            };
+            for (target, label) in edges {
+                if self.blocks.contains_key(&target) {
+                    out.push_str(&format!("    b{} -> b{} [label=\"{}\"];\n", block.start, target, label));
+                }
+            }
+
+            if let Some((address, _)) = block.instructions.last() {
+                for target in observed.get(address).into_iter().flatten() {
+                    if self.blocks.contains_key(target) {
+                        out.push_str(&format!("    b{} -> b{} [style=dashed, label=\"observed\"];\n", block.start, target));
+                    }
+                }
+            }
+        }
+
+        out.push_str("}\n");
+        out
+    }
+}
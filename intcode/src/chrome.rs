@@ -0,0 +1,248 @@
+// Records runs of several connected machines in the Chrome trace-event format, viewable
+// in chrome://tracing, Perfetto or any other trace viewer.
+//
+// Each machine gets its own track. While a machine is running its time is drawn as
+// "run" spans, split at every input and output, which appear as instant events. A value
+// sent from one machine to another is drawn as a flow arrow from the output to the input.
+//
+// Machines joined with connect, or whose devices are wrapped with wrap_input and
+// wrap_output, are recorded automatically: an input device pauses the machine's span
+// while it blocks, and arrows join each output to the next input its receiver takes
+// from the peer (values given locally with put are taken first, so they get no arrow).
+// This assumes each receiver has a single sending peer. A driver that moves values
+// between machines itself can call resume, pause, input, output, send and receive.
+
+use super::io::{self, ClosedPolicy, InputDevice, OutputDevice};
+use serde_json::{json, Value};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// A value in transit between two machines, drawn as an arrow once received
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Flow(u64);
+
+struct Track {
+    // When the current run span started, if the machine is running
+    running: Option<Instant>,
+    // Flows sent to this machine through wrapped devices, in the order they were sent
+    pending: VecDeque<Flow>,
+}
+
+struct TraceState {
+    tracks: Vec<Track>,
+    events: Vec<Value>,
+    next_flow: u64,
+}
+
+pub struct ChromeTrace {
+    state: Mutex<TraceState>,
+    start: Instant,
+}
+
+pub struct TracedInputDevice {
+    trace: Arc<ChromeTrace>,
+    machine: usize,
+    inner: Box<dyn InputDevice + Send>,
+    // Values given with put that haven't been taken yet
+    local: usize,
+}
+
+pub struct TracedOutputDevice {
+    trace: Arc<ChromeTrace>,
+    machine: usize,
+    to: Option<usize>,
+    inner: Box<dyn OutputDevice + Send>,
+}
+
+impl ChromeTrace {
+    pub fn new() -> Arc<ChromeTrace> {
+        Arc::new(ChromeTrace{
+            state: Mutex::new(TraceState{ tracks: vec![], events: vec![], next_flow: 0 }),
+            start: Instant::now(),
+        })
+    }
+
+    // Registers a machine, returning the id of its track
+    pub fn add_machine(&self, name: &str) -> usize {
+        let mut state = self.state.lock().unwrap();
+        let machine = state.tracks.len();
+        state.tracks.push(Track{ running: None, pending: VecDeque::new() });
+        state.events.push(json!({ "name": "thread_name", "ph": "M", "pid": 1, "tid": machine, "args": { "name": name } }));
+        machine
+    }
+
+    // Like io::channel_pair, for outputs of machine `from` feeding the inputs of machine `to`
+    pub fn connect(self: &Arc<Self>, from: usize, to: usize, capacity: Option<usize>, policy: ClosedPolicy)
+        -> (Box<TracedOutputDevice>, Box<TracedInputDevice>) {
+        let (output, input) = io::channel_pair(capacity, policy);
+        (self.wrap_output(from, Some(to), output), self.wrap_input(to, input))
+    }
+
+    // Records the inputs taken by a machine
+    pub fn wrap_input(self: &Arc<Self>, machine: usize, inner: Box<dyn InputDevice + Send>) -> Box<TracedInputDevice> {
+        Box::new(TracedInputDevice{ trace: self.clone(), machine, inner, local: 0 })
+    }
+
+    // Records the outputs of a machine, with arrows to the inputs of machine `to` if given
+    pub fn wrap_output(self: &Arc<Self>, machine: usize, to: Option<usize>, inner: Box<dyn OutputDevice + Send>)
+        -> Box<TracedOutputDevice> {
+        Box::new(TracedOutputDevice{ trace: self.clone(), machine, to, inner })
+    }
+
+    fn timestamp(&self, at: Instant) -> f64 {
+        at.duration_since(self.start).as_secs_f64() * 1e6
+    }
+
+    // Starts a run span, if the machine isn't already running
+    pub fn resume(&self, machine: usize) {
+        let mut state = self.state.lock().unwrap();
+        let track = &mut state.tracks[machine];
+        if track.running.is_none() { track.running = Some(Instant::now()); }
+    }
+
+    // Ends the current run span, if any
+    pub fn pause(&self, machine: usize) {
+        let now = Instant::now();
+        let mut state = self.state.lock().unwrap();
+        if let Some(started) = state.tracks[machine].running.take() {
+            let event = self.span(machine, started, now);
+            state.events.push(event);
+        }
+    }
+
+    fn span(&self, machine: usize, started: Instant, ended: Instant) -> Value {
+        json!({
+            "name": "run", "ph": "X", "pid": 1, "tid": machine,
+            "ts": self.timestamp(started), "dur": ended.duration_since(started).as_secs_f64() * 1e6,
+        })
+    }
+
+    // Records an instant event, splitting the machine's run span there
+    fn instant(&self, state: &mut TraceState, machine: usize, name: String, now: Instant) {
+        let track = &mut state.tracks[machine];
+        if let Some(started) = track.running {
+            track.running = Some(now);
+            state.events.push(self.span(machine, started, now));
+        }
+        state.events.push(json!({ "name": name, "ph": "i", "s": "t", "pid": 1, "tid": machine, "ts": self.timestamp(now) }));
+    }
+
+    pub fn input(&self, machine: usize, value: i64) {
+        let mut state = self.state.lock().unwrap();
+        self.instant(&mut state, machine, format!("in {}", value), Instant::now());
+    }
+
+    pub fn output(&self, machine: usize, value: i64) {
+        let mut state = self.state.lock().unwrap();
+        self.instant(&mut state, machine, format!("out {}", value), Instant::now());
+    }
+
+    // Starts an arrow at the machine's current run span
+    pub fn send(&self, machine: usize) -> Flow {
+        let mut state = self.state.lock().unwrap();
+        self.start_flow(&mut state, machine, Instant::now())
+    }
+
+    fn start_flow(&self, state: &mut TraceState, machine: usize, now: Instant) -> Flow {
+        let flow = Flow(state.next_flow);
+        state.next_flow += 1;
+        state.events.push(json!({
+            "name": "value", "cat": "io", "ph": "s", "id": flow.0, "pid": 1, "tid": machine,
+            "ts": self.timestamp(now),
+        }));
+        flow
+    }
+
+    // Ends an arrow at the machine's next run span
+    pub fn receive(&self, machine: usize, flow: Flow) {
+        let mut state = self.state.lock().unwrap();
+        self.end_flow(&mut state, machine, flow, Instant::now())
+    }
+
+    fn end_flow(&self, state: &mut TraceState, machine: usize, flow: Flow, now: Instant) {
+        state.events.push(json!({
+            "name": "value", "cat": "io", "ph": "f", "id": flow.0, "pid": 1, "tid": machine,
+            "ts": self.timestamp(now),
+        }));
+    }
+
+    // Writes the trace as JSON. Machines still running are shown as running until now.
+    pub fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        let now = Instant::now();
+        let state = self.state.lock().unwrap();
+        let mut events = state.events.clone();
+        for (machine, track) in state.tracks.iter().enumerate() {
+            if let Some(started) = track.running { events.push(self.span(machine, started, now)); }
+        }
+        serde_json::to_writer(&mut writer, &json!({ "traceEvents": events, "displayTimeUnit": "ns" }))?;
+        writeln!(writer)?;
+        Ok(())
+    }
+}
+
+impl TracedInputDevice {
+    fn taken(&mut self, value: i64) {
+        let trace = &self.trace;
+        let mut state = trace.state.lock().unwrap();
+        let now = Instant::now();
+        trace.instant(&mut state, self.machine, format!("in {}", value), now);
+        if self.local > 0 {
+            self.local -= 1;
+        } else if let Some(flow) = state.tracks[self.machine].pending.pop_front() {
+            trace.end_flow(&mut state, self.machine, flow, now);
+        }
+    }
+}
+
+impl InputDevice for TracedInputDevice {
+    fn put(&mut self, output: i64) {
+        self.local += 1;
+        self.inner.put(output)
+    }
+    fn get(&mut self) -> Result<i64> {
+        // The machine isn't running while it waits
+        let running = self.trace.state.lock().unwrap().tracks[self.machine].running.is_some();
+        self.trace.pause(self.machine);
+        let value = self.inner.get();
+        if let Ok(value) = value { self.taken(value); }
+        if running { self.trace.resume(self.machine); }
+        value
+    }
+    fn get_maybe(&mut self) -> Option<i64> {
+        let value = self.inner.get_maybe()?;
+        self.taken(value);
+        Some(value)
+    }
+    fn is_closed(&self) -> bool { self.inner.is_closed() }
+}
+
+impl TracedOutputDevice {
+    // The arrow is queued before sending, so the receiver can't take the value first
+    fn sending(&self, output: i64) {
+        let trace = &self.trace;
+        let mut state = trace.state.lock().unwrap();
+        let now = Instant::now();
+        trace.instant(&mut state, self.machine, format!("out {}", output), now);
+        if let Some(to) = self.to {
+            let flow = trace.start_flow(&mut state, self.machine, now);
+            state.tracks[to].pending.push_back(flow);
+        }
+    }
+}
+
+impl OutputDevice for TracedOutputDevice {
+    fn put(&mut self, output: i64) {
+        self.sending(output);
+        self.inner.put(output)
+    }
+    fn get(&mut self) -> Option<i64> { self.inner.get() }
+    fn is_closed(&self) -> bool { self.inner.is_closed() }
+    fn try_put(&mut self, output: i64) -> Result<()> {
+        self.sending(output);
+        self.inner.try_put(output)
+    }
+}
@@ -0,0 +1,783 @@
+// Compiles a small structured language down to an Intcode program image.
+//
+//     var counter = 0;                 // globals live in the image after the code
+//
+//     fn fib(n) {
+//         if (n < 2) { return n; }
+//         return fib(n - 1) + fib(n - 2);
+//     }
+//
+//     fn main() {
+//         var n = input();
+//         while (n > 0) { output(fib(n)); n = n - 1; counter = counter + 1; }
+//     }
+//
+// Every value is an integer. Expressions support + - * (no division, since
+// Intcode has none), comparisons, unary - and !, and non-short-circuiting
+// && and ||. `input()` reads one value and `output(x)` writes x and evaluates
+// to it. Execution starts at `main`, which takes no arguments. A local is
+// visible from its declaration to the end of the enclosing block and, like a
+// global, starts at 0 unless it's given a value.
+//
+// Functions use a stack frame addressed through the relative base: [rb + 0]
+// holds the return address, followed by the parameters, the locals and then
+// the temporaries used while evaluating expressions. A caller writes the
+// return address and arguments just past its own frame, moves the relative
+// base there and jumps; the callee leaves its result in a global return cell
+// and jumps back through [rb + 0]. The stack starts just past the image, in
+// the machine's extended memory. compile_program also gives the addresses of
+// the functions and the return cell, for calling them with IntcodeProgram::call.
+
+use std::collections::HashMap;
+use std::fmt;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Num(i64),
+    Sym(&'static str),
+}
+
+// Prints the token as it appeared in the source
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Ident(name) => write!(f, "{}", name),
+            Token::Num(n) => write!(f, "{}", n),
+            Token::Sym(sym) => write!(f, "{}", sym),
+        }
+    }
+}
+
+const SYMBOLS: [&str; 19] = [
+    "==", "!=", "<=", ">=", "&&", "||",
+    "+", "-", "*", "<", ">", "!", "=", "(", ")", "{", "}", ",", ";",
+];
+
+fn tokenize(source: &str) -> Result<Vec<(Token, usize)>> {
+    let mut tokens = vec![];
+    for (idx, line) in source.lines().enumerate() {
+        let line_num = idx + 1;
+        let line = line.find("//").map_or(line, |comment| &line[..comment]);
+        let mut rest = line.trim_start();
+        while !rest.is_empty() {
+            let c = rest.chars().next().unwrap();
+            let len = if c.is_ascii_digit() {
+                let len = rest.find(|c: char| !c.is_ascii_digit()).unwrap_or(rest.len());
+                let num = rest[..len].parse::<i64>().map_err(|_| {
+                    format!("line {}: integer literal out of range: {}", line_num, &rest[..len])
+                })?;
+                tokens.push((Token::Num(num), line_num));
+                len
+            } else if c.is_alphabetic() || c == '_' {
+                let len = rest.find(|c: char| !(c.is_alphanumeric() || c == '_')).unwrap_or(rest.len());
+                tokens.push((Token::Ident(rest[..len].to_owned()), line_num));
+                len
+            } else if let Some(sym) = SYMBOLS.iter().find(|sym| rest.starts_with(*sym)) {
+                tokens.push((Token::Sym(sym), line_num));
+                sym.len()
+            } else {
+                return Err(From::from(format!("line {}: unexpected character '{}'", line_num, c)));
+            };
+            rest = rest[len..].trim_start();
+        }
+    }
+    Ok(tokens)
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum BinaryOp {
+    Add, Sub, Mul, Lt, Gt, Le, Ge, Eq, Ne, And, Or,
+}
+
+#[derive(Debug)]
+enum Expr {
+    Num(i64),
+    Var(String, usize),
+    Call(String, Vec<Expr>, usize),
+    Neg(Box<Expr>),
+    Not(Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug)]
+enum Stmt {
+    Var(String, Option<Expr>, usize),
+    Assign(String, Expr, usize),
+    If(Expr, Vec<Stmt>, Vec<Stmt>),
+    While(Expr, Vec<Stmt>),
+    Return(Option<Expr>),
+    Expr(Expr),
+}
+
+struct Function {
+    name: String,
+    params: Vec<String>,
+    body: Vec<Stmt>,
+    line: usize,
+}
+
+struct Global {
+    name: String,
+    value: i64,
+    line: usize,
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+}
+
+impl Parser {
+    fn line(&self) -> usize {
+        self.tokens.get(self.pos).or_else(|| self.tokens.last()).map_or(1, |(_, line)| *line)
+    }
+
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(token, _)| token)
+    }
+
+    fn error<T>(&self, message: &str) -> Result<T> {
+        self.error_at(self.line(), message)
+    }
+
+    fn error_at<T>(&self, line: usize, message: &str) -> Result<T> {
+        match self.peek() {
+            Some(token) => Err(From::from(format!("line {}: {}, found '{}'", line, message, token))),
+            None => Err(From::from(format!("line {}: {}, found end of input", line, message))),
+        }
+    }
+
+    fn at_sym(&self, sym: &str) -> bool {
+        matches!(self.peek(), Some(Token::Sym(s)) if *s == sym)
+    }
+
+    fn at_keyword(&self, keyword: &str) -> bool {
+        matches!(self.peek(), Some(Token::Ident(s)) if s == keyword)
+    }
+
+    fn expect_sym(&mut self, sym: &str) -> Result<()> {
+        if self.at_sym(sym) {
+            self.pos += 1;
+            Ok(())
+        } else if sym == ";" && self.pos > 0 {
+            // A missing terminator belongs at the end of the statement, not wherever the next token is
+            self.error_at(self.tokens[self.pos - 1].1, "expected ';'")
+        } else {
+            self.error(&format!("expected '{}'", sym))
+        }
+    }
+
+    fn expect_ident(&mut self) -> Result<String> {
+        match self.peek() {
+            Some(Token::Ident(name)) if !is_keyword(name) => {
+                let name = name.clone();
+                self.pos += 1;
+                Ok(name)
+            },
+            _ => self.error("expected identifier"),
+        }
+    }
+
+    fn parse_program(&mut self) -> Result<(Vec<Global>, Vec<Function>)> {
+        let (mut globals, mut functions) = (vec![], vec![]);
+        while self.peek().is_some() {
+            let line = self.line();
+            if self.at_keyword("var") {
+                self.pos += 1;
+                let name = self.expect_ident()?;
+                let mut value = 0;
+                if self.at_sym("=") {
+                    self.pos += 1;
+                    let negate = self.at_sym("-");
+                    if negate { self.pos += 1; }
+                    value = match self.peek() {
+                        Some(Token::Num(n)) => if negate { -n } else { *n },
+                        _ => return self.error("expected integer literal for global initializer"),
+                    };
+                    self.pos += 1;
+                }
+                self.expect_sym(";")?;
+                globals.push(Global{ name, value, line });
+            } else if self.at_keyword("fn") {
+                self.pos += 1;
+                let name = self.expect_ident()?;
+                self.expect_sym("(")?;
+                let mut params = vec![];
+                while !self.at_sym(")") {
+                    if !params.is_empty() { self.expect_sym(",")?; }
+                    params.push(self.expect_ident()?);
+                }
+                self.expect_sym(")")?;
+                let body = self.parse_block()?;
+                functions.push(Function{ name, params, body, line });
+            } else {
+                return self.error("expected 'fn' or 'var'");
+            }
+        }
+        Ok((globals, functions))
+    }
+
+    fn parse_block(&mut self) -> Result<Vec<Stmt>> {
+        self.expect_sym("{")?;
+        let mut stmts = vec![];
+        while !self.at_sym("}") {
+            if self.peek().is_none() { return self.error("expected '}'"); }
+            stmts.push(self.parse_stmt()?);
+        }
+        self.pos += 1;
+        Ok(stmts)
+    }
+
+    fn parse_stmt(&mut self) -> Result<Stmt> {
+        let line = self.line();
+        if self.at_keyword("var") {
+            self.pos += 1;
+            let name = self.expect_ident()?;
+            let init = if self.at_sym("=") {
+                self.pos += 1;
+                Some(self.parse_expr()?)
+            } else {
+                None
+            };
+            self.expect_sym(";")?;
+            Ok(Stmt::Var(name, init, line))
+        } else if self.at_keyword("if") {
+            self.pos += 1;
+            self.expect_sym("(")?;
+            let cond = self.parse_expr()?;
+            self.expect_sym(")")?;
+            let then = self.parse_block()?;
+            let otherwise = if self.at_keyword("else") {
+                self.pos += 1;
+                if self.at_keyword("if") { vec![self.parse_stmt()?] } else { self.parse_block()? }
+            } else {
+                vec![]
+            };
+            Ok(Stmt::If(cond, then, otherwise))
+        } else if self.at_keyword("while") {
+            self.pos += 1;
+            self.expect_sym("(")?;
+            let cond = self.parse_expr()?;
+            self.expect_sym(")")?;
+            Ok(Stmt::While(cond, self.parse_block()?))
+        } else if self.at_keyword("return") {
+            self.pos += 1;
+            let value = if self.at_sym(";") { None } else { Some(self.parse_expr()?) };
+            self.expect_sym(";")?;
+            Ok(Stmt::Return(value))
+        } else if let (Some(Token::Ident(name)), Some((Token::Sym("="), _))) = (self.peek(), self.tokens.get(self.pos + 1)) {
+            let name = name.clone();
+            self.pos += 2;
+            let value = self.parse_expr()?;
+            self.expect_sym(";")?;
+            Ok(Stmt::Assign(name, value, line))
+        } else {
+            let expr = self.parse_expr()?;
+            self.expect_sym(";")?;
+            Ok(Stmt::Expr(expr))
+        }
+    }
+
+    fn parse_expr(&mut self) -> Result<Expr> {
+        self.parse_binary(0)
+    }
+
+    // Precedence climbing, from loosest (||) to tightest (*) binding
+    fn parse_binary(&mut self, level: usize) -> Result<Expr> {
+        const LEVELS: [&[(&str, BinaryOp)]; 6] = [
+            &[("||", BinaryOp::Or)],
+            &[("&&", BinaryOp::And)],
+            &[("==", BinaryOp::Eq), ("!=", BinaryOp::Ne)],
+            &[("<=", BinaryOp::Le), (">=", BinaryOp::Ge), ("<", BinaryOp::Lt), (">", BinaryOp::Gt)],
+            &[("+", BinaryOp::Add), ("-", BinaryOp::Sub)],
+            &[("*", BinaryOp::Mul)],
+        ];
+        if level == LEVELS.len() { return self.parse_unary(); }
+
+        let mut lhs = self.parse_binary(level + 1)?;
+        while let Some((_, op)) = LEVELS[level].iter().find(|(sym, _)| self.at_sym(sym)) {
+            self.pos += 1;
+            let rhs = self.parse_binary(level + 1)?;
+            lhs = Expr::Binary(*op, Box::new(lhs), Box::new(rhs));
+        }
+        Ok(lhs)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr> {
+        if self.at_sym("-") {
+            self.pos += 1;
+            Ok(Expr::Neg(Box::new(self.parse_unary()?)))
+        } else if self.at_sym("!") {
+            self.pos += 1;
+            Ok(Expr::Not(Box::new(self.parse_unary()?)))
+        } else {
+            self.parse_primary()
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr> {
+        let line = self.line();
+        match self.peek() {
+            Some(Token::Num(n)) => {
+                let n = *n;
+                self.pos += 1;
+                Ok(Expr::Num(n))
+            },
+            Some(Token::Sym("(")) => {
+                self.pos += 1;
+                let expr = self.parse_expr()?;
+                self.expect_sym(")")?;
+                Ok(expr)
+            },
+            Some(Token::Ident(_)) => {
+                let name = self.expect_ident()?;
+                if self.at_sym("(") {
+                    self.pos += 1;
+                    let mut args = vec![];
+                    while !self.at_sym(")") {
+                        if !args.is_empty() { self.expect_sym(",")?; }
+                        args.push(self.parse_expr()?);
+                    }
+                    self.pos += 1;
+                    Ok(Expr::Call(name, args, line))
+                } else {
+                    Ok(Expr::Var(name, line))
+                }
+            },
+            _ => self.error("expected expression"),
+        }
+    }
+}
+
+fn is_keyword(name: &str) -> bool {
+    ["fn", "var", "if", "else", "while", "return"].contains(&name)
+}
+
+// A word of the emitted image whose value may not be known until later
+#[derive(Copy, Clone, Debug)]
+enum Word {
+    Lit(i64),
+    Label(usize),
+    // The current function's frame size plus an offset, or its negation
+    Frame(i64),
+    NegFrame,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Operand {
+    Imm(Word),
+    Pos(Word),
+    Rel(Word),
+}
+
+const OP_ADD: i64 = 1;
+const OP_MUL: i64 = 2;
+const OP_IN: i64 = 3;
+const OP_OUT: i64 = 4;
+const OP_JNZ: i64 = 5;
+const OP_JEZ: i64 = 6;
+const OP_LT: i64 = 7;
+const OP_EQ: i64 = 8;
+const OP_ARB: i64 = 9;
+const OP_HLT: i64 = 99;
+
+// Per-function codegen state. Slot 0 of the frame is the return address.
+struct Frame {
+    // Variables visible at the current point, mapped to their slots
+    vars: HashMap<String, i64>,
+    next_local: i64,
+    temps_base: i64,
+    temps: i64,
+    max_temps: i64,
+    fixups: Vec<(usize, i64)>,
+}
+
+impl Frame {
+    fn size(&self) -> i64 { self.temps_base + self.max_temps }
+}
+
+struct Codegen {
+    code: Vec<i64>,
+    labels: Vec<Option<usize>>,
+    label_fixups: Vec<(usize, usize)>,
+    functions: HashMap<String, (usize, usize)>,
+    globals: HashMap<String, usize>,
+    ret_label: usize,
+    frame: Frame,
+}
+
+impl Codegen {
+    fn new_label(&mut self) -> usize {
+        self.labels.push(None);
+        self.labels.len() - 1
+    }
+
+    fn place(&mut self, label: usize) {
+        self.labels[label] = Some(self.code.len());
+    }
+
+    fn emit_word(&mut self, word: Word) {
+        match word {
+            Word::Lit(v) => self.code.push(v),
+            Word::Label(label) => {
+                self.label_fixups.push((self.code.len(), label));
+                self.code.push(0);
+            },
+            Word::Frame(offset) => {
+                self.frame.fixups.push((self.code.len(), 1));
+                self.code.push(offset);
+            },
+            Word::NegFrame => {
+                self.frame.fixups.push((self.code.len(), -1));
+                self.code.push(0);
+            },
+        }
+    }
+
+    fn emit(&mut self, opcode: i64, operands: &[Operand]) {
+        let modes = operands.iter().enumerate().fold(0, |acc, (idx, operand)| {
+            acc + 10i64.pow(idx as u32) * match operand {
+                Operand::Pos(_) => 0,
+                Operand::Imm(_) => 1,
+                Operand::Rel(_) => 2,
+            }
+        });
+        self.code.push(modes * 100 + opcode);
+        for operand in operands {
+            match operand {
+                Operand::Imm(word) | Operand::Pos(word) | Operand::Rel(word) => self.emit_word(*word),
+            }
+        }
+    }
+
+    fn emit_jump(&mut self, label: usize) {
+        self.emit(OP_JNZ, &[Operand::Imm(Word::Lit(1)), Operand::Imm(Word::Label(label))]);
+    }
+
+    fn emit_copy(&mut self, from: Operand, to: Operand) {
+        self.emit(OP_ADD, &[from, Operand::Imm(Word::Lit(0)), to]);
+    }
+
+    fn alloc_temp(&mut self) -> Operand {
+        let slot = self.frame.temps_base + self.frame.temps;
+        self.frame.temps += 1;
+        self.frame.max_temps = std::cmp::max(self.frame.max_temps, self.frame.temps);
+        Operand::Rel(Word::Lit(slot))
+    }
+
+    fn declare(&mut self, name: &str, line: usize) -> Result<Operand> {
+        if self.frame.vars.contains_key(name) {
+            return Err(From::from(format!("line {}: '{}' is already declared in this function", line, name)));
+        }
+        let slot = self.frame.next_local;
+        self.frame.next_local += 1;
+        self.frame.vars.insert(name.to_owned(), slot);
+        Ok(Operand::Rel(Word::Lit(slot)))
+    }
+
+    fn lookup(&self, name: &str, line: usize) -> Result<Operand> {
+        if let Some(slot) = self.frame.vars.get(name) {
+            Ok(Operand::Rel(Word::Lit(*slot)))
+        } else if let Some(label) = self.globals.get(name) {
+            Ok(Operand::Pos(Word::Label(*label)))
+        } else {
+            Err(From::from(format!("line {}: undefined variable '{}'", line, name)))
+        }
+    }
+
+    // Emits code evaluating the expression and returns where its value can be read.
+    // Temporaries allocated for subexpressions are released before returning.
+    fn gen_expr(&mut self, expr: &Expr) -> Result<Operand> {
+        let mark = self.frame.temps;
+        match expr {
+            Expr::Num(n) => Ok(Operand::Imm(Word::Lit(*n))),
+            Expr::Var(name, line) => self.lookup(name, *line),
+            Expr::Neg(inner) => {
+                let value = self.gen_expr(inner)?;
+                self.frame.temps = mark;
+                let result = self.alloc_temp();
+                self.emit(OP_MUL, &[value, Operand::Imm(Word::Lit(-1)), result]);
+                Ok(result)
+            },
+            Expr::Not(inner) => {
+                let value = self.gen_expr(inner)?;
+                self.frame.temps = mark;
+                let result = self.alloc_temp();
+                self.emit(OP_EQ, &[value, Operand::Imm(Word::Lit(0)), result]);
+                Ok(result)
+            },
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = self.gen_expr(lhs)?;
+                let rhs = self.gen_expr(rhs)?;
+                self.frame.temps = mark;
+                // The result may reuse the slot holding either operand, so anything derived
+                // from rhs goes into scratch first. Scratch can only alias rhs itself.
+                let (result, scratch) = (self.alloc_temp(), self.alloc_temp());
+                let zero = Operand::Imm(Word::Lit(0));
+                match op {
+                    BinaryOp::Add => self.emit(OP_ADD, &[lhs, rhs, result]),
+                    BinaryOp::Mul => self.emit(OP_MUL, &[lhs, rhs, result]),
+                    BinaryOp::Sub => {
+                        self.emit(OP_MUL, &[rhs, Operand::Imm(Word::Lit(-1)), scratch]);
+                        self.emit(OP_ADD, &[lhs, scratch, result]);
+                    },
+                    BinaryOp::Lt => self.emit(OP_LT, &[lhs, rhs, result]),
+                    BinaryOp::Gt => self.emit(OP_LT, &[rhs, lhs, result]),
+                    BinaryOp::Le => {
+                        self.emit(OP_LT, &[rhs, lhs, result]);
+                        self.emit(OP_EQ, &[result, zero, result]);
+                    },
+                    BinaryOp::Ge => {
+                        self.emit(OP_LT, &[lhs, rhs, result]);
+                        self.emit(OP_EQ, &[result, zero, result]);
+                    },
+                    BinaryOp::Eq => self.emit(OP_EQ, &[lhs, rhs, result]),
+                    BinaryOp::Ne => {
+                        self.emit(OP_EQ, &[lhs, rhs, result]);
+                        self.emit(OP_EQ, &[result, zero, result]);
+                    },
+                    BinaryOp::And | BinaryOp::Or => {
+                        self.emit(OP_EQ, &[rhs, zero, scratch]);
+                        self.emit(OP_EQ, &[lhs, zero, result]);
+                        // a && b is !(!a + !b), a || b is !(!a * !b)
+                        let combine = if *op == BinaryOp::And { OP_ADD } else { OP_MUL };
+                        self.emit(combine, &[result, scratch, result]);
+                        self.emit(OP_EQ, &[result, zero, result]);
+                    },
+                }
+                self.frame.temps = mark + 1;
+                Ok(result)
+            },
+            Expr::Call(name, args, line) => self.gen_call(name, args, *line, mark),
+        }
+    }
+
+    fn gen_call(&mut self, name: &str, args: &[Expr], line: usize, mark: i64) -> Result<Operand> {
+        let check_arity = |expected: usize| -> Result<()> {
+            if args.len() == expected { return Ok(()) }
+            Err(From::from(format!("line {}: '{}' takes {} argument(s) but {} were given",
+                line, name, expected, args.len())))
+        };
+
+        match name {
+            "input" => {
+                check_arity(0)?;
+                let result = self.alloc_temp();
+                self.emit(OP_IN, &[result]);
+                Ok(result)
+            },
+            "output" => {
+                check_arity(1)?;
+                let value = self.gen_expr(&args[0])?;
+                self.emit(OP_OUT, &[value]);
+                Ok(value)
+            },
+            _ => {
+                let (label, arity) = *self.functions.get(name).ok_or_else(|| {
+                    format!("line {}: undefined function '{}'", line, name)
+                })?;
+                check_arity(arity)?;
+
+                // Evaluate every argument before writing any, since nested calls use the same space
+                let values = args.iter().map(|arg| self.gen_expr(arg)).collect::<Result<Vec<Operand>>>()?;
+                for (idx, value) in values.into_iter().enumerate() {
+                    self.emit_copy(value, Operand::Rel(Word::Frame(1 + idx as i64)));
+                }
+
+                let return_label = self.new_label();
+                self.emit_copy(Operand::Imm(Word::Label(return_label)), Operand::Rel(Word::Frame(0)));
+                self.emit(OP_ARB, &[Operand::Imm(Word::Frame(0))]);
+                self.emit_jump(label);
+                self.place(return_label);
+                self.emit(OP_ARB, &[Operand::Imm(Word::NegFrame)]);
+
+                self.frame.temps = mark;
+                let result = self.alloc_temp();
+                self.emit_copy(Operand::Pos(Word::Label(self.ret_label)), result);
+                Ok(result)
+            },
+        }
+    }
+
+    fn gen_return(&mut self, value: Operand) {
+        self.emit_copy(value, Operand::Pos(Word::Label(self.ret_label)));
+        self.emit(OP_JEZ, &[Operand::Imm(Word::Lit(0)), Operand::Rel(Word::Lit(0))]);
+    }
+
+    fn gen_block(&mut self, stmts: &[Stmt]) -> Result<()> {
+        // Locals declared in this block go out of scope at its end
+        let visible = self.frame.vars.clone();
+        for stmt in stmts {
+            let mark = self.frame.temps;
+            match stmt {
+                Stmt::Var(name, value, line) => {
+                    // Evaluated before declaring, so the initialiser can't see the new variable.
+                    // Without one the slot is cleared, since an earlier call may have left a value there.
+                    let value = match value {
+                        Some(value) => self.gen_expr(value)?,
+                        None => Operand::Imm(Word::Lit(0)),
+                    };
+                    let dest = self.declare(name, *line)?;
+                    self.emit_copy(value, dest);
+                },
+                Stmt::Assign(name, value, line) => {
+                    let value = self.gen_expr(value)?;
+                    let dest = self.lookup(name, *line)?;
+                    self.emit_copy(value, dest);
+                },
+                Stmt::If(cond, then, otherwise) => {
+                    let (else_label, end_label) = (self.new_label(), self.new_label());
+                    let cond = self.gen_expr(cond)?;
+                    self.frame.temps = mark;
+                    self.emit(OP_JEZ, &[cond, Operand::Imm(Word::Label(else_label))]);
+                    self.gen_block(then)?;
+                    if !otherwise.is_empty() { self.emit_jump(end_label); }
+                    self.place(else_label);
+                    self.gen_block(otherwise)?;
+                    self.place(end_label);
+                },
+                Stmt::While(cond, body) => {
+                    let (top_label, end_label) = (self.new_label(), self.new_label());
+                    self.place(top_label);
+                    let cond = self.gen_expr(cond)?;
+                    self.frame.temps = mark;
+                    self.emit(OP_JEZ, &[cond, Operand::Imm(Word::Label(end_label))]);
+                    self.gen_block(body)?;
+                    self.emit_jump(top_label);
+                    self.place(end_label);
+                },
+                Stmt::Return(value) => {
+                    let value = match value {
+                        Some(value) => self.gen_expr(value)?,
+                        None => Operand::Imm(Word::Lit(0)),
+                    };
+                    self.gen_return(value);
+                },
+                Stmt::Expr(expr) => { self.gen_expr(expr)?; },
+            }
+            self.frame.temps = mark;
+        }
+        self.frame.vars = visible;
+        Ok(())
+    }
+
+    fn gen_function(&mut self, function: &Function) -> Result<()> {
+        let mut vars = HashMap::new();
+        for (idx, param) in function.params.iter().enumerate() {
+            if vars.insert(param.clone(), 1 + idx as i64).is_some() {
+                return Err(From::from(format!("line {}: duplicate parameter '{}'", function.line, param)));
+            }
+        }
+        let next_local = 1 + vars.len() as i64;
+        let temps_base = next_local + count_locals(&function.body);
+
+        self.frame = Frame{ vars, next_local, temps_base, temps: 0, max_temps: 0, fixups: vec![] };
+        let label = self.functions[&function.name].0;
+        self.place(label);
+        self.gen_block(&function.body)?;
+        self.gen_return(Operand::Imm(Word::Lit(0)));
+
+        let size = self.frame.size();
+        for (pos, sign) in std::mem::take(&mut self.frame.fixups) {
+            self.code[pos] += sign * size;
+        }
+        Ok(())
+    }
+}
+
+// Counts the locals declared anywhere in the function body, each of which gets its own slot
+fn count_locals(stmts: &[Stmt]) -> i64 {
+    stmts.iter().map(|stmt| match stmt {
+        Stmt::Var(..) => 1,
+        Stmt::If(_, then, otherwise) => count_locals(then) + count_locals(otherwise),
+        Stmt::While(_, body) => count_locals(body),
+        _ => 0,
+    }).sum()
+}
+
+// A compiled image, along with the addresses a host needs to call its functions
+pub struct Compiled {
+    pub memory: Vec<i64>,
+    // Every function and global by name
+    pub symbols: HashMap<String, usize>,
+    // Where functions leave their result
+    pub return_cell: usize,
+}
+
+// Compiles source text to a program image runnable with IntcodeProgram::from_memory
+pub fn compile(source: &str) -> Result<Vec<i64>> {
+    Ok(compile_program(source)?.memory)
+}
+
+// Compiles source text, keeping the addresses of its functions and globals
+pub fn compile_program(source: &str) -> Result<Compiled> {
+    let (globals, functions) = Parser{ tokens: tokenize(source)?, pos: 0 }.parse_program()?;
+
+    let mut gen = Codegen{
+        code: vec![],
+        labels: vec![],
+        label_fixups: vec![],
+        functions: HashMap::new(),
+        globals: HashMap::new(),
+        ret_label: 0,
+        frame: Frame{ vars: HashMap::new(), next_local: 0, temps_base: 0, temps: 0, max_temps: 0, fixups: vec![] },
+    };
+    gen.ret_label = gen.new_label();
+    let (stack_label, halt_label) = (gen.new_label(), gen.new_label());
+
+    for function in &functions {
+        if ["input", "output"].contains(&function.name.as_str()) {
+            return Err(From::from(format!("line {}: '{}' is a builtin", function.line, function.name)));
+        }
+        let label = gen.new_label();
+        if gen.functions.insert(function.name.clone(), (label, function.params.len())).is_some() {
+            return Err(From::from(format!("line {}: duplicate function '{}'", function.line, function.name)));
+        }
+    }
+    for global in &globals {
+        if gen.functions.contains_key(&global.name) {
+            return Err(From::from(format!("line {}: '{}' is already a function", global.line, global.name)));
+        }
+        let label = gen.new_label();
+        if gen.globals.insert(global.name.clone(), label).is_some() {
+            return Err(From::from(format!("line {}: duplicate global '{}'", global.line, global.name)));
+        }
+    }
+
+    let main_label = match gen.functions.get("main") {
+        Some((label, 0)) => *label,
+        Some(_) => return Err(From::from("'main' must not take any parameters")),
+        None => return Err(From::from("no 'main' function defined")),
+    };
+
+    // Entry: set up the first frame with a return address pointing at the halt
+    gen.emit(OP_ARB, &[Operand::Imm(Word::Label(stack_label))]);
+    gen.emit_copy(Operand::Imm(Word::Label(halt_label)), Operand::Rel(Word::Lit(0)));
+    gen.emit_jump(main_label);
+    gen.place(halt_label);
+    gen.emit(OP_HLT, &[]);
+
+    for function in &functions {
+        gen.gen_function(function)?;
+    }
+
+    gen.place(gen.ret_label);
+    gen.code.push(0);
+    for global in &globals {
+        gen.place(gen.globals[&global.name]);
+        gen.code.push(global.value);
+    }
+    gen.place(stack_label);
+
+    for (pos, label) in std::mem::take(&mut gen.label_fixups) {
+        gen.code[pos] = gen.labels[label].expect("every label is placed") as i64;
+    }
+    let address = |label: usize| gen.labels[label].expect("every label is placed");
+    let symbols = gen.functions.iter().map(|(name, &(label, _))| (name.clone(), address(label)))
+        .chain(gen.globals.iter().map(|(name, &label)| (name.clone(), address(label))))
+        .collect();
+    Ok(Compiled{ return_cell: address(gen.ret_label), symbols, memory: gen.code })
+}
@@ -0,0 +1,507 @@
+// A Debug Adapter Protocol server, speaking JSON messages over a pair of streams
+// (normally stdin and stdout) so editors can debug Intcode programs.
+//
+// `launch` takes the path of a program image, or of assembly source (`.s` or `.asm`)
+// which is assembled first so breakpoints can be set by line. Breakpoints can also be
+// set on addresses with `setInstructionBreakpoints`. There's a single thread, whose one
+// stack frame is the instruction at ip. `next` steps over a source line, running any
+// calls it makes, and `stepOut` runs until the relative base drops below where it was
+// and the return jump is taken, which follows the assembler's calling convention.
+// Requests are read on their own thread, so a `pause` can stop a running program. The
+// Registers scope shows ip and rb, and the Memory scope groups cells into ranges of 16.
+// Outputs are sent as output events, and the debug console queues input: `5`, `1, 2, 3`
+// or `ascii some text`, while `[n]` shows the value of cell n.
+
+use super::asm;
+use super::program::{Event, IntcodeProgram};
+use serde_json::{json, Value};
+use std::cmp;
+use std::collections::{BTreeMap, BTreeSet};
+use std::collections::VecDeque;
+use std::io::{BufRead, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+const THREAD_ID: i64 = 1;
+const REGISTERS_REFERENCE: i64 = 1;
+const MEMORY_REFERENCE: i64 = 2;
+// Memory ranges are numbered from here
+const RANGE_REFERENCE: i64 = 1000;
+const RANGE_CELLS: usize = 16;
+// Instructions run between checks for a pause request
+const PAUSE_POLL: usize = 10000;
+// Longest message body accepted from the client
+const MAX_MESSAGE: usize = 1 << 20;
+
+type SourceMap = BTreeMap<usize, (String, usize)>;
+
+// What to do once a request has been responded to
+enum After {
+    Nothing,
+    Initialized,
+    ConfigurationDone,
+    Continue,
+    Next,
+    StepIn,
+    StepOut,
+    Disconnect,
+}
+
+struct Session<W: Write> {
+    writer: W,
+    seq: i64,
+    // Messages from the reader thread, and requests that arrived while the program ran
+    incoming: Receiver<std::result::Result<Value, String>>,
+    pending: VecDeque<Value>,
+    program: Option<IntcodeProgram>,
+    // The assembly source the program came from, with its source map
+    source: Option<(String, SourceMap)>,
+    line_breakpoints: BTreeSet<usize>,
+    instruction_breakpoints: BTreeSet<usize>,
+    stop_on_entry: bool,
+    exited: bool,
+}
+
+// Reads one message, or None at the end of the stream
+fn read_message<R: BufRead>(reader: &mut R) -> Result<Option<Value>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header)? == 0 { return Ok(None) }
+        let header = header.trim();
+        if header.is_empty() {
+            if length.is_some() { break } else { continue }
+        }
+        if let Some(value) = header.strip_prefix("Content-Length:") {
+            length = Some(value.trim().parse::<usize>()?);
+        }
+    }
+
+    let length = length.unwrap();
+    if length > MAX_MESSAGE {
+        return Err(From::from(format!("Message of {} bytes is longer than the limit of {}", length, MAX_MESSAGE)));
+    }
+    let mut body = vec![0u8; length];
+    reader.read_exact(&mut body)?;
+    Ok(Some(serde_json::from_slice(&body)?))
+}
+
+fn is_assembly(path: &str) -> bool {
+    matches!(Path::new(path).extension().and_then(|e| e.to_str()), Some("s") | Some("asm"))
+}
+
+// Paths from the client may be spelled differently from the one launched
+fn same_file(a: &str, b: &str) -> bool {
+    match (Path::new(a).canonicalize(), Path::new(b).canonicalize()) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => a == b,
+    }
+}
+
+fn parse_console_inputs(expression: &str) -> Option<Vec<i64>> {
+    if let Some(text) = expression.strip_prefix("ascii ") {
+        return Some(text.chars().chain(std::iter::once('\n')).map(|c| c as i64).collect());
+    }
+    let values: Vec<&str> = expression.split(|c: char| c == ',' || c.is_whitespace()).filter(|s| !s.is_empty()).collect();
+    if values.is_empty() { return None }
+    values.iter().map(|v| v.parse::<i64>().ok()).collect()
+}
+
+impl<W: Write> Session<W> {
+    fn send(&mut self, mut message: Value) -> Result<()> {
+        self.seq += 1;
+        message["seq"] = json!(self.seq);
+        let body = message.to_string();
+        write!(self.writer, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+        self.writer.flush()?;
+        Ok(())
+    }
+
+    fn send_event(&mut self, event: &str, body: Value) -> Result<()> {
+        self.send(json!({ "type": "event", "event": event, "body": body }))
+    }
+
+    fn respond(&mut self, request: &Value, result: std::result::Result<Value, String>) -> Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = json!(message),
+        }
+        self.send(response)
+    }
+
+    fn next_request(&mut self) -> Result<Option<Value>> {
+        if let Some(request) = self.pending.pop_front() { return Ok(Some(request)) }
+        match self.incoming.recv() {
+            Ok(message) => message.map(Some).map_err(From::from),
+            Err(_) => Ok(None),
+        }
+    }
+
+    // Looks for a pause among the requests that arrived while the program ran, responding
+    // to it and keeping the rest to handle once the program has stopped
+    fn pause_requested(&mut self) -> Result<bool> {
+        let mut paused = false;
+        while let Ok(message) = self.incoming.try_recv() {
+            let request = message?;
+            if !paused && request["type"] == "request" && request["command"] == "pause" {
+                self.respond(&request, Ok(json!({})))?;
+                paused = true;
+            } else {
+                self.pending.push_back(request);
+            }
+        }
+        Ok(paused)
+    }
+
+    fn program(&mut self) -> Result<&mut IntcodeProgram> {
+        self.program.as_mut().ok_or_else(|| From::from("No program has been launched"))
+    }
+
+    fn source_line(&self, address: usize) -> Option<usize> {
+        self.source.as_ref().and_then(|(_, map)| map.get(&address)).map(|(_, line)| *line)
+    }
+
+    fn is_breakpoint(&self, address: usize) -> bool {
+        self.line_breakpoints.contains(&address) || self.instruction_breakpoints.contains(&address)
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value> {
+        let path = args["program"].as_str().ok_or("launch requires a program path")?;
+        let contents = std::fs::read_to_string(path)?;
+        let mut program = if is_assembly(path) {
+            let linked = asm::link(&[asm::assemble_module(path, &contents)?])?;
+            self.source = Some((String::from(path), linked.source_map));
+            IntcodeProgram::from_memory(linked.memory)
+        } else {
+            IntcodeProgram::from_raw_input(contents.trim())?
+        };
+        for input in args["input"].as_array().into_iter().flatten() {
+            program.give_input(input.as_i64().ok_or("Inputs must be integers")?);
+        }
+        self.program = Some(program);
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(json!({}))
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Result<Value> {
+        let path = args["source"]["path"].as_str().unwrap_or("");
+        let lines: Vec<i64> = args["breakpoints"].as_array().into_iter().flatten()
+            .filter_map(|b| b["line"].as_i64()).collect();
+
+        self.line_breakpoints.clear();
+        let map = match &self.source {
+            Some((source, map)) if same_file(source, path) => map.clone(),
+            _ => BTreeMap::new(),
+        };
+        let mut breakpoints = vec![];
+        for line in lines {
+            // The first instruction assembled from the line, or failing that from a later one
+            let address = map.iter().find(|(_, (_, l))| *l as i64 == line).map(|(a, _)| *a)
+                .or_else(|| map.iter().filter(|(_, (_, l))| *l as i64 > line).min_by_key(|(_, (_, l))| *l).map(|(a, _)| *a));
+            match address {
+                Some(address) => {
+                    self.line_breakpoints.insert(address);
+                    breakpoints.push(json!({
+                        "verified": true,
+                        "line": self.source_line(address),
+                        "instructionReference": address.to_string(),
+                    }));
+                },
+                None => breakpoints.push(json!({ "verified": false, "line": line, "message": "No instruction at this line" })),
+            }
+        }
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn set_instruction_breakpoints(&mut self, args: &Value) -> Result<Value> {
+        self.instruction_breakpoints.clear();
+        let mut breakpoints = vec![];
+        for breakpoint in args["breakpoints"].as_array().into_iter().flatten() {
+            let reference = breakpoint["instructionReference"].as_str().unwrap_or("");
+            let address = reference.parse::<i64>().ok().map(|a| a + breakpoint["offset"].as_i64().unwrap_or(0));
+            match address {
+                Some(address) if address >= 0 => {
+                    self.instruction_breakpoints.insert(address as usize);
+                    breakpoints.push(json!({ "verified": true, "instructionReference": address.to_string() }));
+                },
+                _ => breakpoints.push(json!({ "verified": false, "message": "Invalid address" })),
+            }
+        }
+        Ok(json!({ "breakpoints": breakpoints }))
+    }
+
+    fn stack_trace(&mut self) -> Result<Value> {
+        let ip = self.program()?.ip();
+        let name = self.program()?.decode(ip).map(|i| i.to_string()).unwrap_or_else(|_| String::from("<invalid instruction>"));
+        let mut frame = json!({
+            "id": 1,
+            "name": name,
+            "line": self.source_line(ip).unwrap_or(0),
+            "column": 0,
+            "instructionPointerReference": ip.to_string(),
+        });
+        if let (Some((path, _)), Some(_)) = (&self.source, self.source_line(ip)) {
+            frame["source"] = json!({ "path": path });
+        }
+        Ok(json!({ "stackFrames": [frame], "totalFrames": 1 }))
+    }
+
+    fn memory_len(&mut self) -> Result<usize> {
+        let program = self.program()?;
+        let highest_extended = program.extended_cells().last().map_or(0, |(address, _)| address + 1);
+        Ok(cmp::max(program.image_len(), highest_extended))
+    }
+
+    fn variables(&mut self, args: &Value) -> Result<Value> {
+        let reference = args["variablesReference"].as_i64().ok_or("Missing variablesReference")?;
+        let variables = match reference {
+            REGISTERS_REFERENCE => {
+                let program = self.program()?;
+                vec![
+                    json!({ "name": "ip", "value": program.ip().to_string(), "variablesReference": 0 }),
+                    json!({ "name": "rb", "value": program.relative_base().to_string(), "variablesReference": 0 }),
+                ]
+            },
+            MEMORY_REFERENCE => {
+                let ranges = self.memory_len()?.div_ceil(RANGE_CELLS);
+                (0..ranges).map(|range| {
+                    let start = range * RANGE_CELLS;
+                    let reference = (range as i64).checked_add(RANGE_REFERENCE).ok_or("Too much memory to show")?;
+                    Ok(json!({
+                        "name": format!("[{}..{}]", start, start + RANGE_CELLS - 1),
+                        "value": "",
+                        "variablesReference": reference,
+                    }))
+                }).collect::<Result<Vec<Value>>>()?
+            },
+            range if range >= RANGE_REFERENCE => {
+                let start = ((range - RANGE_REFERENCE) as usize).checked_mul(RANGE_CELLS)
+                    .filter(|start| start.checked_add(RANGE_CELLS).is_some())
+                    .ok_or("Invalid variablesReference")?;
+                let program = self.program()?;
+                (start..start + RANGE_CELLS).map(|address| json!({
+                    "name": format!("[{}]", address),
+                    "value": program.load_position(address).to_string(),
+                    "variablesReference": 0,
+                })).collect()
+            },
+            _ => vec![],
+        };
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn evaluate(&mut self, args: &Value) -> Result<Value> {
+        let expression = args["expression"].as_str().unwrap_or("").trim();
+        if let Some(address) = expression.strip_prefix('[').and_then(|e| e.strip_suffix(']')) {
+            let value = self.program()?.load_position(address.trim().parse::<usize>()?);
+            return Ok(json!({ "result": value.to_string(), "variablesReference": 0 }));
+        }
+        let inputs = parse_console_inputs(expression)
+            .ok_or_else(|| format!("Expected inputs, 'ascii <text>' or [address], got: {}", expression))?;
+        let program = self.program()?;
+        for &input in &inputs { program.give_input(input); }
+        Ok(json!({ "result": format!("Queued {} input(s)", inputs.len()), "variablesReference": 0 }))
+    }
+
+    fn stopped(&mut self, reason: &str, description: Option<&str>) -> Result<()> {
+        let mut body = json!({ "reason": reason, "threadId": THREAD_ID, "allThreadsStopped": true });
+        if let Some(description) = description {
+            body["description"] = json!(description);
+            body["text"] = json!(description);
+        }
+        self.send_event("stopped", body)
+    }
+
+    // Executes one instruction, returning whether execution should stop
+    fn execute_one(&mut self) -> Result<bool> {
+        match self.program()?.step() {
+            Ok(Some(Event::ProducedOutput)) => {
+                let output = self.program()?.get_output().unwrap_or_default();
+                self.send_event("output", json!({ "category": "stdout", "output": format!("{}\n", output) }))?;
+                Ok(false)
+            },
+            Ok(Some(Event::InputRequired)) | Ok(Some(Event::PeerClosed)) => {
+                self.stopped("pause", Some("Waiting for input"))?;
+                Ok(true)
+            },
+            Ok(Some(Event::Exited)) => {
+                self.exited = true;
+                self.send_event("exited", json!({ "exitCode": 0 }))?;
+                self.send_event("terminated", json!({}))?;
+                Ok(true)
+            },
+            Ok(None) => Ok(false),
+            Err(e) => {
+                self.stopped("exception", Some(&e.to_string()))?;
+                Ok(true)
+            },
+        }
+    }
+
+    // Runs until `done` says to stop, which it's asked after each instruction along with
+    // the address that instruction would have fallen through to, or until something else
+    // stops the program first
+    fn run_until<F: FnMut(&Self, usize) -> bool>(&mut self, reason: &str, mut done: F) -> Result<()> {
+        let mut executed = 0;
+        loop {
+            let ip = self.program()?.ip();
+            let next = ip + self.program()?.decode(ip).map_or(0, |i| i.length());
+            if self.execute_one()? { return Ok(()) }
+            let ip = self.program()?.ip();
+            if done(self, next) { return self.stopped(reason, None) }
+            if self.is_breakpoint(ip) { return self.stopped("breakpoint", None) }
+            executed += 1;
+            if executed % PAUSE_POLL == 0 && self.pause_requested()? {
+                return self.stopped("pause", None)
+            }
+        }
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.run_until("breakpoint", |_, _| false)
+    }
+
+    fn step_in(&mut self) -> Result<()> {
+        if !self.execute_one()? { self.stopped("step", None)?; }
+        Ok(())
+    }
+
+    // Runs to the next source line, running any calls made on the way until they return.
+    // A call is a jump taken with its return address just pushed at rb - 1. Without a
+    // source every instruction is its own line.
+    fn next(&mut self) -> Result<()> {
+        let ip = self.program()?.ip();
+        let (line, rb) = (self.source_line(ip), self.program()?.relative_base());
+        let mut return_to = None;
+        self.run_until("step", |session, fallthrough| {
+            let program = session.program.as_ref().unwrap();
+            let (ip, top) = (program.ip(), program.relative_base());
+            match return_to {
+                Some(address) if ip != address || top > rb => return false,
+                Some(_) => return_to = None,
+                None if ip != fallthrough && top >= 1 && program.load_position(top as usize - 1) == fallthrough as i64 => {
+                    return_to = Some(fallthrough);
+                    return false
+                },
+                None => (),
+            }
+            line.is_none() || session.source_line(ip) != line
+        })
+    }
+
+    fn step_out(&mut self) -> Result<()> {
+        let rb = self.program()?.relative_base();
+        let mut returning = false;
+        self.run_until("step", |session, fallthrough| {
+            let program = session.program.as_ref().unwrap();
+            returning |= program.relative_base() < rb;
+            returning && program.ip() != fallthrough
+        })
+    }
+
+    fn handle(&mut self, command: &str, args: &Value) -> Result<(Value, After)> {
+        Ok(match command {
+            "initialize" => (json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsInstructionBreakpoints": true,
+                "supportsTerminateRequest": true,
+            }), After::Nothing),
+            // Breakpoints need the program loaded, so configuration starts after launch
+            "launch" => (self.launch(args)?, After::Initialized),
+            "setBreakpoints" => (self.set_breakpoints(args)?, After::Nothing),
+            "setInstructionBreakpoints" => (self.set_instruction_breakpoints(args)?, After::Nothing),
+            "setExceptionBreakpoints" => (json!({}), After::Nothing),
+            "configurationDone" => (json!({}), After::ConfigurationDone),
+            "threads" => (json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] }), After::Nothing),
+            "stackTrace" => (self.stack_trace()?, After::Nothing),
+            "scopes" => (json!({ "scopes": [
+                { "name": "Registers", "variablesReference": REGISTERS_REFERENCE, "expensive": false },
+                { "name": "Memory", "variablesReference": MEMORY_REFERENCE, "expensive": false },
+            ] }), After::Nothing),
+            "variables" => (self.variables(args)?, After::Nothing),
+            "evaluate" => (self.evaluate(args)?, After::Nothing),
+            "continue" => (json!({ "allThreadsContinued": true }), After::Continue),
+            "next" => (json!({}), After::Next),
+            "stepIn" => (json!({}), After::StepIn),
+            "stepOut" => (json!({}), After::StepOut),
+            // A pause while running is picked up between instructions, so the program has already stopped
+            "pause" => (json!({}), After::Nothing),
+            "disconnect" | "terminate" => (json!({}), After::Disconnect),
+            _ => return Err(From::from(format!("Unsupported request: {}", command))),
+        })
+    }
+}
+
+// Serves a debugging session until the client disconnects or closes the stream
+pub fn serve<R: BufRead + Send + 'static, W: Write>(mut reader: R, writer: W) -> Result<()> {
+    let (sender, incoming) = mpsc::channel();
+    thread::spawn(move || {
+        loop {
+            let message = match read_message(&mut reader) {
+                Ok(Some(message)) => Ok(message),
+                Ok(None) => break,
+                Err(e) => Err(e.to_string()),
+            };
+            let failed = message.is_err();
+            if sender.send(message).is_err() || failed { break }
+        }
+    });
+
+    let mut session = Session{
+        writer,
+        seq: 0,
+        incoming,
+        pending: VecDeque::new(),
+        program: None,
+        source: None,
+        line_breakpoints: BTreeSet::new(),
+        instruction_breakpoints: BTreeSet::new(),
+        stop_on_entry: false,
+        exited: false,
+    };
+
+    while let Some(request) = session.next_request()? {
+        if request["type"] != "request" { continue }
+        let command = request["command"].as_str().unwrap_or("").to_string();
+        let args = request.get("arguments").cloned().unwrap_or(Value::Null);
+
+        let after = match session.handle(&command, &args) {
+            Ok((body, after)) => {
+                session.respond(&request, Ok(body))?;
+                after
+            },
+            Err(e) => {
+                session.respond(&request, Err(e.to_string()))?;
+                After::Nothing
+            },
+        };
+
+        match after {
+            After::Nothing => (),
+            After::Initialized => session.send_event("initialized", json!({}))?,
+            After::ConfigurationDone if session.stop_on_entry => session.stopped("entry", None)?,
+            After::ConfigurationDone | After::Continue => {
+                if session.exited {
+                    session.send_event("terminated", json!({}))?;
+                } else if session.program.is_some() {
+                    session.resume()?;
+                }
+            },
+            After::Next | After::StepIn | After::StepOut if session.exited || session.program.is_none() => (),
+            After::Next => session.next()?,
+            After::StepIn => session.step_in()?,
+            After::StepOut => session.step_out()?,
+            After::Disconnect => break,
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,422 @@
+// Lifts an Intcode image to C-like pseudo-code.
+//
+// The control-flow graph is split into functions at recognised calls (a jump
+// preceded by a store of its own return address), and each function is
+// structured into while and do-while loops and if/else using its back edges and
+// immediate post-dominators. Anything that doesn't fit those shapes falls back to gotos,
+// so the output is always a faithful, if not always pretty, rendering.
+//
+// Position-mode cells become globals named after their address (`v1024`),
+// or `mem[1024]` if the address holds code. Negative addresses, which fault
+// when the access runs, are left as `mem[-5]` too. `rb[n]` is the cell at the
+// relative base plus n.
+
+use super::cfg::{self, BasicBlock, Condition, ControlFlowGraph, Exit};
+use super::program::{IntcodeInstruction, IntcodeProgram, Parameter, ParameterMode};
+use std::collections::{BTreeMap, BTreeSet};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+enum Line {
+    Text(usize, String),
+    Label(usize),
+}
+
+struct Loop {
+    header: usize,
+    body: BTreeSet<usize>,
+    follow: Option<usize>,
+}
+
+struct Function<'a> {
+    cfg: &'a ControlFlowGraph,
+    names: &'a Names,
+    nodes: BTreeSet<usize>,
+    ipdom: BTreeMap<usize, usize>,
+    loops: BTreeMap<usize, Loop>,
+    emitted: BTreeSet<usize>,
+    gotos: BTreeSet<usize>,
+    lines: Vec<Line>,
+}
+
+struct Names {
+    code: BTreeSet<usize>,
+    globals: BTreeMap<usize, i64>,
+}
+
+impl Names {
+    fn operand(&self, p: &Parameter) -> String {
+        match p.mode {
+            ParameterMode::Immediate => p.param.to_string(),
+            ParameterMode::Position if p.param < 0 || self.code.contains(&(p.param as usize)) => format!("mem[{}]", p.param),
+            ParameterMode::Position => format!("v{}", p.param),
+            ParameterMode::Relative => format!("rb[{}]", p.param),
+        }
+    }
+
+    fn condition(&self, condition: &Condition, negate: bool) -> String {
+        let value = self.operand(&condition.predicate);
+        if condition.nonzero != negate { value } else { format!("!{}", value) }
+    }
+
+    fn function(&self, address: usize) -> String {
+        if address == 0 { "main".to_owned() } else { format!("f_{}", address) }
+    }
+
+    fn statement(&self, instruction: &IntcodeInstruction) -> Option<String> {
+        let imm = |p: &Parameter, v: i64| p.mode == ParameterMode::Immediate && p.param == v;
+        let both_imm = |a: &Parameter, b: &Parameter| a.mode == ParameterMode::Immediate && b.mode == ParameterMode::Immediate;
+        // Constants are only folded or negated where the result fits, otherwise they're left symbolic
+        let negative = |p: &Parameter| Some(p.param).filter(|v| p.mode == ParameterMode::Immediate && *v < 0).and_then(i64::checked_neg);
+        let (dest, value) = match instruction {
+            IntcodeInstruction::Add{o1, o2, dest} => (dest, match o1.param.checked_add(o2.param).filter(|_| both_imm(o1, o2)) {
+                Some(sum) => sum.to_string(),
+                None if imm(o2, 0) => self.operand(o1),
+                None if imm(o1, 0) => self.operand(o2),
+                None => match negative(o2) {
+                    Some(subtrahend) => format!("{} - {}", self.operand(o1), subtrahend),
+                    None => format!("{} + {}", self.operand(o1), self.operand(o2)),
+                },
+            }),
+            IntcodeInstruction::Mul{o1, o2, dest} => (dest, match o1.param.checked_mul(o2.param).filter(|_| both_imm(o1, o2)) {
+                Some(product) => product.to_string(),
+                None if imm(o2, 1) || imm(o1, 1) => self.operand(if imm(o2, 1) { o1 } else { o2 }),
+                None if imm(o2, -1) || imm(o1, -1) => format!("-{}", self.operand(if imm(o2, -1) { o1 } else { o2 })),
+                None => format!("{} * {}", self.operand(o1), self.operand(o2)),
+            }),
+            IntcodeInstruction::LessThan{o1, o2, dest} => (dest, format!("{} < {}", self.operand(o1), self.operand(o2))),
+            IntcodeInstruction::Equals{o1, o2, dest} => (dest, format!("{} == {}", self.operand(o1), self.operand(o2))),
+            IntcodeInstruction::LoadInput{dest} => (dest, "input()".to_owned()),
+            IntcodeInstruction::Output{val} => return Some(format!("output({});", self.operand(val))),
+            IntcodeInstruction::AdjustRelativeBase{val} => return Some(match negative(val) {
+                Some(decrement) => format!("rb -= {};", decrement),
+                None => format!("rb += {};", self.operand(val)),
+            }),
+            IntcodeInstruction::JumpIfTrue{..} | IntcodeInstruction::JumpIfFalse{..} | IntcodeInstruction::Exit => return None,
+        };
+        Some(format!("{} = {};", self.operand(dest), value))
+    }
+}
+
+// Successors within the function: calls continue at their return address
+fn local_successors(block: &BasicBlock) -> Vec<usize> {
+    match block.exit {
+        Exit::Call{ return_to, .. } => vec![return_to],
+        _ => block.successors(),
+    }
+}
+
+impl<'a> Function<'a> {
+    fn new(cfg: &'a ControlFlowGraph, names: &'a Names, entry: usize) -> Function<'a> {
+        let mut nodes = BTreeSet::new();
+        let mut stack = vec![entry];
+        while let Some(node) = stack.pop() {
+            if !cfg.blocks.contains_key(&node) || !nodes.insert(node) { continue }
+            stack.extend(local_successors(&cfg.blocks[&node]));
+        }
+
+        let mut function = Function{
+            cfg, names, nodes, ipdom: BTreeMap::new(), loops: BTreeMap::new(),
+            emitted: BTreeSet::new(), gotos: BTreeSet::new(), lines: vec![],
+        };
+        function.compute_ipdom();
+        function.find_loops(entry);
+        function
+    }
+
+    fn successors(&self, node: usize) -> Vec<usize> {
+        local_successors(&self.cfg.blocks[&node]).into_iter().filter(|s| self.nodes.contains(s)).collect()
+    }
+
+    // Post-dominator sets by iteration to a fixed point. None stands for "every node",
+    // which is what nodes that never reach an exit are left with.
+    fn compute_ipdom(&mut self) {
+        let mut pdom: BTreeMap<usize, Option<BTreeSet<usize>>> = self.nodes.iter().map(|n| (*n, None)).collect();
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for &node in self.nodes.iter().rev() {
+                let mut set: Option<BTreeSet<usize>> = if self.successors(node).is_empty() { Some(BTreeSet::new()) } else { None };
+                for succ in self.successors(node) {
+                    set = match (set, &pdom[&succ]) {
+                        (None, other) => other.clone(),
+                        (Some(s), None) => Some(s),
+                        (Some(s), Some(other)) => Some(s.intersection(other).cloned().collect()),
+                    };
+                }
+                let set = set.map(|mut s| { s.insert(node); s });
+                if set != pdom[&node] {
+                    pdom.insert(node, set);
+                    changed = true;
+                }
+            }
+        }
+
+        for (&node, set) in &pdom {
+            if let Some(set) = set {
+                let strict: Vec<usize> = set.iter().cloned().filter(|n| *n != node).collect();
+                if let Some(&ipdom) = strict.iter().find(|d| {
+                    pdom[d].as_ref().is_some_and(|s| s.len() == strict.len())
+                }) {
+                    self.ipdom.insert(node, ipdom);
+                }
+            }
+        }
+    }
+
+    fn find_loops(&mut self, entry: usize) {
+        // Depth-first search for back edges, i.e. edges to a node still on the stack
+        let mut back_edges: Vec<(usize, usize)> = vec![];
+        let mut visited = BTreeSet::new();
+        let mut on_stack = BTreeSet::new();
+        let mut stack: Vec<(usize, usize)> = vec![(entry, 0)];
+        visited.insert(entry);
+        on_stack.insert(entry);
+        while let Some((node, idx)) = stack.pop() {
+            let successors = self.successors(node);
+            if idx == successors.len() {
+                on_stack.remove(&node);
+                continue;
+            }
+            stack.push((node, idx + 1));
+            let succ = successors[idx];
+            if on_stack.contains(&succ) {
+                back_edges.push((node, succ));
+            } else if visited.insert(succ) {
+                on_stack.insert(succ);
+                stack.push((succ, 0));
+            }
+        }
+
+        for (tail, header) in back_edges {
+            // The natural loop: everything that reaches the back edge without passing the header
+            let mut body = self.loops.remove(&header).map_or_else(BTreeSet::new, |l| l.body);
+            body.insert(header);
+            let mut work = vec![tail];
+            while let Some(node) = work.pop() {
+                if !body.insert(node) { continue }
+                work.extend(self.nodes.iter().cloned().filter(|p| self.successors(*p).contains(&node)));
+            }
+            self.loops.insert(header, Loop{ header, body, follow: None });
+        }
+
+        let (cfg, nodes, ipdom) = (self.cfg, &self.nodes, &self.ipdom);
+        for l in self.loops.values_mut() {
+            let exits: BTreeSet<usize> = l.body.iter()
+                .flat_map(|n| local_successors(&cfg.blocks[n]))
+                .filter(|s| !l.body.contains(s) && nodes.contains(s)).collect();
+            l.follow = match ipdom.get(&l.header) {
+                Some(ipdom) if exits.contains(ipdom) => Some(*ipdom),
+                _ => exits.iter().next().cloned(),
+            };
+        }
+    }
+
+    fn line(&mut self, indent: usize, text: String) {
+        self.lines.push(Line::Text(indent, text));
+    }
+
+    // Emits the sequence of blocks starting at `node`, stopping when `stop` is reached
+    fn emit_sequence(&mut self, node: usize, stop: Option<usize>, current: Option<usize>, indent: usize) {
+        let mut next = Some(node);
+        while let Some(node) = next {
+            if Some(node) == stop { return }
+            if let Some(l) = current.map(|h| &self.loops[&h]) {
+                if node == l.header { return self.line(indent, "continue;".to_owned()) }
+                if Some(node) == l.follow { return self.line(indent, "break;".to_owned()) }
+            }
+            if self.emitted.contains(&node) || !self.nodes.contains(&node) {
+                self.gotos.insert(node);
+                return self.line(indent, format!("goto L_{};", node));
+            }
+
+            next = if self.loops.contains_key(&node) && current != Some(node) {
+                self.emit_loop(node, indent)
+            } else {
+                self.emit_block(node, stop, current, indent)
+            };
+        }
+    }
+
+    fn emit_loop(&mut self, header: usize, indent: usize) -> Option<usize> {
+        let follow = self.loops[&header].follow;
+        let block = &self.cfg.blocks[&header];
+
+        // A header that only tests the loop condition becomes a while condition
+        if let (1, Exit::Branch{ condition, target, fallthrough }) = (block.instructions.len(), &block.exit) {
+            if Some(*target) == follow || Some(*fallthrough) == follow {
+                let (inner, negate) = if Some(*target) == follow { (*fallthrough, true) } else { (*target, false) };
+                let text = format!("while ({}) {{", self.names.condition(condition, negate));
+                self.lines.push(Line::Label(header));
+                self.line(indent, text);
+                self.emitted.insert(header);
+                self.emit_sequence(inner, None, Some(header), indent + 1);
+                self.close_loop(indent);
+                return follow;
+            }
+        }
+
+        // A loop only left from the block jumping back to the header tests its condition at the bottom
+        if let Some((latch, condition, negate)) = self.bottom_test(header, follow) {
+            self.line(indent, "do {".to_owned());
+            if latch != header {
+                let next = self.emit_block(header, Some(latch), Some(header), indent + 1);
+                if let Some(next) = next { self.emit_sequence(next, Some(latch), Some(header), indent + 1); }
+            }
+            self.emitted.insert(latch);
+            self.lines.push(Line::Label(latch));
+            self.emit_statements(latch, indent + 1);
+            let text = format!("}} while ({});", self.names.condition(&condition, negate));
+            self.line(indent, text);
+            return follow;
+        }
+
+        self.line(indent, "while (1) {".to_owned());
+        let next = self.emit_block(header, None, Some(header), indent + 1);
+        if let Some(next) = next { self.emit_sequence(next, None, Some(header), indent + 1); }
+        self.close_loop(indent);
+        follow
+    }
+
+    // The block ending the loop with a branch back to the header or out to the follow, along
+    // with how to render the condition for going round again, if no other block leaves the loop
+    fn bottom_test(&self, header: usize, follow: Option<usize>) -> Option<(usize, Condition, bool)> {
+        let body = &self.loops[&header].body;
+        let leaves = |node: &usize| {
+            let block = &self.cfg.blocks[node];
+            let successors = local_successors(block);
+            successors.is_empty() || matches!(block.exit, Exit::Indirect{..}) || successors.iter().any(|s| !body.contains(s))
+        };
+        let latches: Vec<usize> = body.iter().cloned().filter(|n| local_successors(&self.cfg.blocks[n]).contains(&header)).collect();
+        let exits: Vec<usize> = body.iter().cloned().filter(leaves).collect();
+        if latches.len() != 1 || exits != latches { return None }
+        match &self.cfg.blocks[&latches[0]].exit {
+            Exit::Branch{ condition, target, fallthrough } if *target == header && Some(*fallthrough) == follow => {
+                Some((latches[0], condition.clone(), false))
+            },
+            Exit::Branch{ condition, target, fallthrough } if *fallthrough == header && Some(*target) == follow => {
+                Some((latches[0], condition.clone(), true))
+            },
+            _ => None,
+        }
+    }
+
+    fn close_loop(&mut self, indent: usize) {
+        if let Some(Line::Text(i, text)) = self.lines.last() {
+            if *i == indent + 1 && text == "continue;" { self.lines.pop(); }
+        }
+        self.line(indent, "}".to_owned());
+    }
+
+    // Emits the statements of a block, leaving out the jump ending it
+    fn emit_statements(&mut self, node: usize, indent: usize) {
+        let names = self.names;
+        for (_, instruction) in &self.cfg.blocks[&node].instructions {
+            if let Some(statement) = names.statement(instruction) { self.line(indent, statement); }
+        }
+    }
+
+    // Emits a single block and returns the node that follows it in sequence, if any
+    fn emit_block(&mut self, node: usize, stop: Option<usize>, current: Option<usize>, indent: usize) -> Option<usize> {
+        self.emitted.insert(node);
+        self.lines.push(Line::Label(node));
+        self.emit_statements(node, indent);
+        let names = self.names;
+
+        match self.cfg.blocks[&node].exit.clone() {
+            Exit::Fallthrough(next) | Exit::Jump(next) => Some(next),
+            Exit::Call{ target, return_to } => {
+                self.line(indent, format!("{}();", names.function(target)));
+                Some(return_to)
+            },
+            Exit::Return => { self.line(indent, "return;".to_owned()); None },
+            Exit::Halt => { self.line(indent, "halt();".to_owned()); None },
+            Exit::Invalid(address) => {
+                self.line(indent, format!("trap(); // invalid instruction at {}", address));
+                None
+            },
+            Exit::Indirect{ condition: None, target, .. } => {
+                self.line(indent, format!("goto *{};", names.operand(&target)));
+                None
+            },
+            Exit::Indirect{ condition: Some(condition), target, fallthrough } => {
+                self.line(indent, format!("if ({}) goto *{};", names.condition(&condition, false), names.operand(&target)));
+                fallthrough
+            },
+            Exit::Branch{ condition, target, fallthrough } => {
+                let merge = self.ipdom.get(&node).cloned().filter(|m| Some(*m) != stop).or(stop);
+                if Some(target) == merge {
+                    self.line(indent, format!("if ({}) {{", names.condition(&condition, true)));
+                    self.emit_sequence(fallthrough, merge, current, indent + 1);
+                } else if Some(fallthrough) == merge {
+                    self.line(indent, format!("if ({}) {{", names.condition(&condition, false)));
+                    self.emit_sequence(target, merge, current, indent + 1);
+                } else {
+                    self.line(indent, format!("if ({}) {{", names.condition(&condition, false)));
+                    self.emit_sequence(target, merge, current, indent + 1);
+                    self.line(indent, "} else {".to_owned());
+                    self.emit_sequence(fallthrough, merge, current, indent + 1);
+                }
+                self.line(indent, "}".to_owned());
+                merge.filter(|m| Some(*m) != stop)
+            },
+        }
+    }
+
+    fn render(&self, out: &mut String) {
+        for line in &self.lines {
+            match line {
+                Line::Text(indent, text) => out.push_str(&format!("{}{}\n", "    ".repeat(*indent), text)),
+                Line::Label(node) if self.gotos.contains(node) => out.push_str(&format!("L_{}:\n", node)),
+                Line::Label(_) => (),
+            }
+        }
+    }
+}
+
+// Decompiles the code reachable from address 0 of the image into pseudo-code
+pub fn decompile(image: &[i64]) -> Result<String> {
+    let program = IntcodeProgram::from_memory(image.to_vec());
+    let cfg = cfg::build(&program, &[]);
+
+    let code: BTreeSet<usize> = cfg.blocks.values()
+        .flat_map(|b| b.instructions.iter().flat_map(|(a, i)| *a..(*a + i.length())))
+        .collect();
+    let mut names = Names{ code, globals: BTreeMap::new() };
+    for block in cfg.blocks.values() {
+        for (_, instruction) in &block.instructions {
+            for p in parameters(instruction) {
+                if p.mode == ParameterMode::Position && p.param >= 0 && !names.code.contains(&(p.param as usize)) {
+                    names.globals.insert(p.param as usize, program.load_position(p.param as usize));
+                }
+            }
+        }
+    }
+
+    let mut out = String::new();
+    out.push_str("// rb is the relative base; rb[n] is the cell at rb + n\n");
+    for (address, value) in &names.globals {
+        out.push_str(&format!("int v{} = {};\n", address, value));
+    }
+
+    for &entry in &cfg.functions {
+        if !cfg.blocks.contains_key(&entry) { continue }
+        let mut function = Function::new(&cfg, &names, entry);
+        function.emit_sequence(entry, None, None, 1);
+        out.push_str(&format!("\nvoid {}() {{\n", names.function(entry)));
+        function.render(&mut out);
+        out.push_str("}\n");
+    }
+    Ok(out)
+}
+
+fn parameters(instruction: &IntcodeInstruction) -> Vec<&Parameter> {
+    match instruction {
+        IntcodeInstruction::Add{o1, o2, dest} | IntcodeInstruction::Mul{o1, o2, dest} |
+        IntcodeInstruction::LessThan{o1, o2, dest} | IntcodeInstruction::Equals{o1, o2, dest} => vec![o1, o2, dest],
+        IntcodeInstruction::JumpIfTrue{predicate, target} | IntcodeInstruction::JumpIfFalse{predicate, target} => vec![predicate, target],
+        IntcodeInstruction::LoadInput{dest} => vec![dest],
+        IntcodeInstruction::Output{val} | IntcodeInstruction::AdjustRelativeBase{val} => vec![val],
+        IntcodeInstruction::Exit => vec![],
+    }
+}
@@ -0,0 +1,88 @@
+use super::program::{Event, IntcodeProgram};
+use super::stats::Stats;
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Strategy {
+    BreadthFirst,
+    DepthFirst,
+}
+
+// A state reached during exploration, along with the inputs that reached it
+#[derive(Clone, Debug)]
+pub struct Visit<S> {
+    pub state: S,
+    pub depth: usize,
+    pub path: Vec<i64>,
+    pub exited: bool,
+}
+
+// Runs the machine until it needs input or exits, collecting any outputs on the way
+fn run_to_input(program: &mut IntcodeProgram, outputs: &mut Vec<i64>) -> Result<Event> {
+    loop {
+        match program.execute_until_event()? {
+            Event::ProducedOutput => outputs.extend(program.get_output()),
+            event => return Ok(event),
+        }
+    }
+}
+
+// Explores every machine state reachable from `program` by feeding it each of
+// `inputs` whenever it asks for one. Rather than undoing moves, the machine is
+// forked at each input request, so each branch runs on its own copy.
+//
+// After a branch has consumed its input and run up to the next input request
+// (or exit), `classify` is given the parent state, the input and the outputs
+// produced, and returns the child state or None to prune the branch. States
+// whose `key` has been seen before are not expanded again. Returns every
+// visited state in the order it was discovered, starting with `root`.
+pub fn explore<S, K, C, F>(program: &IntcodeProgram, root: S, inputs: &[i64], strategy: Strategy,
+                           classify: C, key: F) -> Result<Vec<Visit<S>>>
+    where C: FnMut(&S, i64, &[i64]) -> Option<S>, F: Fn(&S) -> K, K: Hash + Eq {
+    explore_with_stats(program, root, inputs, strategy, classify, key, &mut Stats::new())
+}
+
+// Like explore, adding the statistics of every forked machine's run to `stats`
+pub fn explore_with_stats<S, K, C, F>(program: &IntcodeProgram, root: S, inputs: &[i64], strategy: Strategy,
+                                      mut classify: C, key: F, stats: &mut Stats) -> Result<Vec<Visit<S>>>
+    where C: FnMut(&S, i64, &[i64]) -> Option<S>, F: Fn(&S) -> K, K: Hash + Eq {
+    let mut outputs = vec![];
+    let mut root_machine = program.fork();
+    let exited = run_to_input(&mut root_machine, &mut outputs)? == Event::Exited;
+    stats.merge(&root_machine.stats());
+
+    let mut seen: HashSet<K> = HashSet::new();
+    seen.insert(key(&root));
+    let mut visits = vec![Visit{ state: root, depth: 0, path: vec![], exited }];
+    let mut frontier: VecDeque<(usize, IntcodeProgram)> = VecDeque::new();
+    if !exited { frontier.push_back((0, root_machine)); }
+
+    while let Some((parent, machine)) = match strategy {
+        Strategy::BreadthFirst => frontier.pop_front(),
+        Strategy::DepthFirst => frontier.pop_back(),
+    } {
+        for &input in inputs {
+            let mut child = machine.fork();
+            child.give_input(input);
+            outputs.clear();
+            let exited = run_to_input(&mut child, &mut outputs)? == Event::Exited;
+            stats.merge(&child.stats());
+
+            let state = match classify(&visits[parent].state, input, &outputs) {
+                Some(state) => state,
+                None => continue,
+            };
+            if !seen.insert(key(&state)) { continue }
+
+            let mut path = visits[parent].path.clone();
+            path.push(input);
+            visits.push(Visit{ state, depth: visits[parent].depth + 1, path, exited });
+            if !exited { frontier.push_back((visits.len() - 1, child)); }
+        }
+    }
+
+    Ok(visits)
+}
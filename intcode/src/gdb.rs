@@ -0,0 +1,404 @@
+// A GDB remote serial protocol stub, so programs can be debugged from gdb or another
+// front end speaking the protocol (`target remote localhost:1234`).
+//
+// Memory is presented as bytes with each cell taking eight little-endian bytes, so cell n
+// lives at address 8 * n. There are two 64-bit registers, ip (the pc) and rb, which hold
+// byte addresses in the same space. Stop replies use SIGTRAP after a step or breakpoint,
+// SIGTTIN when the program needs input it hasn't been given, SIGILL on a bad instruction
+// (whose error is sent to the console) and SIGINT when interrupted while running. Input
+// can be queued with `monitor input 1 2 3`, and outputs are sent to the console as
+// they're produced.
+
+use super::program::{Event, IntcodeProgram};
+use std::collections::BTreeSet;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+const CELL_BYTES: usize = 8;
+const MAX_PACKET: usize = 0x4000;
+// Instructions run between checks for an interrupt while continuing
+const INTERRUPT_POLL: usize = 10000;
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const SIGILL: u8 = 4;
+const SIGTTIN: u8 = 21;
+
+const TARGET_XML: &str = "<?xml version=\"1.0\"?>\
+<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\
+<target version=\"1.0\"><feature name=\"org.intcode.core\">\
+<reg name=\"ip\" bitsize=\"64\" type=\"code_ptr\" regnum=\"0\"/>\
+<reg name=\"rb\" bitsize=\"64\" type=\"int64\" regnum=\"1\"/>\
+</feature></target>";
+
+// A connection to the debugger, which can be switched to non-blocking to look for an interrupt
+pub trait Stream: Read + Write {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()>;
+}
+
+impl Stream for TcpStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> { TcpStream::set_nonblocking(self, nonblocking) }
+}
+
+impl Stream for UnixStream {
+    fn set_nonblocking(&self, nonblocking: bool) -> io::Result<()> { UnixStream::set_nonblocking(self, nonblocking) }
+}
+
+struct Connection<S: Stream> {
+    stream: S,
+    ack: bool,
+}
+
+struct Stub<'a, S: Stream> {
+    connection: Connection<S>,
+    program: &'a mut IntcodeProgram,
+    breakpoints: BTreeSet<usize>,
+    exited: bool,
+}
+
+fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b))
+}
+
+fn hex_encode(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn hex_decode(hex: &str) -> Result<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return Err(From::from(format!("Odd length hex string: {}", hex)));
+    }
+    (0..hex.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).map_err(|_| From::from(format!("Invalid hex: {}", hex))))
+        .collect()
+}
+
+fn parse_hex(hex: &str) -> Result<usize> {
+    usize::from_str_radix(hex, 16).map_err(|_| From::from(format!("Invalid hex number: {}", hex)))
+}
+
+// Registers are sent as little-endian byte strings
+fn encode_register(value: i64) -> String {
+    hex_encode(&value.to_le_bytes())
+}
+
+fn decode_register(hex: &str) -> Result<i64> {
+    let bytes = hex_decode(hex)?;
+    if bytes.len() != CELL_BYTES {
+        return Err(From::from(format!("Register value should be {} bytes: {}", CELL_BYTES, hex)));
+    }
+    let mut raw = [0u8; CELL_BYTES];
+    raw.copy_from_slice(&bytes);
+    Ok(i64::from_le_bytes(raw))
+}
+
+impl<S: Stream> Connection<S> {
+    fn read_byte(&mut self) -> Result<Option<u8>> {
+        let mut byte = [0u8];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    // Returns the next packet's contents, or None once the client disconnects.
+    // An interrupt (ctrl-c) is returned as a packet holding just 0x03.
+    fn read_packet(&mut self) -> Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(0x03) => return Ok(Some(String::from("\x03"))),
+                Some(b'$') => (),
+                Some(_) => continue, // Acks and noise between packets
+            }
+
+            let mut data = vec![];
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(_) if data.len() == MAX_PACKET => {
+                        return Err(From::from(format!("Packet is longer than the limit of {} bytes", MAX_PACKET)));
+                    },
+                    Some(b) => data.push(b),
+                }
+            }
+            let mut sum = [0u8; 2];
+            for digit in sum.iter_mut() {
+                *digit = match self.read_byte()? {
+                    Some(b) => b,
+                    None => return Ok(None),
+                };
+            }
+
+            let valid = std::str::from_utf8(&sum).ok()
+                .and_then(|sum| u8::from_str_radix(sum, 16).ok())
+                .is_some_and(|sum| sum == checksum(&data));
+            if !self.ack {
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            if valid {
+                self.stream.write_all(b"+")?;
+                return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+            }
+            self.stream.write_all(b"-")?;
+        }
+    }
+
+    // Checks for an interrupt without waiting for one. The debugger sends nothing else
+    // while the program runs, so any other byte is dropped, and a disconnect stops it too.
+    fn interrupted(&mut self) -> Result<bool> {
+        let mut byte = [0u8];
+        self.stream.set_nonblocking(true)?;
+        let read = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+        match read {
+            Ok(0) => Ok(true),
+            Ok(_) => Ok(byte[0] == 0x03),
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(From::from(e)),
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) -> Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum(data.as_bytes()));
+        self.stream.write_all(packet.as_bytes())?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    // Writes text to the debugger's console
+    fn send_console(&mut self, text: &str) -> Result<()> {
+        self.send_packet(&format!("O{}", hex_encode(text.as_bytes())))
+    }
+}
+
+impl<'a, S: Stream> Stub<'a, S> {
+    fn stop_reply(&self, signal: u8) -> String {
+        if self.exited { String::from("W00") } else { format!("S{:02x}", signal) }
+    }
+
+    // Executes one instruction, returning the signal to stop with if it didn't simply move on
+    fn step(&mut self) -> Result<Option<u8>> {
+        match self.program.step() {
+            Ok(Some(Event::ProducedOutput)) => {
+                let output = self.program.get_output().unwrap_or_default();
+                self.connection.send_console(&format!("output: {}\n", output))?;
+                Ok(None)
+            },
+            Ok(Some(Event::InputRequired)) | Ok(Some(Event::PeerClosed)) => Ok(Some(SIGTTIN)),
+            Ok(Some(Event::Exited)) => {
+                self.exited = true;
+                Ok(Some(SIGTRAP))
+            },
+            Ok(None) => Ok(None),
+            Err(e) => {
+                self.connection.send_console(&format!("error: {}\n", e))?;
+                Ok(Some(SIGILL))
+            },
+        }
+    }
+
+    fn resume(&mut self) -> Result<String> {
+        let mut executed = 0;
+        loop {
+            if let Some(signal) = self.step()? { return Ok(self.stop_reply(signal)) }
+            if self.breakpoints.contains(&self.program.ip()) { return Ok(self.stop_reply(SIGTRAP)) }
+            executed += 1;
+            if executed % INTERRUPT_POLL == 0 && self.connection.interrupted()? { return Ok(self.stop_reply(SIGINT)) }
+        }
+    }
+
+    fn single_step(&mut self) -> Result<String> {
+        let signal = self.step()?.unwrap_or(SIGTRAP);
+        Ok(self.stop_reply(signal))
+    }
+
+    fn read_register(&self, register: usize) -> Option<i64> {
+        match register {
+            0 => Some((self.program.ip() * CELL_BYTES) as i64),
+            1 => Some(self.program.relative_base() * CELL_BYTES as i64),
+            _ => None,
+        }
+    }
+
+    fn write_register(&mut self, register: usize, value: i64) -> Result<()> {
+        if value % CELL_BYTES as i64 != 0 {
+            return Err(From::from(format!("Register value {} isn't cell aligned", value)));
+        }
+        match register {
+            0 if value >= 0 => self.program.set_ip(value as usize / CELL_BYTES)?,
+            1 => self.program.set_relative_base(value / CELL_BYTES as i64)?,
+            _ => return Err(From::from(format!("Can't set register {} to {}", register, value))),
+        }
+        Ok(())
+    }
+
+    // None if the range runs off the end of the address space
+    fn read_memory(&self, address: usize, length: usize) -> Option<String> {
+        let bytes: Vec<u8> = (address..address.checked_add(length)?)
+            .map(|a| self.program.load_position(a / CELL_BYTES).to_le_bytes()[a % CELL_BYTES])
+            .collect();
+        Some(hex_encode(&bytes))
+    }
+
+    fn write_memory(&mut self, address: usize, bytes: &[u8]) -> Result<()> {
+        for (offset, byte) in bytes.iter().enumerate() {
+            let a = address.checked_add(offset).ok_or("Address out of range")?;
+            let mut cell = self.program.read(a / CELL_BYTES)?.to_le_bytes();
+            cell[a % CELL_BYTES] = *byte;
+            self.program.write(a / CELL_BYTES, i64::from_le_bytes(cell))?;
+        }
+        Ok(())
+    }
+
+    fn monitor(&mut self, command: &str) -> Result<String> {
+        let mut words = command.split_whitespace();
+        match words.next() {
+            Some("input") => {
+                for word in words {
+                    self.program.give_input(word.parse::<i64>()?);
+                }
+                Ok(String::from("OK"))
+            },
+            Some("ascii") => {
+                let text = command.trim_start()["ascii".len()..].trim();
+                for c in text.chars().chain(std::iter::once('\n')) {
+                    self.program.give_input(c as i64);
+                }
+                Ok(String::from("OK"))
+            },
+            _ => {
+                self.connection.send_console("monitor commands: input <n>..., ascii <text>\n")?;
+                Ok(String::from("OK"))
+            },
+        }
+    }
+
+    fn transfer_features(&self, annex: &str, window: &str) -> Result<String> {
+        if annex != "target.xml" { return Ok(String::from("E00")) }
+        let (offset, length) = window.split_once(',').ok_or("Invalid qXfer window")?;
+        let (offset, length) = (parse_hex(offset)?, parse_hex(length)?);
+        let chunk = TARGET_XML.get(offset..).unwrap_or("");
+        if chunk.len() > length {
+            Ok(format!("m{}", &chunk[..length]))
+        } else {
+            Ok(format!("l{}", chunk))
+        }
+    }
+
+    fn breakpoint(&mut self, args: &str, insert: bool) -> Result<String> {
+        let mut parts = args.split(',');
+        let kind = parts.next().unwrap_or("");
+        if kind != "0" && kind != "1" { return Ok(String::new()) } // Watchpoints aren't supported
+        let address = parse_hex(parts.next().ok_or("Missing breakpoint address")?)?;
+        if address % CELL_BYTES != 0 { return Ok(String::from("E01")) }
+        if insert {
+            self.breakpoints.insert(address / CELL_BYTES);
+        } else {
+            self.breakpoints.remove(&(address / CELL_BYTES));
+        }
+        Ok(String::from("OK"))
+    }
+
+    // Returns the reply to a packet, or None if the session should end
+    fn handle(&mut self, packet: &str) -> Result<Option<String>> {
+        let (command, args) = packet.split_at(packet.chars().next().map_or(0, |c| c.len_utf8()));
+        let reply = match command {
+            "\x03" | "?" => self.stop_reply(SIGTRAP),
+            "g" => format!("{}{}", encode_register(self.read_register(0).unwrap()), encode_register(self.read_register(1).unwrap())),
+            "G" => {
+                if args.len() != 4 * CELL_BYTES { return Ok(Some(String::from("E01"))) }
+                let (ip, rb) = (decode_register(&args[..2 * CELL_BYTES])?, decode_register(&args[2 * CELL_BYTES..])?);
+                match self.write_register(0, ip).and_then(|_| self.write_register(1, rb)) {
+                    Ok(()) => String::from("OK"),
+                    Err(_) => String::from("E01"),
+                }
+            },
+            "p" => match self.read_register(parse_hex(args)?) {
+                Some(value) => encode_register(value),
+                None => String::from("E01"),
+            },
+            "P" => {
+                let (register, value) = args.split_once('=').ok_or("Invalid P packet")?;
+                match self.write_register(parse_hex(register)?, decode_register(value)?) {
+                    Ok(()) => String::from("OK"),
+                    Err(_) => String::from("E01"),
+                }
+            },
+            "m" => {
+                let (address, length) = args.split_once(',').ok_or("Invalid m packet")?;
+                let length = parse_hex(length)?;
+                if length > MAX_PACKET / 2 { return Ok(Some(String::from("E01"))) }
+                self.read_memory(parse_hex(address)?, length).unwrap_or_else(|| String::from("E01"))
+            },
+            "M" => {
+                let (location, data) = args.split_once(':').ok_or("Invalid M packet")?;
+                let (address, length) = location.split_once(',').ok_or("Invalid M packet")?;
+                let bytes = hex_decode(data)?;
+                if bytes.len() != parse_hex(length)? { return Ok(Some(String::from("E01"))) }
+                match self.write_memory(parse_hex(address)?, &bytes) {
+                    Ok(()) => String::from("OK"),
+                    Err(_) => String::from("E01"),
+                }
+            },
+            "c" => self.resume()?,
+            "s" => self.single_step()?,
+            "Z" => self.breakpoint(args, true)?,
+            "z" => self.breakpoint(args, false)?,
+            "H" | "T" => String::from("OK"),
+            "D" => {
+                self.connection.send_packet("OK")?;
+                return Ok(None)
+            },
+            "k" => return Ok(None),
+            _ => self.query(packet)?,
+        };
+        Ok(Some(reply))
+    }
+
+    fn query(&mut self, packet: &str) -> Result<String> {
+        if packet.starts_with("qSupported") {
+            return Ok(format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+", MAX_PACKET));
+        }
+        if let Some(rest) = packet.strip_prefix("qXfer:features:read:") {
+            let (annex, window) = rest.split_once(':').ok_or("Invalid qXfer packet")?;
+            return self.transfer_features(annex, window);
+        }
+        if let Some(hex) = packet.strip_prefix("qRcmd,") {
+            let command = String::from_utf8_lossy(&hex_decode(hex)?).into_owned();
+            return self.monitor(&command);
+        }
+        Ok(match packet {
+            "QStartNoAckMode" => String::from("OK"),
+            "qAttached" => String::from("1"),
+            "qC" => String::from("QC1"),
+            "qfThreadInfo" => String::from("m1"),
+            "qsThreadInfo" => String::from("l"),
+            _ => String::new(), // Unsupported
+        })
+    }
+}
+
+// Serves a debugging session over the stream until the client detaches, kills the
+// program or disconnects
+pub fn serve<S: Stream>(program: &mut IntcodeProgram, stream: S) -> Result<()> {
+    let mut stub = Stub{
+        connection: Connection{ stream, ack: true },
+        program,
+        breakpoints: BTreeSet::new(),
+        exited: false,
+    };
+    while let Some(packet) = stub.connection.read_packet()? {
+        let reply = match stub.handle(&packet) {
+            Ok(Some(reply)) => reply,
+            Ok(None) => break,
+            Err(_) => String::from("E01"),
+        };
+        stub.connection.send_packet(&reply)?;
+        if packet == "QStartNoAckMode" { stub.connection.ack = false; }
+    }
+    Ok(())
+}
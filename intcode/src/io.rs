@@ -1,6 +1,8 @@
 use std::io::{self, prelude::*};
 use std::collections::VecDeque;
-use std::sync::mpsc::{Sender, Receiver};
+use std::sync::mpsc::{self, Sender, SyncSender, Receiver, RecvTimeoutError, TryRecvError};
+use std::thread;
+use super::watchdog::Watch;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
@@ -8,15 +10,59 @@ pub trait InputDevice {
     fn put(&mut self, output: i64);
     fn get(&mut self) -> Result<i64>;
     fn get_maybe(&mut self) -> Option<i64>;
+    // True once no more input can arrive: the peer has gone and nothing is buffered
+    fn is_closed(&self) -> bool { false }
 }
 
 pub trait OutputDevice {
     fn put(&mut self, output: i64);
     fn get(&mut self) -> Option<i64>;
+    // True once the peer receiving outputs has gone
+    fn is_closed(&self) -> bool { false }
+    // Like put, but fails if the output couldn't be delivered. Running programs output
+    // through this; devices that never fail can leave it calling put.
+    fn try_put(&mut self, output: i64) -> Result<()> {
+        self.put(output);
+        Ok(())
+    }
+}
+
+// What a channel output device does with outputs once its receiver has gone. Whatever
+// the policy, a stepping program reports a PeerClosed event when the first put fails.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ClosedPolicy {
+    // Fail try_put, which stops the program with an error (or a PeerClosed event on every
+    // retry). A plain put loses the output.
+    Error,
+    // Discard the output, letting the program carry on once PeerClosed has been reported
+    Drop,
+    // Keep the output in a local buffer, retrievable with get
+    Buffer,
 }
 
 pub struct DefaultInputDevice {
-    buffer: VecDeque<i64>
+    // Queued input is used first, then lines are read from reader (stdin if None)
+    buffer: VecDeque<i64>,
+    reader: Option<Box<dyn BufRead + Send>>,
+    writer: Box<dyn Write + Send>,
+    prompt: Option<String>,
+    // Error rather than read once the queued input is exhausted
+    strict: bool,
+    // Report the peer closed once the queued input is exhausted, rather than asking for more
+    closes_when_empty: bool,
+}
+
+// Reads from each source in turn, moving on once a source is closed
+pub struct ChainInputDevice {
+    buffer: VecDeque<i64>,
+    sources: VecDeque<Box<dyn InputDevice + Send>>,
+}
+
+// Never waits: when the inner device has nothing available the fallback value is used
+pub struct FallbackInputDevice {
+    buffer: VecDeque<i64>,
+    inner: Box<dyn InputDevice + Send>,
+    fallback: i64,
 }
 
 pub struct DefaultOutputDevice {
@@ -27,17 +73,86 @@ pub struct ChannelInputDevice {
     // Buffer is used if available, otherwise channel is
     buffer: VecDeque<i64>,
     channel: Receiver<i64>,
+    disconnected: bool,
+    watch: Option<Watch>,
+}
+
+enum ChannelSender {
+    Unbounded(Sender<i64>),
+    // Sends block while the channel is full
+    Bounded(SyncSender<i64>),
 }
 
 pub struct ChannelOutputDevice {
-    // If the output channel is closed we'll write to buffer instead
+    // If the output channel is closed we'll write to buffer instead, depending on policy
     buffer: VecDeque<i64>,
-    channel: Sender<i64>,
+    channel: ChannelSender,
+    policy: ClosedPolicy,
+    disconnected: bool,
+    watch: Option<Watch>,
 }
 
 impl DefaultInputDevice {
     pub fn new() -> Box<DefaultInputDevice> {
-        Box::new(DefaultInputDevice{ buffer: VecDeque::new() })
+        Box::new(DefaultInputDevice{
+            buffer: VecDeque::new(),
+            reader: None,
+            writer: Box::new(io::stdout()),
+            prompt: Some(String::from("Enter program input: ")),
+            strict: false,
+            closes_when_empty: false,
+        })
+    }
+
+    // Reads input lines from reader, writing any prompt to writer
+    pub fn with_io<R, W>(reader: R, writer: W) -> Box<DefaultInputDevice>
+        where R: BufRead + Send + 'static, W: Write + Send + 'static {
+        let mut device = DefaultInputDevice::new();
+        device.reader = Some(Box::new(reader));
+        device.writer = Box::new(writer);
+        device
+    }
+
+    // Only ever returns queued input, so a program asking for more fails instead of waiting
+    pub fn strict() -> Box<DefaultInputDevice> {
+        let mut device = DefaultInputDevice::new();
+        device.strict = true;
+        device
+    }
+
+    // A strict device holding just these values, which closes once they're used up
+    pub fn script(values: &[i64]) -> Box<DefaultInputDevice> {
+        let mut device = DefaultInputDevice::strict();
+        device.closes_when_empty = true;
+        for &v in values { device.put(v); }
+        device
+    }
+
+    pub fn set_prompt(&mut self, prompt: Option<&str>) {
+        self.prompt = prompt.map(String::from);
+    }
+
+    pub fn set_strict(&mut self, strict: bool) {
+        self.strict = strict;
+    }
+
+    fn read_input(&mut self) -> Result<i64> {
+        if self.strict {
+            return Err(From::from("Program requested more input than was provided"));
+        }
+        if let Some(prompt) = &self.prompt {
+            write!(self.writer, "{}", prompt)?;
+            self.writer.flush()?;
+        }
+        let mut input = String::new();
+        let read = match &mut self.reader {
+            Some(reader) => reader.read_line(&mut input)?,
+            None => io::stdin().read_line(&mut input)?,
+        };
+        if read == 0 {
+            return Err(From::from("Reached end of input"));
+        }
+        input.trim().parse::<i64>().map_err(|e| From::from(format!("Invalid input {:?}: {}", input.trim(), e)))
     }
 }
 
@@ -49,13 +164,77 @@ impl DefaultOutputDevice {
 
 impl ChannelInputDevice {
     pub fn new(channel: Receiver<i64>) -> Box<ChannelInputDevice> {
-        Box::new(ChannelInputDevice{ buffer: VecDeque::new(), channel: channel })
+        Box::new(ChannelInputDevice{ buffer: VecDeque::new(), channel, disconnected: false, watch: None })
+    }
+
+    pub(crate) fn set_watch(&mut self, watch: Watch) {
+        self.watch = Some(watch);
     }
 }
 
 impl ChannelOutputDevice {
     pub fn new(channel: Sender<i64>) -> Box<ChannelOutputDevice> {
-        Box::new(ChannelOutputDevice{ buffer: VecDeque::new(), channel: channel })
+        ChannelOutputDevice::with_policy(channel, ClosedPolicy::Buffer)
+    }
+
+    pub fn with_policy(channel: Sender<i64>, policy: ClosedPolicy) -> Box<ChannelOutputDevice> {
+        Box::new(ChannelOutputDevice{
+            buffer: VecDeque::new(), channel: ChannelSender::Unbounded(channel), policy, disconnected: false, watch: None
+        })
+    }
+
+    pub(crate) fn set_watch(&mut self, watch: Watch) {
+        self.watch = Some(watch);
+    }
+
+    pub fn bounded(channel: SyncSender<i64>, policy: ClosedPolicy) -> Box<ChannelOutputDevice> {
+        Box::new(ChannelOutputDevice{
+            buffer: VecDeque::new(), channel: ChannelSender::Bounded(channel), policy, disconnected: false, watch: None
+        })
+    }
+}
+
+impl ChainInputDevice {
+    pub fn new(sources: Vec<Box<dyn InputDevice + Send>>) -> Box<ChainInputDevice> {
+        Box::new(ChainInputDevice{ buffer: VecDeque::new(), sources: sources.into_iter().collect() })
+    }
+}
+
+impl FallbackInputDevice {
+    pub fn new(inner: Box<dyn InputDevice + Send>, fallback: i64) -> Box<FallbackInputDevice> {
+        Box::new(FallbackInputDevice{ buffer: VecDeque::new(), inner, fallback })
+    }
+}
+
+// Merges several receivers into one input device, taking values in the order they arrive.
+// Each receiver is drained by a forwarding thread, which ends once its sender has gone
+// or the device has been dropped and another value arrives.
+pub fn merge(receivers: Vec<Receiver<i64>>) -> Box<ChannelInputDevice> {
+    let (tx, rx) = mpsc::channel();
+    for receiver in receivers {
+        let tx = tx.clone();
+        thread::spawn(move || {
+            for v in receiver {
+                if tx.send(v).is_err() { break }
+            }
+        });
+    }
+    ChannelInputDevice::new(rx)
+}
+
+// Creates an output device connected to an input device, for wiring one program's
+// outputs to another's inputs. With a capacity, outputs block while that many are
+// waiting to be read; a capacity of 0 makes every output wait for its reader.
+pub fn channel_pair(capacity: Option<usize>, policy: ClosedPolicy) -> (Box<ChannelOutputDevice>, Box<ChannelInputDevice>) {
+    match capacity {
+        Some(capacity) => {
+            let (tx, rx) = mpsc::sync_channel(capacity);
+            (ChannelOutputDevice::bounded(tx, policy), ChannelInputDevice::new(rx))
+        },
+        None => {
+            let (tx, rx) = mpsc::channel();
+            (ChannelOutputDevice::with_policy(tx, policy), ChannelInputDevice::new(rx))
+        },
     }
 }
 
@@ -64,17 +243,54 @@ impl ChannelOutputDevice {
 impl InputDevice for DefaultInputDevice {
     fn put(&mut self, output: i64) { self.buffer.push_front(output) }
     fn get(&mut self) -> Result<i64> {
-        self.buffer.pop_back().map_or_else(|| {
-            print!("Enter program input: ");
-            io::stdout().flush()?;
-            let mut input = String::new();
-            io::stdin().read_line(&mut input)?;
-            Ok(input.trim().parse::<i64>()?)
-        }, |v| Ok(v))
+        match self.buffer.pop_back() {
+            Some(v) => Ok(v),
+            None => self.read_input(),
+        }
     }
     fn get_maybe(&mut self) -> Option<i64> {
         self.buffer.pop_back()
     }
+    fn is_closed(&self) -> bool { self.closes_when_empty && self.buffer.is_empty() }
+}
+
+impl InputDevice for ChainInputDevice {
+    fn put(&mut self, output: i64) { self.buffer.push_front(output) }
+    fn get(&mut self) -> Result<i64> {
+        if let Some(v) = self.buffer.pop_back() { return Ok(v) }
+        while let Some(source) = self.sources.front_mut() {
+            match source.get() {
+                Ok(v) => return Ok(v),
+                Err(_) if source.is_closed() => { self.sources.pop_front(); },
+                Err(e) => return Err(e),
+            }
+        }
+        Err(From::from("All input sources are closed"))
+    }
+    fn get_maybe(&mut self) -> Option<i64> {
+        if let Some(v) = self.buffer.pop_back() { return Some(v) }
+        while let Some(source) = self.sources.front_mut() {
+            match source.get_maybe() {
+                Some(v) => return Some(v),
+                None if source.is_closed() => { self.sources.pop_front(); },
+                None => return None,
+            }
+        }
+        None
+    }
+    fn is_closed(&self) -> bool {
+        self.buffer.is_empty() && self.sources.iter().all(|source| source.is_closed())
+    }
+}
+
+impl InputDevice for FallbackInputDevice {
+    fn put(&mut self, output: i64) { self.buffer.push_front(output) }
+    fn get(&mut self) -> Result<i64> {
+        Ok(self.get_maybe().unwrap_or(self.fallback))
+    }
+    fn get_maybe(&mut self) -> Option<i64> {
+        self.buffer.pop_back().or_else(|| self.inner.get_maybe()).or(Some(self.fallback))
+    }
 }
 
 impl OutputDevice for DefaultOutputDevice {
@@ -85,23 +301,76 @@ impl OutputDevice for DefaultOutputDevice {
 impl InputDevice for ChannelInputDevice {
     fn put(&mut self, output: i64) { self.buffer.push_front(output) }
     fn get(&mut self) -> Result<i64> {
-        self.buffer.pop_back().map_or_else(|| self.channel.recv()
-            .map_err(|_| From::from("Failed to recv")), |x| Ok(x))
+        if let Some(v) = self.buffer.pop_back() { return Ok(v) }
+        let watch = match &self.watch {
+            Some(watch) => watch,
+            None => return self.channel.recv().map_err(|_| {
+                self.disconnected = true;
+                From::from("Input channel closed")
+            }),
+        };
+
+        // Wake up now and then to let the watchdog look for a deadlock
+        loop {
+            match self.channel.recv_timeout(watch.interval()) {
+                Ok(v) => {
+                    watch.received();
+                    return Ok(v)
+                },
+                Err(RecvTimeoutError::Timeout) => watch.wait()?,
+                Err(RecvTimeoutError::Disconnected) => {
+                    self.disconnected = true;
+                    return Err(From::from(watch.closed_error()))
+                },
+            }
+        }
     }
     fn get_maybe(&mut self) -> Option<i64> {
-        if self.buffer.len() > 0 {
+        if !self.buffer.is_empty() {
             self.buffer.pop_back()
-        } else if let Ok(v) = self.channel.try_recv() {
-            Some(v)
         } else {
-            None
+            match self.channel.try_recv() {
+                Ok(v) => {
+                    if let Some(watch) = &self.watch { watch.received(); }
+                    Some(v)
+                },
+                Err(TryRecvError::Disconnected) => { self.disconnected = true; None },
+                Err(TryRecvError::Empty) => None,
+            }
         }
     }
+    fn is_closed(&self) -> bool { self.disconnected && self.buffer.is_empty() }
 }
 
 impl OutputDevice for ChannelOutputDevice {
+    // Failures are dropped here; try_put reports them
     fn put(&mut self, output: i64) {
-        self.channel.send(output).unwrap_or_else(|_| self.buffer.push_front(output))
+        let _ = self.try_put(output);
     }
     fn get(&mut self) -> Option<i64> { self.buffer.pop_back() }
-}
\ No newline at end of file
+    fn is_closed(&self) -> bool { self.disconnected }
+    fn try_put(&mut self, output: i64) -> Result<()> {
+        if let Some(watch) = &self.watch { watch.sending(); }
+        let sent = match (&self.channel, &self.watch) {
+            (ChannelSender::Unbounded(channel), _) => channel.send(output).is_ok(),
+            (ChannelSender::Bounded(channel), None) => channel.send(output).is_ok(),
+            (ChannelSender::Bounded(channel), Some(watch)) => match watch.send_bounded(channel, output) {
+                Ok(sent) => sent,
+                Err(e) => {
+                    watch.send_failed();
+                    return Err(e)
+                },
+            },
+        };
+        if sent { return Ok(()) }
+
+        if let Some(watch) = &self.watch { watch.send_failed(); }
+        self.disconnected = true;
+        match self.policy {
+            ClosedPolicy::Error => return Err(From::from("Output channel closed")),
+            ClosedPolicy::Drop => (),
+            ClosedPolicy::Buffer => self.buffer.push_front(output),
+        }
+        Ok(())
+    }
+}
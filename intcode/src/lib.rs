@@ -1,2 +1,21 @@
 pub mod program;
-pub mod io;
\ No newline at end of file
+pub mod io;
+pub mod explore;
+pub mod compiler;
+pub mod asm;
+pub mod cfg;
+pub mod decompile;
+pub mod memmap;
+pub mod watchdog;
+pub mod gdb;
+pub mod dap;
+pub mod taint;
+pub mod optimize;
+pub mod protect;
+pub mod scheduler;
+pub mod scan;
+pub mod loops;
+pub mod stats;
+pub mod strict;
+pub mod chrome;
+pub mod network;
\ No newline at end of file
@@ -0,0 +1,91 @@
+// Detects programs stuck in an infinite loop without doing any I/O.
+//
+// Between I/O instructions a machine is deterministic, so if its complete state (ip,
+// relative base and memory) ever repeats it will go round the same cycle forever. Every
+// `interval` instructions the state is compared against one saved earlier, using Brent's
+// algorithm: the saved state is replaced after 1, 2, 4, 8, ... comparisons, so a cycle
+// of any length is found after at most a few times its length. States are hashed first
+// and only compared in full when the hashes match, so a report is never a false alarm.
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+
+// The complete state of a machine, minus its devices
+#[derive(Clone, PartialEq, Hash)]
+pub(crate) struct MachineState {
+    pub(crate) ip: usize,
+    pub(crate) relative_base: i64,
+    pub(crate) memory: Vec<i64>,
+    // Non-zero extended memory, in address order
+    pub(crate) extended: Vec<(usize, i64)>,
+}
+
+#[derive(Clone)]
+pub(crate) struct LoopDetector {
+    interval: usize,
+    // Instructions executed since the last I/O
+    executed: u64,
+    saved: Option<(u64, MachineState, u64)>,
+    power: u64,
+    comparisons: u64,
+}
+
+// A loop found by the detector. The program is left somewhere inside the cycle.
+#[derive(Clone, Debug, PartialEq)]
+pub struct InfiniteLoop {
+    // Instructions executed since the last I/O when the repeat was found
+    pub detected_after: u64,
+    // Length of the cycle in instructions (possibly a multiple of the shortest one)
+    pub period: u64,
+    // Lowest and highest instruction addresses executed in the cycle
+    pub lowest: usize,
+    pub highest: usize,
+}
+
+impl fmt::Display for InfiniteLoop {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Infinite loop: the machine's state repeats every {} instructions, executing addresses {} to {}",
+            self.period, self.lowest, self.highest)
+    }
+}
+
+impl std::error::Error for InfiniteLoop {}
+
+impl LoopDetector {
+    pub(crate) fn new(interval: usize) -> LoopDetector {
+        LoopDetector{ interval: interval.max(1), executed: 0, saved: None, power: 1, comparisons: 0 }
+    }
+
+    // Forgets everything seen, after I/O changes what the program will do next
+    pub(crate) fn reset(&mut self) {
+        *self = LoopDetector::new(self.interval);
+    }
+
+    // Counts an executed instruction, returning whether the state should be checked now
+    pub(crate) fn tick(&mut self) -> bool {
+        self.executed += 1;
+        self.executed.is_multiple_of(self.interval as u64)
+    }
+
+    // Compares the state against the saved one, returning the cycle length if it repeats
+    pub(crate) fn check(&mut self, state: MachineState) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        state.hash(&mut hasher);
+        let hash = hasher.finish();
+        if let Some((saved_hash, saved, at)) = &self.saved {
+            if *saved_hash == hash && *saved == state { return Some(self.executed - at) }
+        }
+        self.comparisons += 1;
+        if self.saved.is_none() || self.comparisons == self.power {
+            self.saved = Some((hash, state, self.executed));
+            self.power *= 2;
+            self.comparisons = 0;
+        }
+        None
+    }
+
+    pub(crate) fn executed(&self) -> u64 {
+        self.executed
+    }
+}
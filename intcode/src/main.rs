@@ -0,0 +1,437 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{self, prelude::*, BufReader};
+use std::path::PathBuf;
+use structopt::StructOpt;
+use intcode::{asm, cfg, compiler, dap, decompile, gdb, memmap, optimize, protect, taint};
+use intcode::program::{Event, IntcodeProgram};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "intcode", about = "Runs, traces and disassembles Intcode program images")]
+enum Cli {
+    #[structopt(name = "run", about = "Runs a program and prints its outputs")]
+    Run {
+        #[structopt(flatten)]
+        opts: RunOpts,
+    },
+    #[structopt(name = "trace", about = "Runs a program, printing each instruction as it executes")]
+    Trace {
+        #[structopt(flatten)]
+        opts: RunOpts,
+    },
+    #[structopt(name = "taint", about = "Runs a program, showing which inputs each output depends on")]
+    Taint {
+        #[structopt(flatten)]
+        opts: RunOpts,
+        /// Also treat the initial value of this cell as a source
+        #[structopt(short = "c", long = "cell")]
+        cells: Vec<usize>,
+        /// Show the sources of this cell's final value
+        #[structopt(short = "s", long = "show")]
+        show: Vec<usize>,
+    },
+    #[structopt(name = "protect", about = "Runs a program under memory protection, reporting self-modifying code")]
+    Protect {
+        #[structopt(flatten)]
+        opts: RunOpts,
+        /// Protected region, e.g. ro:0..100, nx:100..200 or code:0..50; may be repeated
+        #[structopt(short = "r", long = "region")]
+        regions: Vec<String>,
+    },
+    #[structopt(name = "disassemble", about = "Prints a listing of a program image")]
+    Disassemble {
+        #[structopt(short = "f", parse(from_os_str))]
+        file: PathBuf,
+        /// Memory patches applied before disassembly, e.g. 0=2
+        #[structopt(short = "p", long = "patch")]
+        patches: Vec<String>,
+    },
+    #[structopt(name = "decompile", about = "Prints C-like pseudo-code for a program image")]
+    Decompile {
+        #[structopt(short = "f", parse(from_os_str))]
+        file: PathBuf,
+    },
+    #[structopt(name = "cfg", about = "Prints the control-flow graph of a program image in DOT format")]
+    Cfg {
+        #[structopt(short = "f", parse(from_os_str))]
+        file: PathBuf,
+        /// Run the program first and add the indirect jump targets it takes
+        #[structopt(long = "observe")]
+        observe: bool,
+        /// Inputs for the observed run, comma separated
+        #[structopt(short = "i", long = "input")]
+        input: Option<String>,
+        /// Maximum number of instructions for the observed run
+        #[structopt(short = "b", long = "budget", default_value = "10000000")]
+        budget: usize,
+    },
+    #[structopt(name = "memmap", about = "Renders memory usage over a run as PPM/PGM images or HTML")]
+    Memmap {
+        #[structopt(short = "f", parse(from_os_str))]
+        file: PathBuf,
+        /// Inputs for the run, comma separated
+        #[structopt(short = "i", long = "input")]
+        input: Option<String>,
+        /// Capture a frame every this many instructions, or only at the end if 0
+        #[structopt(long = "interval", default_value = "0")]
+        interval: usize,
+        /// Maximum number of instructions to execute
+        #[structopt(short = "b", long = "budget", default_value = "10000000")]
+        budget: usize,
+        /// What to render: state, reads, writes or executions
+        #[structopt(short = "l", long = "layer", default_value = "state")]
+        layer: String,
+        /// Write HTML grids instead of images
+        #[structopt(long = "html")]
+        html: bool,
+        /// Cells per row
+        #[structopt(short = "w", long = "width", default_value = "64")]
+        width: usize,
+        /// Pixels per cell in images
+        #[structopt(short = "s", long = "scale", default_value = "4")]
+        scale: usize,
+        /// Maximum number of cells drawn
+        #[structopt(long = "limit", default_value = "65536")]
+        limit: usize,
+        /// Directory the frames are written to
+        #[structopt(short = "o", long = "out", parse(from_os_str))]
+        out: PathBuf,
+    },
+    #[structopt(name = "optimize", about = "Prints an optimised program image")]
+    Optimize {
+        #[structopt(short = "f", parse(from_os_str))]
+        file: PathBuf,
+        /// Assume relative-mode operands only address a stack beyond the image
+        #[structopt(long = "assume-stack")]
+        assume_stack: bool,
+        /// Remove stores to image cells the program never reads
+        #[structopt(long = "dead-stores")]
+        dead_stores: bool,
+        /// A cell read after the program exits, whose stores are kept; may be repeated
+        #[structopt(short = "k", long = "keep")]
+        keep: Vec<usize>,
+        /// Inputs to check the optimised image against the original with, comma separated; may be repeated
+        #[structopt(short = "c", long = "check")]
+        checks: Vec<String>,
+        /// Maximum number of instructions for each check run
+        #[structopt(short = "b", long = "budget", default_value = "10000000")]
+        budget: usize,
+    },
+    #[structopt(name = "gdb", about = "Serves a program to a GDB remote protocol debugger")]
+    Gdb {
+        #[structopt(short = "f", parse(from_os_str))]
+        file: PathBuf,
+        /// Inputs queued before the session starts, comma separated
+        #[structopt(short = "i", long = "input")]
+        input: Option<String>,
+        /// TCP address to listen on
+        #[structopt(long = "tcp", default_value = "127.0.0.1:1234")]
+        tcp: String,
+        /// Listen on a Unix socket at this path instead
+        #[structopt(long = "unix", parse(from_os_str))]
+        unix: Option<PathBuf>,
+    },
+    #[structopt(name = "dap", about = "Serves the Debug Adapter Protocol over stdin and stdout")]
+    Dap,
+    #[structopt(name = "assemble", about = "Assembles and links source modules into a program image")]
+    Assemble {
+        #[structopt(short = "f", parse(from_os_str), required = true)]
+        files: Vec<PathBuf>,
+    },
+    #[structopt(name = "compile", about = "Compiles a source file to a program image")]
+    Compile {
+        #[structopt(short = "f", parse(from_os_str))]
+        file: PathBuf,
+    },
+}
+
+#[derive(Debug, StructOpt)]
+struct RunOpts {
+    #[structopt(short = "f", parse(from_os_str))]
+    file: PathBuf,
+    /// Inputs given on the command line, comma separated in numeric mode
+    #[structopt(short = "i", long = "input")]
+    input: Option<String>,
+    /// File to read inputs from
+    #[structopt(long = "input-file", parse(from_os_str))]
+    input_file: Option<PathBuf>,
+    /// Read inputs from stdin until EOF
+    #[structopt(long = "stdin")]
+    stdin: bool,
+    /// How inputs are interpreted: numeric or ascii
+    #[structopt(short = "m", long = "mode", default_value = "numeric")]
+    mode: String,
+    /// Memory patches applied before running, e.g. 0=2
+    #[structopt(short = "p", long = "patch")]
+    patches: Vec<String>,
+    /// Maximum number of instructions to execute
+    #[structopt(short = "b", long = "budget")]
+    budget: Option<usize>,
+    /// How outputs are printed: list, json or ascii
+    #[structopt(short = "o", long = "output", default_value = "list")]
+    output: String,
+    /// Fail if the program loops forever without I/O, checking every this many instructions
+    #[structopt(long = "detect-loops")]
+    detect_loops: Option<usize>,
+    /// Fault on invalid mode digits, immediate-mode destinations and extra opcode digits
+    #[structopt(long = "strict")]
+    strict: bool,
+}
+
+fn read_file(path: &PathBuf) -> Result<String> {
+    let f = File::open(path)?;
+    let mut reader = BufReader::new(f);
+    let mut contents = String::new();
+    reader.read_to_string(&mut contents)?;
+    Ok(contents)
+}
+
+fn load_memory(file: &PathBuf) -> Result<Vec<i64>> {
+    IntcodeProgram::raw_to_memory(read_file(file)?.trim())
+}
+
+fn load_patched(file: &PathBuf, patches: &[String]) -> Result<IntcodeProgram> {
+    let mut program = IntcodeProgram::from_memory(load_memory(file)?);
+    for patch in patches {
+        let (location, value) = match patch.find('=') {
+            Some(idx) => (patch[..idx].trim().parse::<usize>(), patch[idx + 1..].trim().parse::<i64>()),
+            None => return Err(From::from(format!("Invalid patch, expected <address>=<value>: {}", patch))),
+        };
+        let (location, value) = match (location, value) {
+            (Ok(location), Ok(value)) => (location, value),
+            _ => return Err(From::from(format!("Invalid patch, expected <address>=<value>: {}", patch))),
+        };
+        // Patches past the end of the image go to extended memory, like the program's own writes
+        program.write(location, value).map_err(|e| format!("Invalid patch {}: {}", patch, e))?;
+    }
+    Ok(program)
+}
+
+fn parse_inputs(raw: &str, mode: &str) -> Result<Vec<i64>> {
+    match mode {
+        "numeric" => raw.split(|c: char| c == ',' || c.is_whitespace()).filter(|s| !s.is_empty()).map(|item| {
+            item.parse::<i64>().map_err(|_| From::from(format!("Invalid integer input: {}", item)))
+        }).collect(),
+        "ascii" => Ok(raw.lines().flat_map(|line| line.chars().map(|c| c as i64).chain(std::iter::once(10))).collect()),
+        _ => Err(From::from(format!("Unknown input mode: {}", mode))),
+    }
+}
+
+fn gather_inputs(opts: &RunOpts) -> Result<Vec<i64>> {
+    let mut inputs = vec![];
+    if let Some(raw) = &opts.input { inputs.extend(parse_inputs(raw, &opts.mode)?); }
+    if let Some(path) = &opts.input_file { inputs.extend(parse_inputs(&read_file(path)?, &opts.mode)?); }
+    if opts.stdin {
+        let mut raw = String::new();
+        io::stdin().read_to_string(&mut raw)?;
+        inputs.extend(parse_inputs(&raw, &opts.mode)?);
+    }
+    Ok(inputs)
+}
+
+fn print_outputs(outputs: &[i64], format: &str) -> Result<()> {
+    match format {
+        "list" => println!("{}", outputs.iter().map(|o| o.to_string()).collect::<Vec<String>>().join(",")),
+        "json" => println!("[{}]", outputs.iter().map(|o| o.to_string()).collect::<Vec<String>>().join(", ")),
+        "ascii" => {
+            // Values outside the ASCII range (e.g. aoc_17's dust count) are printed as numbers
+            for &o in outputs {
+                match o {
+                    0..=127 => print!("{}", (o as u8) as char),
+                    _ => println!("{}", o),
+                }
+            }
+            io::stdout().flush()?;
+        },
+        _ => return Err(From::from(format!("Unknown output format: {}", format))),
+    }
+    Ok(())
+}
+
+fn load_program(opts: &RunOpts) -> Result<IntcodeProgram> {
+    let mut program = load_patched(&opts.file, &opts.patches)?;
+    for input in gather_inputs(opts)? { program.give_input(input); }
+    program.detect_loops(opts.detect_loops);
+    program.set_strict(opts.strict);
+    Ok(program)
+}
+
+// Runs the program until it exits, collecting its outputs
+fn run_to_exit(program: &mut IntcodeProgram, budget: Option<usize>, outputs: &mut Vec<i64>) -> Result<()> {
+    program.timed(|program| {
+        let mut executed = 0;
+        loop {
+            if budget.is_some_and(|budget| executed >= budget) {
+                return Err(From::from(format!("Instruction budget of {} exhausted", executed)));
+            }
+            executed += 1;
+            // Structured errors (faults, loops) are reported by their message rather than their fields
+            match program.step().map_err(|e| e.to_string())? {
+                Some(Event::ProducedOutput) => outputs.extend(program.get_output()),
+                Some(Event::InputRequired) => return Err(From::from("Program requested more input than was provided")),
+                Some(Event::Exited) | Some(Event::PeerClosed) => return Ok(()),
+                None => (),
+            }
+        }
+    })
+}
+
+fn run(opts: &RunOpts, trace: bool) -> Result<()> {
+    let mut program = load_program(opts)?;
+    program.set_trace(trace);
+
+    let mut outputs = vec![];
+    let result = run_to_exit(&mut program, opts.budget, &mut outputs);
+    print_outputs(&outputs, &opts.output)?;
+    result
+}
+
+fn describe_taint(taint: &taint::Taint) -> String {
+    if taint.is_empty() { return String::from("(constant)") }
+    taint.iter().map(|source| source.to_string()).collect::<Vec<String>>().join(", ")
+}
+
+// Runs the program with taint tracking, printing the sources of each output and chosen cell
+fn run_taint(opts: &RunOpts, cells: &[usize], show: &[usize]) -> Result<()> {
+    let mut program = load_program(opts)?;
+    program.track_taint(true);
+    for &cell in cells { program.taint_cell(cell); }
+
+    let result = run_to_exit(&mut program, opts.budget, &mut vec![]);
+    let tracker = program.taint().unwrap();
+    for (value, taint) in tracker.outputs() {
+        println!("output {} <- {}", value, describe_taint(taint));
+    }
+    for &address in show {
+        println!("[{}] = {} <- {}", address, program.load_position(address), describe_taint(&tracker.cell(address)));
+    }
+    result
+}
+
+fn parse_region(raw: &str) -> Result<(std::ops::Range<usize>, protect::Protection)> {
+    let invalid = || From::from(format!("Invalid region, expected <ro|nx|code>:<start>..<end>: {}", raw));
+    let (kind, range) = raw.split_once(':').ok_or_else(invalid)?;
+    let (start, end) = range.split_once("..").ok_or_else(invalid)?;
+    let protection = match kind {
+        "ro" => protect::Protection::ReadOnly,
+        "nx" => protect::Protection::NoExecute,
+        "code" => protect::Protection::Code,
+        _ => return Err(invalid()),
+    };
+    match (start.trim().parse::<usize>(), end.trim().parse::<usize>()) {
+        (Ok(start), Ok(end)) => Ok((start..end, protection)),
+        _ => Err(invalid()),
+    }
+}
+
+// Runs the program under the given protection, then lists instructions executed after being written
+fn run_protected(opts: &RunOpts, regions: &[String]) -> Result<()> {
+    let mut program = load_program(opts)?;
+    program.detect_self_modification(true);
+    for raw in regions {
+        let (range, protection) = parse_region(raw)?;
+        program.protect(range, protection);
+    }
+
+    let mut outputs = vec![];
+    let result = run_to_exit(&mut program, opts.budget, &mut outputs);
+    print_outputs(&outputs, &opts.output)?;
+    let mut modifications = program.memory_protection().unwrap().self_modifications().peekable();
+    if modifications.peek().is_none() { eprintln!("No instructions were executed after being written"); }
+    for modification in modifications {
+        eprintln!("Self-modified: {}", modification);
+    }
+    result
+}
+
+fn main() -> Result<()> {
+    match Cli::from_args() {
+        Cli::Run{ opts } => run(&opts, false),
+        Cli::Trace{ opts } => run(&opts, true),
+        Cli::Taint{ opts, cells, show } => run_taint(&opts, &cells, &show),
+        Cli::Protect{ opts, regions } => run_protected(&opts, &regions),
+        Cli::Disassemble{ file, patches } => {
+            load_patched(&file, &patches)?.disassemble();
+            Ok(())
+        },
+        Cli::Decompile{ file } => {
+            print!("{}", decompile::decompile(&load_memory(&file)?)?);
+            Ok(())
+        },
+        Cli::Cfg{ file, observe, input, budget } => {
+            let memory = load_memory(&file)?;
+            let mut observed = BTreeMap::new();
+            if observe {
+                let mut program = IntcodeProgram::from_memory(memory.clone());
+                for value in parse_inputs(input.as_deref().unwrap_or(""), "numeric")? { program.give_input(value); }
+                observed = cfg::observe_indirect_jumps(&mut program, budget)?;
+            }
+            let program = IntcodeProgram::from_memory(memory);
+            print!("{}", cfg::ControlFlowGraph::with_observed(&program, &observed).to_dot(&observed));
+            Ok(())
+        },
+        Cli::Memmap{ file, input, interval, budget, layer, html, width, scale, limit, out } => {
+            let heat = match layer.as_str() {
+                "state" => None,
+                "reads" => Some(memmap::Heat::Reads),
+                "writes" => Some(memmap::Heat::Writes),
+                "executions" => Some(memmap::Heat::Executions),
+                _ => return Err(From::from(format!("Unknown layer: {}", layer))),
+            };
+            if width == 0 || scale == 0 { return Err(From::from("Width and scale must be positive")); }
+
+            let mut program = IntcodeProgram::from_memory(load_memory(&file)?);
+            for value in parse_inputs(input.as_deref().unwrap_or(""), "numeric")? { program.give_input(value); }
+            std::fs::create_dir_all(&out)?;
+            for (idx, frame) in memmap::record_frames(&mut program, interval, budget, limit)?.iter().enumerate() {
+                let (contents, extension) = match (html, heat) {
+                    (true, heat) => (frame.to_html(width, heat).into_bytes(), "html"),
+                    (false, None) => (frame.to_ppm(width, scale), "ppm"),
+                    (false, Some(heat)) => (frame.to_pgm(width, scale, heat), "pgm"),
+                };
+                std::fs::write(out.join(format!("frame_{:05}.{}", idx, extension)), contents)?;
+            }
+            Ok(())
+        },
+        Cli::Optimize{ file, assume_stack, dead_stores, keep, checks, budget } => {
+            let memory = load_memory(&file)?;
+            let options = optimize::Options{
+                assume_stack_outside_image: assume_stack, remove_dead_stores: dead_stores, host_reads: keep
+            };
+            let optimized = optimize::optimize(&memory, &options)?;
+            eprintln!("Folded {} instructions, removed {} dead stores, threaded {} jumps",
+                optimized.folded, optimized.dead_stores, optimized.jumps_threaded);
+            let input_sets = checks.iter().map(|raw| parse_inputs(raw, "numeric")).collect::<Result<Vec<Vec<i64>>>>()?;
+            optimize::differential_check(&memory, &optimized.memory, &optimized.dead_cells, &input_sets, budget)?;
+            print_outputs(&optimized.memory, "list")
+        },
+        Cli::Gdb{ file, input, tcp, unix } => {
+            let mut program = IntcodeProgram::from_memory(load_memory(&file)?);
+            for value in parse_inputs(input.as_deref().unwrap_or(""), "numeric")? { program.give_input(value); }
+            match unix {
+                Some(path) => {
+                    let listener = std::os::unix::net::UnixListener::bind(&path)?;
+                    eprintln!("Waiting for debugger on {}", path.display());
+                    gdb::serve(&mut program, listener.accept()?.0)
+                },
+                None => {
+                    let listener = std::net::TcpListener::bind(&tcp)?;
+                    eprintln!("Waiting for debugger on {}", listener.local_addr()?);
+                    let (stream, _) = listener.accept()?;
+                    stream.set_nodelay(true)?;
+                    gdb::serve(&mut program, stream)
+                },
+            }
+        },
+        Cli::Dap => dap::serve(BufReader::new(io::stdin()), io::stdout()),
+        Cli::Assemble{ files } => {
+            let objects = files.iter().map(|file| {
+                asm::assemble_module(&file.to_string_lossy(), &read_file(file)?)
+            }).collect::<Result<Vec<asm::Object>>>()?;
+            print_outputs(&asm::link(&objects)?.memory, "list")
+        },
+        Cli::Compile{ file } => print_outputs(&compiler::compile(&read_file(&file)?)?, "list"),
+    }
+}
@@ -0,0 +1,184 @@
+// Renders a machine's memory as an image or HTML grid, one cell per address,
+// laid out left to right and top to bottom.
+//
+// Cells are coloured by region: code (executed image cells), the rest of the
+// image, the stack (extended cells accessed through the relative base) and
+// other extended memory. Zero cells are drawn dimmed. Heat maps shade each
+// cell by how often it was read, written or executed, on a log scale.
+
+use super::program::{AccessCount, Event, IntcodeProgram};
+use std::cmp;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Region {
+    Code,
+    Image,
+    Stack,
+    Extended,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Heat {
+    Reads,
+    Writes,
+    Executions,
+}
+
+// The contents of memory at one point in a run
+pub struct Snapshot {
+    pub instructions: usize,
+    pub cells: Vec<i64>,
+    pub counts: Vec<AccessCount>,
+    pub image_len: usize,
+}
+
+impl Region {
+    fn color(self) -> (u8, u8, u8) {
+        match self {
+            Region::Code => (70, 130, 180),
+            Region::Image => (147, 112, 219),
+            Region::Stack => (60, 179, 113),
+            Region::Extended => (255, 165, 0),
+        }
+    }
+}
+
+impl Snapshot {
+    // Captures the image plus any extended memory that's non-zero or has been accessed,
+    // truncated to `limit` cells
+    pub fn capture(program: &IntcodeProgram, instructions: usize, limit: usize) -> Snapshot {
+        let counts = program.access_counts();
+        let highest_extended = program.extended_cells().into_iter().map(|(address, _)| address)
+            .chain(counts.into_iter().flat_map(|c| c.keys().cloned()))
+            .max().map_or(0, |k| k + 1);
+        let len = cmp::min(limit, cmp::max(program.image_len(), highest_extended));
+
+        Snapshot{
+            instructions,
+            cells: (0..len).map(|i| program.load_position(i)).collect(),
+            counts: (0..len).map(|i| counts.and_then(|c| c.get(&i)).cloned().unwrap_or_default()).collect(),
+            image_len: program.image_len(),
+        }
+    }
+
+    pub fn region(&self, address: usize) -> Region {
+        let count = &self.counts[address];
+        match (address < self.image_len, count.executions > 0, count.relative > 0) {
+            (true, true, _) => Region::Code,
+            (true, false, _) => Region::Image,
+            (false, _, true) => Region::Stack,
+            (false, _, false) => Region::Extended,
+        }
+    }
+
+    fn heat(&self, address: usize, heat: Heat) -> u64 {
+        let count = &self.counts[address];
+        match heat {
+            Heat::Reads => count.reads,
+            Heat::Writes => count.writes,
+            Heat::Executions => count.executions,
+        }
+    }
+
+    // Brightness 0-255 of each cell's heat, log scaled against the hottest cell
+    fn heat_levels(&self, heat: Heat) -> Vec<u8> {
+        let max = (0..self.cells.len()).map(|i| self.heat(i, heat)).max().unwrap_or(0);
+        (0..self.cells.len()).map(|i| {
+            if max == 0 { return 0 }
+            (255.0 * (1.0 + self.heat(i, heat) as f64).ln() / (1.0 + max as f64).ln()) as u8
+        }).collect()
+    }
+
+    fn cell_color(&self, address: usize) -> (u8, u8, u8) {
+        let (r, g, b) = self.region(address).color();
+        if self.cells[address] == 0 { (r / 4, g / 4, b / 4) } else { (r, g, b) }
+    }
+
+    fn dimensions(&self, width: usize, scale: usize) -> (usize, usize) {
+        let rows = cmp::max(1, self.cells.len().div_ceil(width));
+        (width * scale, rows * scale)
+    }
+
+    // Calls `pixel` with the cell at each pixel (or None past the end) in row-major order
+    fn for_each_pixel<F: FnMut(Option<usize>)>(&self, width: usize, scale: usize, mut pixel: F) {
+        let (w, h) = self.dimensions(width, scale);
+        for y in 0..h {
+            for x in 0..w {
+                let address = (y / scale) * width + x / scale;
+                pixel(if address < self.cells.len() { Some(address) } else { None });
+            }
+        }
+    }
+
+    // Binary PPM coloured by region and value
+    pub fn to_ppm(&self, width: usize, scale: usize) -> Vec<u8> {
+        let (w, h) = self.dimensions(width, scale);
+        let mut out = format!("P6\n{} {}\n255\n", w, h).into_bytes();
+        self.for_each_pixel(width, scale, |address| {
+            let (r, g, b) = address.map_or((0, 0, 0), |a| self.cell_color(a));
+            out.extend(&[r, g, b]);
+        });
+        out
+    }
+
+    // Binary PGM of the chosen access counts
+    pub fn to_pgm(&self, width: usize, scale: usize, heat: Heat) -> Vec<u8> {
+        let (w, h) = self.dimensions(width, scale);
+        let levels = self.heat_levels(heat);
+        let mut out = format!("P5\n{} {}\n255\n", w, h).into_bytes();
+        self.for_each_pixel(width, scale, |address| out.push(address.map_or(0, |a| levels[a])));
+        out
+    }
+
+    // A table with a cell per address; hovering shows its value and access counts.
+    // With a heat mode, cells are shaded red by that count instead of by region.
+    pub fn to_html(&self, width: usize, heat: Option<Heat>) -> String {
+        let levels = heat.map(|h| self.heat_levels(h));
+        let mut out = String::new();
+        out.push_str("<!DOCTYPE html>\n<html><head><style>\n");
+        out.push_str("table { border-collapse: collapse; } td { width: 8px; height: 8px; padding: 0; }\n");
+        out.push_str("</style></head><body>\n");
+        out.push_str(&format!("<p>After {} instructions, {} cells</p>\n<table>\n", self.instructions, self.cells.len()));
+        for row in (0..self.cells.len()).step_by(width) {
+            out.push_str("<tr>");
+            for address in row..cmp::min(row + width, self.cells.len()) {
+                let (r, g, b) = match &levels {
+                    Some(levels) => (levels[address], 0, 0),
+                    None => self.cell_color(address),
+                };
+                let count = &self.counts[address];
+                out.push_str(&format!(
+                    "<td style=\"background:#{:02x}{:02x}{:02x}\" title=\"{}: {} ({:?}, r{} w{} x{})\"></td>",
+                    r, g, b, address, self.cells[address], self.region(address), count.reads, count.writes, count.executions));
+            }
+            out.push_str("</tr>\n");
+        }
+        out.push_str("</table>\n</body></html>\n");
+        out
+    }
+}
+
+// Runs the program with access counting on, capturing a snapshot every `interval` instructions
+// and once more when it exits, needs input it hasn't been given or exhausts the budget
+pub fn record_frames(program: &mut IntcodeProgram, interval: usize, budget: usize, limit: usize) -> Result<Vec<Snapshot>> {
+    if program.access_counts().is_none() { program.count_accesses(true); }
+
+    let mut frames = vec![];
+    let mut executed = 0;
+    while executed < budget {
+        match program.step()? {
+            Some(Event::InputRequired) | Some(Event::Exited) | Some(Event::PeerClosed) => break,
+            _ => executed += 1,
+        }
+        if interval > 0 && executed % interval == 0 {
+            frames.push(Snapshot::capture(program, executed, limit));
+        }
+    }
+
+    if frames.last().is_none_or(|f| f.instructions != executed) {
+        frames.push(Snapshot::capture(program, executed, limit));
+    }
+    Ok(frames)
+}
@@ -0,0 +1,155 @@
+// Runs a network of machines on one thread in a reproducible order.
+//
+// Every tick, each machine still running gets one turn, in the order the policy picks:
+// by id, or a new random permutation each tick. A turn lasts until the machine's next
+// event or for a quantum of instructions, which may be drawn at random for every turn.
+// Events are passed to a handler as they happen, which is where a driver routes outputs
+// and provides input. All randomness comes from the policy's seed, so running the same
+// programs and handler with the same policy always gives the same interleaving, and a
+// failure seen under one seed can be replayed exactly.
+
+use super::chrome::ChromeTrace;
+use super::program::{Event, IntcodeProgram};
+use std::sync::Arc;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Order {
+    // Machines take their turns in id order
+    RoundRobin,
+    // A fresh random permutation every tick
+    Shuffled,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Quantum {
+    // Each turn runs until the machine's next event
+    UntilEvent,
+    // At most this many instructions per turn, ending early at an event
+    Fixed(usize),
+    // Like Fixed, with a limit drawn from 1 to this for each turn
+    Random(usize),
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Policy {
+    pub order: Order,
+    pub quantum: Quantum,
+    pub seed: u64,
+}
+
+// SplitMix64: small, fast and plenty for picking interleavings
+struct Rng(u64);
+
+pub struct Network {
+    programs: Vec<IntcodeProgram>,
+    // Set once a machine has exited or lost a peer, after which it gets no more turns
+    done: Vec<bool>,
+    policy: Policy,
+    rng: Rng,
+    ticks: u64,
+    trace: Option<Arc<ChromeTrace>>,
+}
+
+impl Policy {
+    // Every machine in id order, running until its next event
+    pub fn round_robin() -> Policy {
+        Policy{ order: Order::RoundRobin, quantum: Quantum::UntilEvent, seed: 0 }
+    }
+
+    // Shuffled turns of between 1 and `max_quantum` instructions
+    pub fn random(seed: u64, max_quantum: usize) -> Policy {
+        Policy{ order: Order::Shuffled, quantum: Quantum::Random(max_quantum), seed }
+    }
+}
+
+impl Default for Policy {
+    fn default() -> Policy {
+        Policy::round_robin()
+    }
+}
+
+impl Rng {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // A number from 0 to n - 1
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+impl Network {
+    pub fn new(policy: Policy) -> Network {
+        Network{ programs: vec![], done: vec![], policy, rng: Rng(policy.seed), ticks: 0, trace: None }
+    }
+
+    // Adds a machine, returning its id
+    pub fn add(&mut self, program: IntcodeProgram) -> usize {
+        self.programs.push(program);
+        self.done.push(false);
+        self.programs.len() - 1
+    }
+
+    // Draws each turn as a run span on the trace track with the machine's id
+    pub fn set_trace(&mut self, trace: Option<Arc<ChromeTrace>>) { self.trace = trace }
+
+    pub fn policy(&self) -> Policy { self.policy }
+    pub fn ticks(&self) -> u64 { self.ticks }
+    pub fn programs(&self) -> &[IntcodeProgram] { &self.programs }
+    pub fn program_mut(&mut self, id: usize) -> &mut IntcodeProgram { &mut self.programs[id] }
+
+    // True once every machine has exited or lost a peer
+    pub fn finished(&self) -> bool {
+        self.done.iter().all(|&done| done)
+    }
+
+    // The order machines take their turns in this tick
+    fn turns(&mut self) -> Vec<usize> {
+        let mut order: Vec<usize> = (0..self.programs.len()).filter(|&id| !self.done[id]).collect();
+        if self.policy.order == Order::Shuffled {
+            for i in (1..order.len()).rev() {
+                order.swap(i, self.rng.below(i + 1));
+            }
+        }
+        order
+    }
+
+    fn quantum(&mut self) -> Option<usize> {
+        match self.policy.quantum {
+            Quantum::UntilEvent => None,
+            Quantum::Fixed(quantum) => Some(quantum.max(1)),
+            Quantum::Random(max) => Some(1 + self.rng.below(max.max(1))),
+        }
+    }
+
+    // Gives every running machine one turn, passing each event to the handler along with
+    // the machine's id. Errors from a machine are returned with its id.
+    pub fn tick<F>(&mut self, mut handle: F) -> Result<()>
+        where F: FnMut(usize, &mut IntcodeProgram, Event) -> Result<()> {
+        self.ticks += 1;
+        for id in self.turns() {
+            let quantum = self.quantum();
+            let program = &mut self.programs[id];
+            if let Some(trace) = &self.trace { trace.resume(id); }
+            let event = match quantum {
+                None => Some(program.execute_until_event()),
+                Some(quantum) => program.timed(|program| (0..quantum).find_map(|_| program.step().transpose())),
+            };
+            if let Some(trace) = &self.trace { trace.pause(id); }
+            let event = match event {
+                Some(event) => event.map_err(|e| format!("Machine {}: {}", id, e))?,
+                None => continue,
+            };
+            if matches!(event, Event::Exited | Event::PeerClosed) { self.done[id] = true; }
+            handle(id, program, event)?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,324 @@
+// Peephole optimiser for program images.
+//
+// Instructions are only rewritten in place, so every address (and any code or data
+// referring to one) stays valid. The passes are:
+//
+//   - constant folding: position operands reading cells that are never written become
+//     immediates, arithmetic on two immediates is replaced by storing its result, and
+//     jumps with a constant predicate become unconditional or are disabled
+//   - dead store removal (opt-in): arithmetic storing to an image cell that the program
+//     never reads is replaced by a jump over it. The host may still read the image once
+//     the program exits (aoc_2 reads cell 0), so cells it reads must be listed to keep
+//     their stores.
+//   - jump threading: a jump to an unconditional jump goes straight to its target
+//
+// The analysis needs every instruction that can execute and every cell that can be
+// read or written, so it refuses images with jumps through memory (other than returns
+// via the relative base) or that run into invalid instructions. Relative-mode operands
+// could address any cell, so they also block optimisation unless the caller assumes
+// they only reach a stack beyond the end of the image, as compiled programs' do. An
+// instruction is only rewritten if none of its cells are ever written or read as data.
+// Cells patched before running (e.g. noun and verb) count as constants, so patch first.
+
+use super::cfg::{self, Exit};
+use super::program::{Event, IntcodeInstruction, IntcodeProgram, Parameter, ParameterMode};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    // Treat relative-mode operands as only reaching cells beyond the image
+    pub assume_stack_outside_image: bool,
+    pub remove_dead_stores: bool,
+    // Cells the host reads after running, whose stores are never removed
+    pub host_reads: Vec<usize>,
+}
+
+pub struct Optimized {
+    pub memory: Vec<i64>,
+    pub folded: usize,
+    pub dead_stores: usize,
+    pub jumps_threaded: usize,
+    // Cells whose stores were removed, so they can end a run holding different values
+    pub dead_cells: Vec<usize>,
+}
+
+struct Analysis {
+    image_len: usize,
+    instructions: BTreeMap<usize, IntcodeInstruction>,
+    code: HashSet<usize>,
+    reads: HashSet<usize>,
+    writes: HashSet<usize>,
+}
+
+fn immediate(param: i64) -> Parameter {
+    Parameter{ param, mode: ParameterMode::Immediate }
+}
+
+// Operands an instruction reads, and the one it writes to
+fn operands(instruction: &IntcodeInstruction) -> (Vec<&Parameter>, Option<&Parameter>) {
+    match instruction {
+        IntcodeInstruction::Add{o1, o2, dest} | IntcodeInstruction::Mul{o1, o2, dest} |
+        IntcodeInstruction::LessThan{o1, o2, dest} | IntcodeInstruction::Equals{o1, o2, dest} => (vec![o1, o2], Some(dest)),
+        IntcodeInstruction::JumpIfTrue{predicate, target} | IntcodeInstruction::JumpIfFalse{predicate, target} => (vec![predicate, target], None),
+        IntcodeInstruction::LoadInput{dest} => (vec![], Some(dest)),
+        IntcodeInstruction::Output{val} | IntcodeInstruction::AdjustRelativeBase{val} => (vec![val], None),
+        IntcodeInstruction::Exit => (vec![], None),
+    }
+}
+
+// Stores through an immediate-mode destination write to that address, like position mode
+fn destination(p: &Parameter) -> Option<usize> {
+    if p.mode == ParameterMode::Relative { None } else { Some(p.param as usize) }
+}
+
+// The target of a jump that's always taken, if the instruction is one
+fn unconditional_target(instruction: &IntcodeInstruction) -> Option<i64> {
+    match instruction {
+        IntcodeInstruction::JumpIfTrue{predicate, target} |
+        IntcodeInstruction::JumpIfFalse{predicate, target} if predicate.mode == ParameterMode::Immediate && target.mode == ParameterMode::Immediate => {
+            let nonzero = matches!(instruction, IntcodeInstruction::JumpIfTrue{..});
+            if (predicate.param != 0) == nonzero { Some(target.param) } else { None }
+        },
+        _ => None,
+    }
+}
+
+impl Analysis {
+    fn new(image: &[i64], options: &Options) -> Result<Analysis> {
+        let program = IntcodeProgram::from_memory(image.to_vec());
+        let graph = cfg::build(&program, &[]);
+        let mut analysis = Analysis{
+            image_len: image.len(),
+            instructions: BTreeMap::new(),
+            code: HashSet::new(),
+            reads: HashSet::new(),
+            writes: HashSet::new(),
+        };
+
+        for block in graph.blocks.values() {
+            match block.exit {
+                Exit::Indirect{..} => return Err(From::from(format!(
+                    "Block at {} jumps through memory, so not all code can be found", block.start))),
+                Exit::Invalid(address) => return Err(From::from(format!(
+                    "Execution can reach an invalid instruction at {}", address))),
+                _ => (),
+            }
+            for (address, instruction) in &block.instructions {
+                analysis.code.extend(*address..*address + instruction.length());
+                let (reads, dest) = operands(instruction);
+                for p in reads.iter().chain(dest.iter()) {
+                    if p.mode == ParameterMode::Relative && !options.assume_stack_outside_image {
+                        return Err(From::from(format!(
+                            "Instruction at {} uses the relative base, which could address any cell", address)));
+                    }
+                }
+                analysis.reads.extend(reads.iter().filter(|p| p.mode == ParameterMode::Position).map(|p| p.param as usize));
+                analysis.writes.extend(dest.and_then(destination));
+                analysis.instructions.insert(*address, instruction.clone());
+            }
+        }
+        Ok(analysis)
+    }
+
+    // Whether a cell holds its initial value throughout any run
+    fn is_constant(&self, address: usize) -> bool {
+        address < self.image_len && !self.writes.contains(&address)
+    }
+
+    // Whether an instruction can be rewritten: its cells are never written or read as data
+    fn can_rewrite(&self, address: usize, instruction: &IntcodeInstruction) -> bool {
+        (address..address + instruction.length()).all(|a| !self.writes.contains(&a) && !self.reads.contains(&a))
+    }
+}
+
+// Replaces position operands that read constant cells with their values, then evaluates
+// the instruction if everything it depends on is known
+fn fold(instruction: &IntcodeInstruction, analysis: &Analysis, image: &[i64]) -> IntcodeInstruction {
+    let constant = |p: &Parameter| {
+        if p.mode == ParameterMode::Position && analysis.is_constant(p.param as usize) {
+            immediate(image[p.param as usize])
+        } else {
+            p.clone()
+        }
+    };
+    let both = |o1: &Parameter, o2: &Parameter| -> Option<(i64, i64)> {
+        if o1.mode == ParameterMode::Immediate && o2.mode == ParameterMode::Immediate { Some((o1.param, o2.param)) } else { None }
+    };
+
+    let folded = match instruction {
+        IntcodeInstruction::Add{o1, o2, dest} => IntcodeInstruction::Add{ o1: constant(o1), o2: constant(o2), dest: dest.clone() },
+        IntcodeInstruction::Mul{o1, o2, dest} => IntcodeInstruction::Mul{ o1: constant(o1), o2: constant(o2), dest: dest.clone() },
+        IntcodeInstruction::LessThan{o1, o2, dest} => IntcodeInstruction::LessThan{ o1: constant(o1), o2: constant(o2), dest: dest.clone() },
+        IntcodeInstruction::Equals{o1, o2, dest} => IntcodeInstruction::Equals{ o1: constant(o1), o2: constant(o2), dest: dest.clone() },
+        IntcodeInstruction::JumpIfTrue{predicate, target} => IntcodeInstruction::JumpIfTrue{ predicate: constant(predicate), target: constant(target) },
+        IntcodeInstruction::JumpIfFalse{predicate, target} => IntcodeInstruction::JumpIfFalse{ predicate: constant(predicate), target: constant(target) },
+        IntcodeInstruction::Output{val} => IntcodeInstruction::Output{ val: constant(val) },
+        IntcodeInstruction::AdjustRelativeBase{val} => IntcodeInstruction::AdjustRelativeBase{ val: constant(val) },
+        other => other.clone(),
+    };
+    // With no operand made constant, only arithmetic other than a constant store is left to evaluate
+    if folded.encode() == instruction.encode() && !matches!(instruction,
+        IntcodeInstruction::Mul{..} | IntcodeInstruction::LessThan{..} | IntcodeInstruction::Equals{..}) {
+        return folded;
+    }
+
+    let value = match &folded {
+        IntcodeInstruction::Add{o1, o2, dest} => both(o1, o2).map(|(a, b)| (a.wrapping_add(b), dest)),
+        IntcodeInstruction::Mul{o1, o2, dest} => both(o1, o2).map(|(a, b)| (a.wrapping_mul(b), dest)),
+        IntcodeInstruction::LessThan{o1, o2, dest} => both(o1, o2).map(|(a, b)| ((a < b) as i64, dest)),
+        IntcodeInstruction::Equals{o1, o2, dest} => both(o1, o2).map(|(a, b)| ((a == b) as i64, dest)),
+        _ => None,
+    };
+    if let Some((value, dest)) = value {
+        return IntcodeInstruction::Add{ o1: immediate(value), o2: immediate(0), dest: dest.clone() };
+    }
+
+    match &folded {
+        IntcodeInstruction::JumpIfTrue{predicate, target} | IntcodeInstruction::JumpIfFalse{predicate, target}
+            if predicate.mode == ParameterMode::Immediate => {
+            let nonzero = matches!(folded, IntcodeInstruction::JumpIfTrue{..});
+            if (predicate.param != 0) == nonzero {
+                IntcodeInstruction::JumpIfTrue{ predicate: immediate(1), target: target.clone() }
+            } else {
+                IntcodeInstruction::JumpIfTrue{ predicate: immediate(0), target: immediate(0) }
+            }
+        },
+        _ => folded,
+    }
+}
+
+// The image cell an arithmetic instruction stores to, if neither the program nor the host reads it
+fn dead_store(instruction: &IntcodeInstruction, analysis: &Analysis, options: &Options) -> Option<usize> {
+    match instruction {
+        IntcodeInstruction::Add{dest, ..} | IntcodeInstruction::Mul{dest, ..} |
+        IntcodeInstruction::LessThan{dest, ..} | IntcodeInstruction::Equals{dest, ..} => {
+            destination(dest).filter(|address| {
+                *address < analysis.image_len && !analysis.reads.contains(address) && !analysis.code.contains(address) &&
+                    !options.host_reads.contains(address)
+            })
+        },
+        _ => None,
+    }
+}
+
+pub fn optimize(image: &[i64], options: &Options) -> Result<Optimized> {
+    let analysis = Analysis::new(image, options)?;
+    let mut result = Optimized{ memory: image.to_vec(), folded: 0, dead_stores: 0, jumps_threaded: 0, dead_cells: vec![] };
+    let mut rewritten: BTreeMap<usize, IntcodeInstruction> = BTreeMap::new();
+
+    for (&address, instruction) in &analysis.instructions {
+        if !analysis.can_rewrite(address, instruction) { continue }
+        let mut new = fold(instruction, &analysis, image);
+        if new.encode() != instruction.encode() { result.folded += 1; }
+        if let Some(cell) = dead_store(&new, &analysis, options).filter(|_| options.remove_dead_stores) {
+            new = IntcodeInstruction::JumpIfTrue{ predicate: immediate(1), target: immediate((address + instruction.length()) as i64) };
+            result.dead_stores += 1;
+            result.dead_cells.push(cell);
+        }
+        rewritten.insert(address, new);
+    }
+
+    // Jump threading, looking through the rewritten instructions
+    let current = |address: usize| rewritten.get(&address).or_else(|| analysis.instructions.get(&address));
+    let mut threaded: BTreeMap<usize, IntcodeInstruction> = BTreeMap::new();
+    for (&address, instruction) in &rewritten {
+        let (predicate, target, nonzero) = match instruction {
+            IntcodeInstruction::JumpIfTrue{predicate, target} => (predicate, target, true),
+            IntcodeInstruction::JumpIfFalse{predicate, target} => (predicate, target, false),
+            _ => continue,
+        };
+        if target.mode != ParameterMode::Immediate { continue }
+
+        // Follow the chain, stopping if it loops or reaches a jump that's modified at runtime
+        let mut end = target.param;
+        let mut seen = HashSet::new();
+        while end >= 0 && seen.insert(end) {
+            let next = current(end as usize)
+                .filter(|jump| analysis.can_rewrite(end as usize, jump))
+                .and_then(unconditional_target);
+            match next {
+                Some(next) => end = next,
+                None => break,
+            }
+        }
+        if end != target.param {
+            let target = immediate(end);
+            let predicate = predicate.clone();
+            threaded.insert(address, if nonzero {
+                IntcodeInstruction::JumpIfTrue{ predicate, target }
+            } else {
+                IntcodeInstruction::JumpIfFalse{ predicate, target }
+            });
+            result.jumps_threaded += 1;
+        }
+    }
+    rewritten.extend(threaded);
+    result.dead_cells.sort_unstable();
+    result.dead_cells.dedup();
+
+    for (address, instruction) in rewritten {
+        let original_length = analysis.instructions[&address].length();
+        let mut words = instruction.encode();
+        words.resize(original_length, 0);
+        result.memory[address..address + original_length].copy_from_slice(&words);
+    }
+    Ok(result)
+}
+
+// Runs an image to completion, returning its outputs, how it stopped and the machine
+fn run(memory: &[i64], inputs: &[i64], budget: usize) -> (Vec<i64>, String, IntcodeProgram) {
+    let mut program = IntcodeProgram::from_memory(memory.to_vec());
+    for &input in inputs { program.give_input(input); }
+    let mut outputs = vec![];
+    let end = program.timed(|program| {
+        for _ in 0..budget {
+            let end = match program.step() {
+                Ok(Some(Event::ProducedOutput)) => {
+                    outputs.extend(program.get_output());
+                    continue
+                },
+                Ok(Some(Event::InputRequired)) | Ok(Some(Event::PeerClosed)) => String::from("needs more input"),
+                Ok(Some(Event::Exited)) => String::from("exited"),
+                Ok(None) => continue,
+                Err(e) => format!("failed: {}", e),
+            };
+            return end
+        }
+        String::from("exhausted the budget")
+    });
+    (outputs, end, program)
+}
+
+// The first cell, in the image or beyond it, that two finished runs leave holding different
+// values. Cells the images start out with different values in (rewritten instructions) and
+// `ignored` cells aren't compared.
+fn memory_difference(original: &[i64], optimized: &[i64], ignored: &[usize],
+                     expected: &IntcodeProgram, actual: &IntcodeProgram) -> Option<usize> {
+    let image = (0..original.len().max(optimized.len())).filter(|&a| original.get(a) == optimized.get(a));
+    let extended: BTreeSet<usize> = expected.extended_cells().into_iter().chain(actual.extended_cells())
+        .map(|(address, _)| address).collect();
+    image.chain(extended).find(|a| !ignored.contains(a) && expected.load_position(*a) != actual.load_position(*a))
+}
+
+// Runs both images on each set of inputs, failing if their outputs, endings or final memory
+// differ. `ignored` lists cells allowed to end differently, such as Optimized::dead_cells.
+pub fn differential_check(original: &[i64], optimized: &[i64], ignored: &[usize], input_sets: &[Vec<i64>],
+                          budget: usize) -> Result<()> {
+    for (idx, inputs) in input_sets.iter().enumerate() {
+        let (expected, expected_end, expected_machine) = run(original, inputs, budget);
+        let (actual, actual_end, actual_machine) = run(optimized, inputs, budget);
+        if expected != actual || expected_end != actual_end {
+            return Err(From::from(format!(
+                "Input set {} differs: original output {:?} and {}, optimised output {:?} and {}",
+                idx, expected, expected_end, actual, actual_end)));
+        }
+        if let Some(address) = memory_difference(original, optimized, ignored, &expected_machine, &actual_machine) {
+            return Err(From::from(format!(
+                "Input set {} differs: cell {} ends as {} in the original and {} optimised",
+                idx, address, expected_machine.load_position(address), actual_machine.load_position(address))));
+        }
+    }
+    Ok(())
+}
@@ -1,33 +1,58 @@
 use super::io;
+use super::loops::{InfiniteLoop, LoopDetector, MachineState};
+use super::protect::{MemoryProtection, Protection};
+use super::stats::Stats;
+use super::strict;
+use super::taint::{Taint, TaintEffect, TaintTracker};
 use std::collections::HashMap;
+use std::ops::Range;
+use std::time::Instant;
 
 type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
 
+const MAX_ADDRESS: usize = i64::MAX as usize;
+// Most cells read_range will copy out at once
+const MAX_RANGE_LEN: usize = 1 << 24;
+
 #[derive(PartialEq)]
 pub enum Event {
     InputRequired,
     ProducedOutput,
     Exited,
+    // A channel peer has gone: no more input will arrive, or an output couldn't be delivered
+    PeerClosed,
+}
+
+// How IntcodeProgram::call passes arguments to a routine and gets its result back
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Convention {
+    // The assembler's `call`: arguments are pushed onto the relative-base stack followed by
+    // the return address, and the result is left in the last argument's slot. A routine
+    // taking no arguments gets one zeroed slot for its result.
+    Assembler,
+    // The compiler's: the return address is at [rb + 0] with the arguments above it, and
+    // the result is left in the return cell at this address (compiler::Compiled::return_cell)
+    Compiled(usize),
 }
 
-#[derive(Copy, Clone, Debug)]
-enum ParameterMode {
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ParameterMode {
     Position,
     Immediate,
     Relative
 }
 
 #[derive(Clone, Debug)]
-struct Parameter {
-    param: i64,
-    mode: ParameterMode,
+pub struct Parameter {
+    pub param: i64,
+    pub mode: ParameterMode,
 }
 
 impl Parameter {
     fn new(param: i64, mode: ParameterMode) -> Parameter {
         Parameter {
-            param: param,
-            mode: mode,
+            param,
+            mode,
         }
     }
 }
@@ -42,8 +67,8 @@ impl std::fmt::Display for Parameter {
     }
 }
 
-#[derive(Debug)]
-enum IntcodeInstruction {
+#[derive(Clone, Debug)]
+pub enum IntcodeInstruction {
     Add { o1: Parameter, o2: Parameter, dest: Parameter },
     Mul { o1: Parameter, o2: Parameter, dest: Parameter },
     LoadInput { dest: Parameter },
@@ -63,7 +88,7 @@ impl std::fmt::Display for IntcodeInstruction {
                 write!(f, "add: {} <- {} + {}", dest, o1, o2)
             },
             IntcodeInstruction::Mul{o1, o2, dest} => {
-                write!(f, "mul: {} <- {} + {}", dest, o1, o2)
+                write!(f, "mul: {} <- {} * {}", dest, o1, o2)
             },
             IntcodeInstruction::LoadInput{dest} => {
                 write!(f, "in: {}", dest)
@@ -91,6 +116,64 @@ impl std::fmt::Display for IntcodeInstruction {
     }
 }
 
+impl ParameterMode {
+    fn digit(self) -> i64 {
+        match self {
+            ParameterMode::Position => 0,
+            ParameterMode::Immediate => 1,
+            ParameterMode::Relative => 2,
+        }
+    }
+}
+
+impl IntcodeInstruction {
+    // The words the instruction is stored as, which decode back to the same instruction
+    pub fn encode(&self) -> Vec<i64> {
+        let (opcode, params) = match self {
+            IntcodeInstruction::Add{o1, o2, dest} => (1, vec![o1, o2, dest]),
+            IntcodeInstruction::Mul{o1, o2, dest} => (2, vec![o1, o2, dest]),
+            IntcodeInstruction::LoadInput{dest} => (3, vec![dest]),
+            IntcodeInstruction::Output{val} => (4, vec![val]),
+            IntcodeInstruction::JumpIfTrue{predicate, target} => (5, vec![predicate, target]),
+            IntcodeInstruction::JumpIfFalse{predicate, target} => (6, vec![predicate, target]),
+            IntcodeInstruction::LessThan{o1, o2, dest} => (7, vec![o1, o2, dest]),
+            IntcodeInstruction::Equals{o1, o2, dest} => (8, vec![o1, o2, dest]),
+            IntcodeInstruction::AdjustRelativeBase{val} => (9, vec![val]),
+            IntcodeInstruction::Exit => (99, vec![]),
+        };
+        let modes: i64 = params.iter().enumerate().map(|(i, p)| p.mode.digit() * 10i64.pow(i as u32 + 2)).sum();
+        std::iter::once(opcode + modes).chain(params.iter().map(|p| p.param)).collect()
+    }
+
+    // Number of words the instruction occupies, including the opcode
+    pub fn length(&self) -> usize {
+        match self {
+            IntcodeInstruction::Add{..} | IntcodeInstruction::Mul{..} |
+            IntcodeInstruction::LessThan{..} | IntcodeInstruction::Equals{..} => 4,
+            IntcodeInstruction::JumpIfTrue{..} | IntcodeInstruction::JumpIfFalse{..} => 3,
+            IntcodeInstruction::LoadInput{..} | IntcodeInstruction::Output{..} |
+            IntcodeInstruction::AdjustRelativeBase{..} => 2,
+            IntcodeInstruction::Exit => 1,
+        }
+    }
+
+    // Where the instruction is counted in Stats::by_opcode, with hlt at 0
+    fn stats_index(&self) -> usize {
+        match self {
+            IntcodeInstruction::Exit => 0,
+            IntcodeInstruction::Add{..} => 1,
+            IntcodeInstruction::Mul{..} => 2,
+            IntcodeInstruction::LoadInput{..} => 3,
+            IntcodeInstruction::Output{..} => 4,
+            IntcodeInstruction::JumpIfTrue{..} => 5,
+            IntcodeInstruction::JumpIfFalse{..} => 6,
+            IntcodeInstruction::LessThan{..} => 7,
+            IntcodeInstruction::Equals{..} => 8,
+            IntcodeInstruction::AdjustRelativeBase{..} => 9,
+        }
+    }
+}
+
 enum Assembly {
     Data(i64),
     Instruction(Vec<i64>, IntcodeInstruction),
@@ -105,6 +188,19 @@ impl std::fmt::Display for Assembly {
     }
 }
 
+// How often a memory cell has been read, written and executed as an opcode.
+// Relative counts the reads and writes made through relative-mode parameters.
+#[derive(Copy, Clone, Debug, Default)]
+pub struct AccessCount {
+    pub reads: u64,
+    pub writes: u64,
+    pub executions: u64,
+    pub relative: u64,
+}
+
+// A memory address and whether it was reached through the relative base
+type Access = (usize, bool);
+
 pub struct IntcodeProgram {
     memory: Vec<i64>,
     extended_memory: HashMap<usize, i64>,
@@ -112,6 +208,15 @@ pub struct IntcodeProgram {
     relative_base: i64,
     input: Box<dyn io::InputDevice + Send>,
     output: Box<dyn io::OutputDevice + Send>,
+    trace: bool,
+    access_counts: Option<HashMap<usize, AccessCount>>,
+    taint: Option<TaintTracker>,
+    protection: Option<MemoryProtection>,
+    loop_detector: Option<LoopDetector>,
+    stats: Stats,
+    strict: bool,
+    // Whether the last step ran hlt, so stepping the exited machine again doesn't recount it
+    exited: bool,
 }
 
 fn instruction_param_length(opcode: i64) -> Result<usize> {
@@ -131,7 +236,7 @@ fn instruction_param_length(opcode: i64) -> Result<usize> {
 }
 
 impl IntcodeProgram {
-    pub fn raw_to_memory(raw: &String) -> Result<Vec<i64>> {
+    pub fn raw_to_memory(raw: &str) -> Result<Vec<i64>> {
         raw.split(",").map(|item| {
             item.parse::<i64>().map_err(|_| {
                 From::from(format!("Invalid integer given: {}", item))
@@ -139,7 +244,7 @@ impl IntcodeProgram {
         }).collect()
     }
 
-    pub fn from_raw_input(input: &String) -> Result<IntcodeProgram> {
+    pub fn from_raw_input(input: &str) -> Result<IntcodeProgram> {
         Ok(IntcodeProgram::from_memory(
             IntcodeProgram::raw_to_memory(input)?
         ))
@@ -147,24 +252,136 @@ impl IntcodeProgram {
 
     pub fn from_memory(memory: Vec<i64>) -> IntcodeProgram {
         IntcodeProgram{
-            memory: memory,
+            memory,
             extended_memory: HashMap::new(),
             ip: 0,
             relative_base: 0,
             input: io::DefaultInputDevice::new(),
             output: io::DefaultOutputDevice::new(),
+            trace: false,
+            access_counts: None,
+            taint: None,
+            protection: None,
+            loop_detector: None,
+            stats: Stats::new(),
+            strict: false,
+            exited: false,
+        }
+    }
+
+    // Returns a copy of this machine's state (memory, ip and relative base)
+    // attached to fresh default devices. Strict decoding, memory protection, taint
+    // and loop detection carry over, so the copy faults and reports like the original.
+    // Buffered input and output and tracing aren't copied, and the copy's statistics
+    // and access counts start from zero, apart from the extended memory it already holds.
+    pub fn fork(&self) -> IntcodeProgram {
+        IntcodeProgram{
+            memory: self.memory.clone(),
+            extended_memory: self.extended_memory.clone(),
+            ip: self.ip,
+            relative_base: self.relative_base,
+            input: io::DefaultInputDevice::new(),
+            output: io::DefaultOutputDevice::new(),
+            trace: false,
+            access_counts: self.access_counts.as_ref().map(|_| HashMap::new()),
+            taint: self.taint.clone(),
+            protection: self.protection.clone(),
+            loop_detector: self.loop_detector.clone(),
+            stats: Stats{ peak_extended_memory: self.extended_memory.len(), ..Stats::new() },
+            strict: self.strict,
+            exited: self.exited,
         }
     }
 
+    pub fn ip(&self) -> usize { self.ip }
+    pub fn relative_base(&self) -> i64 { self.relative_base }
+    pub fn image_len(&self) -> usize { self.memory.len() }
+
+    // Programs can only form addresses that fit in a (non-negative) i64
+    fn check_address(address: usize) -> Result<()> {
+        if address > MAX_ADDRESS {
+            return Err(From::from(format!("Address {} is out of range", address)))
+        }
+        Ok(())
+    }
+
+    fn check_range(range: &Range<usize>) -> Result<()> {
+        if range.start > range.end {
+            return Err(From::from(format!("Invalid memory range {}..{}", range.start, range.end)))
+        }
+        IntcodeProgram::check_address(range.end.saturating_sub(1))
+    }
+
+    pub fn set_ip(&mut self, ip: usize) -> Result<()> {
+        IntcodeProgram::check_address(ip)?;
+        self.ip = ip;
+        self.exited = false;
+        Ok(())
+    }
+
+    // Programs can move the relative base below 0, but a host setting it there is a mistake
+    pub fn set_relative_base(&mut self, relative_base: i64) -> Result<()> {
+        if relative_base < 0 {
+            return Err(From::from(format!("Relative base {} is out of range", relative_base)))
+        }
+        self.relative_base = relative_base;
+        Ok(())
+    }
+
+    // Rejects invalid mode digits, immediate-mode destinations and extra opcode digits
+    // with a strict::DecodeFault rather than decoding them leniently
+    pub fn set_strict(&mut self, strict: bool) { self.strict = strict }
+
+    // Reads any cell, in the image or the extended memory beyond it
+    pub fn read(&self, address: usize) -> Result<i64> {
+        IntcodeProgram::check_address(address)?;
+        Ok(self.load_position(address))
+    }
+
+    // Writes any cell, in the image or the extended memory beyond it
+    pub fn write(&mut self, address: usize, value: i64) -> Result<()> {
+        IntcodeProgram::check_address(address)?;
+        self.store_position(address, value);
+        Ok(())
+    }
+
+    // Copies out a range of cells, which may extend past the image
+    pub fn read_range(&self, range: Range<usize>) -> Result<Vec<i64>> {
+        IntcodeProgram::check_range(&range)?;
+        if range.len() > MAX_RANGE_LEN {
+            return Err(From::from(format!("Range {}..{} is longer than {} cells", range.start, range.end, MAX_RANGE_LEN)))
+        }
+        Ok(range.map(|address| self.load_position(address)).collect())
+    }
+
+    // Borrows a range of cells inside the image
+    pub fn image_slice(&self, range: Range<usize>) -> Result<&[i64]> {
+        IntcodeProgram::check_range(&range)?;
+        if range.end > self.memory.len() {
+            return Err(From::from(format!("Range {}..{} extends past the image of {} cells",
+                range.start, range.end, self.memory.len())))
+        }
+        Ok(&self.memory[range])
+    }
+
+    // Non-zero cells beyond the image, in address order
+    pub fn extended_cells(&self) -> Vec<(usize, i64)> {
+        let mut cells: Vec<(usize, i64)> = self.extended_memory.iter()
+            .filter(|(_, &value)| value != 0).map(|(&address, &value)| (address, value)).collect();
+        cells.sort_unstable();
+        cells
+    }
+
     pub fn load_position(&self, location: usize) -> i64 {
         if location >= self.memory.len() {
             *self.extended_memory.get(&location).unwrap_or(&0)
         } else {
-            self.memory[location as usize]
+            self.memory[location]
         }
     }
 
-    fn load(&self, p: Parameter) -> i64 {
+    // An operand's value without counting it as an access
+    fn value(&self, p: Parameter) -> i64 {
         match p.mode {
             ParameterMode::Position => self.load_position(p.param as usize),
             ParameterMode::Immediate => p.param,
@@ -172,14 +389,32 @@ impl IntcodeProgram {
         }
     }
 
+    fn load(&mut self, p: Parameter) -> i64 {
+        match p.mode {
+            ParameterMode::Position => self.touch(p.param as usize),
+            ParameterMode::Relative => self.touch((p.param + self.relative_base) as usize),
+            ParameterMode::Immediate => (),
+        }
+        self.value(p)
+    }
+
     fn store(&mut self, p: Parameter, value: i64) {
         let location = match p.mode {
             ParameterMode::Relative => (p.param + self.relative_base) as usize,
             _ => p.param as usize,
         };
+        self.touch(location);
+        self.store_position(location, value)
+    }
 
+    fn touch(&mut self, location: usize) {
+        self.stats.highest_address = self.stats.highest_address.max(location);
+    }
+
+    fn store_position(&mut self, location: usize, value: i64) {
         if location >= self.memory.len() {
             self.extended_memory.insert(location, value);
+            self.stats.peak_extended_memory = self.stats.peak_extended_memory.max(self.extended_memory.len());
         } else {
             self.memory[location] = value;
         }
@@ -188,7 +423,14 @@ impl IntcodeProgram {
     // Returns the next instruction and increments the instruction
     // pointer to the subsequent yet-unfetched one, or returns error
     fn get_instruction(&mut self) -> Result<IntcodeInstruction> {
-        let curr_ip = self.ip;
+        if self.strict { strict::check(self.ip, self.load_position(self.ip))?; }
+        let instruction = self.decode(self.ip)?;
+        self.ip += instruction.length();
+        Ok(instruction)
+    }
+
+    // Decodes the instruction at the given address without executing it
+    pub fn decode(&self, curr_ip: usize) -> Result<IntcodeInstruction> {
         let instruction = self.load_position(curr_ip);
         let opcode = instruction % 100;
         let num_params = instruction_param_length(opcode)?;
@@ -203,7 +445,6 @@ impl IntcodeProgram {
             }
         }).chain(std::iter::repeat(ParameterMode::Position)).take(num_params).collect();
 
-        self.ip += 1 + num_params;
         match opcode {
             1 => {
                 Ok(IntcodeInstruction::Add{
@@ -270,15 +511,19 @@ impl IntcodeProgram {
     fn execute_instruction(&mut self, instruction: IntcodeInstruction, input_break: bool) -> Result<Option<Event>> {
         match instruction {
             IntcodeInstruction::Add{o1, o2, dest} => {
-                self.store(dest, self.load(o1) + self.load(o2));
+                let value = self.load(o1) + self.load(o2);
+                self.store(dest, value);
             },
             IntcodeInstruction::Mul{o1, o2, dest} => {
-                self.store(dest, self.load(o1) * self.load(o2));
+                let value = self.load(o1) * self.load(o2);
+                self.store(dest, value);
             },
             IntcodeInstruction::LoadInput{dest} => {
                 if input_break {
                     if let Some(input) = self.input.get_maybe() {
                         self.store(dest, input);
+                    } else if self.input.is_closed() {
+                        return Ok(Some(Event::PeerClosed))
                     } else {
                         return Ok(Some(Event::InputRequired))
                     }
@@ -289,7 +534,10 @@ impl IntcodeProgram {
             },
             IntcodeInstruction::Output{val} => {
                 let output = self.load(val);
-                self.output.put(output);
+                match self.output.try_put(output) {
+                    Err(_) if input_break && self.output.is_closed() => return Ok(Some(Event::PeerClosed)),
+                    result => result?,
+                }
                 return Ok(Some(Event::ProducedOutput))
             },
             IntcodeInstruction::JumpIfTrue{predicate, target} => {
@@ -299,13 +547,16 @@ impl IntcodeProgram {
                 if self.load(predicate) == 0 { self.ip = self.load(target) as usize; }
             },
             IntcodeInstruction::LessThan{o1, o2, dest} => {
-                self.store(dest, if self.load(o1) < self.load(o2) { 1 } else { 0 })
+                let value = if self.load(o1) < self.load(o2) { 1 } else { 0 };
+                self.store(dest, value)
             },
             IntcodeInstruction::Equals{o1, o2, dest} => {
-                self.store(dest, if self.load(o1) == self.load(o2) { 1 } else { 0 })
+                let value = if self.load(o1) == self.load(o2) { 1 } else { 0 };
+                self.store(dest, value)
             },
             IntcodeInstruction::AdjustRelativeBase{val} => {
                 self.relative_base += self.load(val);
+                self.stats.max_relative_base = self.stats.max_relative_base.max(self.relative_base);
             },
             IntcodeInstruction::Exit => return Ok(Some(Event::Exited)),
         }
@@ -313,25 +564,312 @@ impl IntcodeProgram {
         Ok(None)
     }
 
-    pub fn execute(&mut self) -> Result<()> {
-        loop {
-            let instruction = self.get_instruction()?;
-            if let Some(Event::Exited) = self.execute_instruction(instruction, false)? { return Ok(()) }
-        }
+    fn raw_instruction(&self, location: usize) -> Result<Vec<i64>> {
+        let length = 1 + instruction_param_length(self.load_position(location) % 100)?;
+        Ok((location..(location + length)).map(|i| self.load_position(i)).collect())
     }
 
-    pub fn execute_until_event(&mut self) -> Result<Event> {
-        loop {
-            let curr_ip = self.ip;
-            let instruction = self.get_instruction()?;
-            if let Some(event) = self.execute_instruction(instruction, true)? {
-                match event {
-                    Event::ProducedOutput => (),
-                    _ => self.ip = curr_ip // Keep program at same instruction for input/exit
+    fn step_with(&mut self, input_break: bool) -> Result<Option<Event>> {
+        let curr_ip = self.ip;
+        if let Some(protection) = &self.protection { protection.check_execute(curr_ip, curr_ip..curr_ip + 1)?; }
+        let instruction = self.get_instruction()?;
+        let words = curr_ip..curr_ip + instruction.length();
+        let writes = self.protection.as_ref().map(|_| self.accesses(&instruction).1);
+        if let (Some(protection), Some(writes)) = (&self.protection, &writes) {
+            let checked = protection.check_execute(curr_ip, words.start + 1..words.end)
+                .and_then(|_| writes.iter().try_for_each(|&(address, _)| protection.check_write(curr_ip, address)));
+            if let Err(fault) = checked {
+                self.ip = curr_ip;
+                return Err(Box::new(fault))
+            }
+        }
+        if self.trace {
+            println!("{:>10} : {} (rb = {})", curr_ip, Assembly::Instruction(
+                self.raw_instruction(curr_ip)?, instruction.clone()), self.relative_base);
+        }
+
+        let does_io = matches!(instruction, IntcodeInstruction::LoadInput{..} | IntcodeInstruction::Output{..});
+        let output_open = input_break && matches!(instruction, IntcodeInstruction::Output{..}) && !self.output.is_closed();
+        let counter = instruction.stats_index();
+        let accesses = self.access_counts.as_ref().map(|_| self.accesses(&instruction));
+        let taint = self.taint.as_ref().map(|tracker| self.taint_effect(tracker, &instruction));
+        let event = self.execute_instruction(instruction, input_break)?;
+        if let (Some(tracker), Some(effect)) = (self.taint.as_mut(), taint) {
+            if event.is_none() || event == Some(Event::ProducedOutput) { tracker.apply(effect); }
+        }
+        if let (Some(protection), Some(writes)) = (self.protection.as_mut(), writes) {
+            // Nothing was stored if the instruction is waiting on a peer
+            if event != Some(Event::InputRequired) && event != Some(Event::PeerClosed) {
+                protection.record_execute(curr_ip, words);
+                for (location, _) in writes { protection.record_write(curr_ip, location); }
+            }
+        }
+        if let (Some(counts), Some((reads, writes))) = (self.access_counts.as_mut(), accesses) {
+            // An input request doesn't execute the instruction, it'll be retried
+            if event != Some(Event::InputRequired) {
+                counts.entry(curr_ip).or_default().executions += 1;
+                for (location, relative) in reads {
+                    let count = counts.entry(location).or_default();
+                    count.reads += 1;
+                    if relative { count.relative += 1; }
+                }
+                for (location, relative) in writes {
+                    let count = counts.entry(location).or_default();
+                    count.writes += 1;
+                    if relative { count.relative += 1; }
                 }
-                return Ok(event)
             }
         }
+        match event {
+            Some(Event::InputRequired) | Some(Event::PeerClosed) => {
+                self.ip = curr_ip // Keep program at same instruction for input
+            },
+            Some(Event::Exited) => {
+                self.ip = curr_ip; // Keep program at same instruction for exit
+                if !self.exited { self.stats.by_opcode[counter] += 1; }
+            },
+            _ => self.stats.by_opcode[counter] += 1,
+        }
+        self.exited = event == Some(Event::Exited);
+        if let Some(mut detector) = self.loop_detector.take() {
+            let result = if does_io || event.is_some() {
+                detector.reset();
+                Ok(())
+            } else if detector.tick() {
+                match detector.check(self.machine_state()) {
+                    Some(period) => Err(self.describe_loop(detector.executed(), period)),
+                    None => Ok(()),
+                }
+            } else {
+                Ok(())
+            };
+            self.loop_detector = Some(detector);
+            result?;
+        }
+        // Under Drop and Buffer the output is still taken, so the instruction has run, but the
+        // driver hears once that the peer has gone
+        if output_open && event == Some(Event::ProducedOutput) && self.output.is_closed() {
+            return Ok(Some(Event::PeerClosed))
+        }
+        Ok(event)
+    }
+
+    fn machine_state(&self) -> MachineState {
+        MachineState{
+            ip: self.ip,
+            relative_base: self.relative_base,
+            memory: self.memory.clone(),
+            extended: self.extended_cells(),
+        }
+    }
+
+    // Runs a copy of the machine once round the cycle to find the addresses it covers.
+    // There's no I/O in the cycle, so the copy's devices are never used.
+    fn describe_loop(&self, executed: u64, period: u64) -> Box<dyn std::error::Error> {
+        let mut copy = self.fork();
+        let (mut lowest, mut highest) = (self.ip, self.ip);
+        for _ in 0..period {
+            if copy.step().is_err() { break }
+            lowest = lowest.min(copy.ip);
+            highest = highest.max(copy.ip);
+        }
+        Box::new(InfiniteLoop{ detected_after: executed, period, lowest, highest })
+    }
+
+    // Address a parameter refers to, along with whether it's relative, or None if immediate
+    fn address_of(&self, p: &Parameter) -> Option<Access> {
+        match p.mode {
+            ParameterMode::Position => Some((p.param as usize, false)),
+            ParameterMode::Immediate => None,
+            ParameterMode::Relative => Some(((p.param + self.relative_base) as usize, true)),
+        }
+    }
+
+    // Cells the instruction will read and write, computed before it executes
+    fn accesses(&self, instruction: &IntcodeInstruction) -> (Vec<Access>, Vec<Access>) {
+        let (reads, writes) = match instruction {
+            IntcodeInstruction::Add{o1, o2, dest} | IntcodeInstruction::Mul{o1, o2, dest} |
+            IntcodeInstruction::LessThan{o1, o2, dest} | IntcodeInstruction::Equals{o1, o2, dest} => (vec![o1, o2], vec![dest]),
+            IntcodeInstruction::JumpIfTrue{predicate, target} | IntcodeInstruction::JumpIfFalse{predicate, target} => (vec![predicate, target], vec![]),
+            IntcodeInstruction::LoadInput{dest} => (vec![], vec![dest]),
+            IntcodeInstruction::Output{val} | IntcodeInstruction::AdjustRelativeBase{val} => (vec![val], vec![]),
+            IntcodeInstruction::Exit => (vec![], vec![]),
+        };
+        (reads.into_iter().filter_map(|p| self.address_of(p)).collect(),
+         writes.into_iter().filter_map(|p| self.address_of(p)).collect())
+    }
+
+    // How the instruction will change the shadow memory, computed before it executes
+    fn taint_effect(&self, tracker: &TaintTracker, instruction: &IntcodeInstruction) -> TaintEffect {
+        let taint_of = |p: &Parameter| self.address_of(p).map_or_else(Taint::new, |(address, _)| tracker.cell(address));
+        let dest_of = |p: &Parameter| self.address_of(p).map_or(p.param as usize, |(address, _)| address);
+        match instruction {
+            IntcodeInstruction::Add{o1, o2, dest} | IntcodeInstruction::Mul{o1, o2, dest} |
+            IntcodeInstruction::LessThan{o1, o2, dest} | IntcodeInstruction::Equals{o1, o2, dest} => {
+                TaintEffect::Store(dest_of(dest), taint_of(o1).union(&taint_of(o2)).cloned().collect())
+            },
+            IntcodeInstruction::LoadInput{dest} => TaintEffect::Input(dest_of(dest)),
+            IntcodeInstruction::Output{val} => TaintEffect::Output(self.value(val.clone()), taint_of(val)),
+            _ => TaintEffect::Nothing,
+        }
+    }
+
+    // Starts (or stops) tracking which inputs each value depends on
+    pub fn track_taint(&mut self, enabled: bool) {
+        self.taint = if enabled { Some(TaintTracker::new()) } else { None };
+    }
+
+    pub fn taint(&self) -> Option<&TaintTracker> {
+        self.taint.as_ref()
+    }
+
+    // Treats a cell's current value as a taint source, starting tracking if needed
+    pub fn taint_cell(&mut self, address: usize) {
+        self.taint.get_or_insert_with(TaintTracker::new).seed_cell(address);
+    }
+
+    // Starts remembering stored cells to report instructions executed from them, keeping
+    // any protected regions. Stopping drops the regions along with the report.
+    pub fn detect_self_modification(&mut self, enabled: bool) {
+        if enabled {
+            self.protection.get_or_insert_with(MemoryProtection::new);
+        } else {
+            self.protection = None;
+        }
+    }
+
+    // Faults on stores to or fetches from the range as the protection dictates,
+    // starting self-modification detection if needed
+    pub fn protect(&mut self, range: Range<usize>, protection: Protection) {
+        self.protection.get_or_insert_with(MemoryProtection::new).protect(range, protection);
+    }
+
+    pub fn memory_protection(&self) -> Option<&MemoryProtection> {
+        self.protection.as_ref()
+    }
+
+    // Starts (or stops) checking every `interval` instructions whether the machine is
+    // stuck in a loop without I/O, failing with an InfiniteLoop error if so
+    pub fn detect_loops(&mut self, interval: Option<usize>) {
+        self.loop_detector = interval.map(LoopDetector::new);
+    }
+
+    // Starts (or stops) counting reads, writes and executions of each memory cell
+    pub fn count_accesses(&mut self, enabled: bool) {
+        self.access_counts = if enabled { Some(HashMap::new()) } else { None };
+    }
+
+    pub fn access_counts(&self) -> Option<&HashMap<usize, AccessCount>> {
+        self.access_counts.as_ref()
+    }
+
+    // Executes a single instruction, stopping at input requests and exits like execute_until_event
+    pub fn step(&mut self) -> Result<Option<Event>> {
+        self.step_with(true)
+    }
+
+    pub fn execute(&mut self) -> Result<()> {
+        let start = Instant::now();
+        let result = loop {
+            match self.step_with(false) {
+                Ok(Some(Event::Exited)) => break Ok(()),
+                Ok(_) => (),
+                Err(e) => break Err(e),
+            }
+        };
+        self.stats.elapsed += start.elapsed();
+        result
+    }
+
+    // Inputs and outputs are the in and out counters, so they're only filled in here
+    pub fn stats(&self) -> Stats {
+        let mut stats = self.stats.clone();
+        stats.inputs = stats.by_opcode[3];
+        stats.outputs = stats.by_opcode[4];
+        stats
+    }
+
+    // Extended memory already in use counts towards the new peak
+    pub fn reset_stats(&mut self) {
+        self.stats = Stats{ peak_extended_memory: self.extended_memory.len(), ..Stats::new() };
+    }
+
+    // Calls the routine at `address` following the given convention, with a return address
+    // that's never a real instruction, and runs it until it returns there. The ip and relative
+    // base are restored afterwards, even on failure, but memory changes are kept. For the
+    // assembler's convention the stack starts at the relative base, which points at the next
+    // free slot, or just past the image if it's still 0 as in a program that hasn't started.
+    // Compiled functions don't say how big their frames are, so theirs starts past every cell
+    // in use, and the return cell is restored in case the caller hadn't read it yet.
+    pub fn call(&mut self, address: usize, args: &[i64], convention: Convention) -> Result<i64> {
+        const SENTINEL: i64 = -1;
+        let start = Instant::now();
+        let (saved_ip, saved_relative_base) = (self.ip, self.relative_base);
+        let (result_cell, saved_result) = match convention {
+            Convention::Assembler => {
+                let stack = if self.relative_base == 0 { self.memory.len() as i64 } else { self.relative_base };
+                let slots = if args.is_empty() { vec![0] } else { args.to_vec() };
+                for (offset, &value) in slots.iter().enumerate() {
+                    self.store_position((stack + offset as i64) as usize, value);
+                }
+                let result_slot = (stack + slots.len() as i64 - 1) as usize;
+                self.store_position(result_slot + 1, SENTINEL);
+                self.relative_base = result_slot as i64 + 2;
+                (result_slot, None)
+            },
+            Convention::Compiled(return_cell) => {
+                let stack = self.extended_memory.keys().map(|&address| address + 1).fold(self.memory.len(), usize::max);
+                self.store_position(stack, SENTINEL);
+                for (offset, &value) in args.iter().enumerate() {
+                    self.store_position(stack + 1 + offset, value);
+                }
+                self.relative_base = stack as i64;
+                (return_cell, Some(self.load_position(return_cell)))
+            },
+        };
+        self.ip = address;
+
+        let result = loop {
+            if self.ip == SENTINEL as usize { break Ok(self.load_position(result_cell)) }
+            match self.step_with(false) {
+                Ok(Some(Event::Exited)) => break Err(From::from(format!("Program exited at {} before the routine returned", self.ip))),
+                Ok(_) => (),
+                Err(e) => break Err(e),
+            }
+        };
+        if let Some(value) = saved_result { self.store_position(result_cell, value); }
+        self.ip = saved_ip;
+        self.relative_base = saved_relative_base;
+        self.stats.elapsed += start.elapsed();
+        result
+    }
+
+    // Runs `f` on the machine, adding the time it takes to the elapsed time in the statistics.
+    // For hosts driving the machine with step, which isn't timed itself; `f` shouldn't call
+    // the methods that are, or their time is counted twice.
+    pub fn timed<T, F>(&mut self, f: F) -> T where F: FnOnce(&mut IntcodeProgram) -> T {
+        let start = Instant::now();
+        let result = f(self);
+        self.stats.elapsed += start.elapsed();
+        result
+    }
+
+    pub fn execute_until_event(&mut self) -> Result<Event> {
+        let start = Instant::now();
+        let result = loop {
+            match self.step() {
+                Ok(Some(event)) => break Ok(event),
+                Ok(None) => (),
+                Err(e) => break Err(e),
+            }
+        };
+        self.stats.elapsed += start.elapsed();
+        result
+    }
+
+    // Prints each instruction as it's executed, along with the relative base
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
     }
 
     pub fn replace_input(&mut self, new: Box<dyn io::InputDevice + Send>) {
@@ -352,15 +890,10 @@ impl IntcodeProgram {
     pub fn disassemble(&mut self) {
         std::iter::repeat_with(|| {
             let curr_ip = self.ip;
-            let curr_opcode = self.load_position(curr_ip) % 100;
             if self.ip >= self.memory.len() {
                 None
             } else if let Ok(instr) = self.get_instruction() {
-                Some((curr_ip, Assembly::Instruction(
-                    (curr_ip..(curr_ip + 1 + instruction_param_length(curr_opcode).unwrap()))
-                        .map(|i| self.memory[i]).collect(),
-                    instr
-                )))
+                Some((curr_ip, Assembly::Instruction(self.raw_instruction(curr_ip).unwrap(), instr)))
             } else {
                 self.ip += 1;
                 Some((curr_ip, Assembly::Data(self.load_position(curr_ip))))
@@ -0,0 +1,145 @@
+// Memory protection regions and self-modifying code detection.
+//
+// Regions mark ranges of cells as read-only (stores fault), no-execute (fetching an
+// instruction word from them faults) or code (stores fault, and once any code region
+// exists, fetching from outside every code region faults). Later regions take priority
+// where they overlap. Independently of any regions, every cell the program stores to
+// is remembered so instructions fetched from written cells can be reported.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::ops::Range;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Protection {
+    ReadOnly,
+    NoExecute,
+    Code,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum FaultKind {
+    Write,
+    Execute,
+}
+
+// A violation of the protection policy. The program is left at the faulting instruction
+// and can be downcast from the error returned by step or execute.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Fault {
+    pub kind: FaultKind,
+    pub address: usize,
+    pub ip: usize,
+    // The region that forbade the access, or None when fetching outside every code region
+    pub region: Option<(Range<usize>, Protection)>,
+}
+
+// An instruction word that was executed after the program stored to it
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SelfModification {
+    pub address: usize,
+    // Address of the last instruction that stored to the cell before it was first executed
+    pub written_by: usize,
+    // Address of the first instruction fetched with the written cell as one of its words
+    pub executed_at: usize,
+}
+
+#[derive(Clone, Default)]
+pub struct MemoryProtection {
+    regions: Vec<(Range<usize>, Protection)>,
+    // Cell -> address of the instruction that last stored to it
+    written: HashMap<usize, usize>,
+    modifications: BTreeMap<usize, SelfModification>,
+}
+
+impl fmt::Display for Protection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Protection::ReadOnly => write!(f, "read-only"),
+            Protection::NoExecute => write!(f, "no-execute"),
+            Protection::Code => write!(f, "code"),
+        }
+    }
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let action = match self.kind {
+            FaultKind::Write => "Store to",
+            FaultKind::Execute => "Instruction fetch from",
+        };
+        match &self.region {
+            Some((range, protection)) => write!(f, "{} {} at {} violates {} region {}..{}",
+                action, self.address, self.ip, protection, range.start, range.end),
+            None => write!(f, "{} {} at {} is outside every code region", action, self.address, self.ip),
+        }
+    }
+}
+
+impl std::error::Error for Fault {}
+
+impl fmt::Display for SelfModification {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} written at {}, executed at {}", self.address, self.written_by, self.executed_at)
+    }
+}
+
+impl MemoryProtection {
+    pub fn new() -> MemoryProtection {
+        Default::default()
+    }
+
+    pub fn protect(&mut self, range: Range<usize>, protection: Protection) {
+        self.regions.push((range, protection));
+    }
+
+    pub fn regions(&self) -> &[(Range<usize>, Protection)] {
+        &self.regions
+    }
+
+    // The innermost (most recently added) region covering an address
+    pub fn region(&self, address: usize) -> Option<&(Range<usize>, Protection)> {
+        self.regions.iter().rev().find(|(range, _)| range.contains(&address))
+    }
+
+    // Instruction words executed after being written, in address order
+    pub fn self_modifications(&self) -> impl Iterator<Item = &SelfModification> {
+        self.modifications.values()
+    }
+
+    pub(crate) fn check_write(&self, ip: usize, address: usize) -> Result<(), Fault> {
+        match self.region(address) {
+            Some((range, protection)) if *protection != Protection::NoExecute => Err(Fault{
+                kind: FaultKind::Write, address, ip, region: Some((range.clone(), *protection)),
+            }),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_execute(&self, ip: usize, words: Range<usize>) -> Result<(), Fault> {
+        let has_code = self.regions.iter().any(|(_, protection)| *protection == Protection::Code);
+        for address in words {
+            match self.region(address) {
+                Some((range, Protection::NoExecute)) => return Err(Fault{
+                    kind: FaultKind::Execute, address, ip, region: Some((range.clone(), Protection::NoExecute)),
+                }),
+                Some((_, Protection::Code)) => (),
+                _ if has_code => return Err(Fault{ kind: FaultKind::Execute, address, ip, region: None }),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn record_write(&mut self, ip: usize, address: usize) {
+        self.written.insert(address, ip);
+    }
+
+    pub(crate) fn record_execute(&mut self, ip: usize, words: Range<usize>) {
+        for address in words {
+            if let Some(&written_by) = self.written.get(&address) {
+                self.modifications.entry(address).or_insert(SelfModification{ address, written_by, executed_at: ip });
+            }
+        }
+    }
+}
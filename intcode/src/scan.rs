@@ -0,0 +1,124 @@
+// Cheat-style memory scanner for finding where a running program keeps its state.
+//
+//     let mut scanner = Scanner::new(&program);   // every cell is a candidate
+//     ... run until the score changes ...
+//     scanner.scan(&program, Filter::Increased);
+//     ... run until a frame where the score stays the same ...
+//     scanner.scan(&program, Filter::Unchanged);
+//     scanner.scan(&program, Filter::Equals(score));
+//
+// Each scan takes a snapshot and keeps the candidate addresses whose value passes the
+// filter, comparing against the previous snapshot. Candidates start as every cell of the
+// image plus any non-zero extended memory, and every scan also considers extended cells
+// that first become non-zero since the one before, so state the program only sets up
+// later can still be found. Such a cell held 0 in every earlier snapshot, so it's only
+// considered while a cell staying at 0 would have passed every earlier filter.
+
+use super::program::IntcodeProgram;
+use std::collections::{BTreeMap, BTreeSet};
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Filter {
+    Equals(i64),
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+    // The value changed by exactly this much, which may be negative
+    IncreasedBy(i64),
+}
+
+// Every cell's value as of a scan, for the next scan to compare against
+#[derive(Clone, Debug)]
+pub struct MemorySnapshot {
+    image: Vec<i64>,
+    extended: BTreeMap<usize, i64>,
+}
+
+pub struct Scanner {
+    previous: MemorySnapshot,
+    // None until the first scan, meaning every address in a snapshot
+    candidates: Option<Vec<usize>>,
+    // Every address present in a snapshot so far, so ones eliminated aren't added back
+    seen: BTreeSet<usize>,
+    // Whether a cell that's stayed at 0 throughout would still be a candidate
+    zero_matches: bool,
+}
+
+impl MemorySnapshot {
+    pub fn capture(program: &IntcodeProgram) -> MemorySnapshot {
+        MemorySnapshot{
+            image: program.image_slice(0..program.image_len()).unwrap().to_vec(),
+            extended: program.extended_cells().into_iter().collect(),
+        }
+    }
+
+    pub fn get(&self, address: usize) -> i64 {
+        match self.image.get(address) {
+            Some(&value) => value,
+            None => *self.extended.get(&address).unwrap_or(&0),
+        }
+    }
+
+    fn addresses(&self) -> impl Iterator<Item = usize> + '_ {
+        (0..self.image.len()).chain(self.extended.keys().cloned())
+    }
+}
+
+impl Filter {
+    pub fn matches(self, before: i64, after: i64) -> bool {
+        match self {
+            Filter::Equals(value) => after == value,
+            Filter::Changed => after != before,
+            Filter::Unchanged => after == before,
+            Filter::Increased => after > before,
+            Filter::Decreased => after < before,
+            Filter::IncreasedBy(delta) => after.checked_sub(before) == Some(delta),
+        }
+    }
+}
+
+impl Scanner {
+    pub fn new(program: &IntcodeProgram) -> Scanner {
+        let previous = MemorySnapshot::capture(program);
+        let seen = previous.addresses().collect();
+        Scanner{ previous, candidates: None, seen, zero_matches: true }
+    }
+
+    // Narrows the candidates to those passing the filter since the last scan, returning how many remain
+    pub fn scan(&mut self, program: &IntcodeProgram, filter: Filter) -> usize {
+        let current = MemorySnapshot::capture(program);
+        let mut addresses: Vec<usize> = match self.candidates.take() {
+            Some(candidates) => candidates,
+            None => self.seen.iter().cloned().collect(),
+        };
+        // Cells appearing for the first time join the candidates from either snapshot
+        for address in current.addresses() {
+            if self.seen.insert(address) && self.zero_matches { addresses.push(address); }
+        }
+        self.zero_matches &= filter.matches(0, 0);
+        addresses.sort_unstable();
+        let remaining: Vec<usize> = addresses.into_iter()
+            .filter(|&address| filter.matches(self.previous.get(address), current.get(address)))
+            .collect();
+        let count = remaining.len();
+        self.candidates = Some(remaining);
+        self.previous = current;
+        count
+    }
+
+    // Addresses still matching every scan, in order, or None before the first scan
+    pub fn candidates(&self) -> Option<&[usize]> {
+        self.candidates.as_deref()
+    }
+
+    // Current values of the remaining candidates
+    pub fn values(&self, program: &IntcodeProgram) -> Vec<(usize, i64)> {
+        self.candidates.iter().flatten().map(|&address| (address, program.load_position(address))).collect()
+    }
+
+    // Starts again with every address as a candidate
+    pub fn reset(&mut self, program: &IntcodeProgram) {
+        *self = Scanner::new(program);
+    }
+}
@@ -0,0 +1,342 @@
+// Runs many machines on a small pool of worker threads.
+//
+// Each machine runs for up to a quantum of instructions at a time before going to the
+// back of the run queue. A machine waiting on an empty mailbox is parked until a value is
+// sent to it; one reading from any other device is polled again whenever the workers run
+// out of work, and is reported as blocked if its device stays empty for MAX_IDLE_POLLING.
+// Once nothing is runnable or running and no sender outside the scheduler
+// is alive, machines still parked can never run again and are reported as blocked. Senders
+// from outside are a separate type that can't be a machine's device, since a machine
+// holding one would keep its peers from ever being reported as blocked.
+
+use super::io::{InputDevice, OutputDevice};
+use super::program::{Event, IntcodeProgram};
+use std::collections::VecDeque;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+// How long a polled machine may go on finding no input before it's given up on
+const MAX_IDLE_POLLING: Duration = Duration::from_secs(1);
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    Exited,
+    // One of the machine's devices reported that its peer had gone
+    PeerClosed,
+    // Parked on an empty mailbox when nothing could send to it any more, or polled for
+    // MAX_IDLE_POLLING without its device producing any input
+    Blocked,
+    Failed(String),
+}
+
+// How a machine finished, and the machine itself so its memory and outputs can be inspected
+pub struct Report {
+    pub outcome: Outcome,
+    pub executed: u64,
+    pub program: IntcodeProgram,
+    // Values sent to the machine's mailbox that it never read
+    pub unread: Vec<i64>,
+}
+
+#[derive(Copy, Clone, PartialEq)]
+enum Status {
+    Runnable,
+    Running,
+    // Waiting for a value to be sent to its mailbox
+    Parked,
+    // Waiting on some other device, retried when the workers are idle
+    Polled,
+    Done,
+}
+
+type Queue = Arc<Mutex<VecDeque<i64>>>;
+
+struct Slot {
+    program: Option<IntcodeProgram>,
+    status: Status,
+    // Set when a value is sent while the machine is running, so it isn't parked afterwards
+    woken: bool,
+    // When polling it started finding no input
+    idle_since: Option<Instant>,
+    executed: u64,
+    outcome: Option<Outcome>,
+    mailbox: Option<Queue>,
+}
+
+struct State {
+    slots: Vec<Slot>,
+    run_queue: VecDeque<usize>,
+    polled: Vec<usize>,
+    running: usize,
+    done: usize,
+    external_senders: usize,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+pub struct Scheduler {
+    shared: Arc<Shared>,
+    workers: usize,
+    quantum: usize,
+}
+
+// Input device of a scheduled machine, filled by its senders
+pub struct Mailbox {
+    queue: Queue,
+}
+
+// Sends values to a scheduled machine's mailbox, waking it if it's parked. Used as the
+// output device of connected machines.
+#[derive(Clone)]
+struct MailboxSender {
+    queue: Queue,
+    shared: Arc<Shared>,
+    id: usize,
+}
+
+// Sends values to a scheduled machine's mailbox from outside the scheduler. While any is
+// alive, parked machines aren't reported as blocked.
+pub struct ExternalSender {
+    sender: MailboxSender,
+}
+
+impl Scheduler {
+    // One worker per available CPU, running machines 10000 instructions at a time
+    pub fn new() -> Scheduler {
+        let workers = thread::available_parallelism().map_or(4, |n| n.get());
+        Scheduler::with_workers(workers, 10000)
+    }
+
+    pub fn with_workers(workers: usize, quantum: usize) -> Scheduler {
+        Scheduler{
+            shared: Arc::new(Shared{
+                state: Mutex::new(State{
+                    slots: vec![], run_queue: VecDeque::new(), polled: vec![], running: 0, done: 0, external_senders: 0,
+                }),
+                changed: Condvar::new(),
+            }),
+            workers: workers.max(1),
+            quantum: quantum.max(1),
+        }
+    }
+
+    fn push(&mut self, program: IntcodeProgram, mailbox: Option<Queue>) -> usize {
+        let mut state = self.shared.state.lock().unwrap();
+        let id = state.slots.len();
+        state.slots.push(Slot{ program: Some(program), status: Status::Runnable, woken: false, idle_since: None, executed: 0, outcome: None, mailbox });
+        state.run_queue.push_back(id);
+        id
+    }
+
+    // Adds a machine whose input device is replaced by a mailbox, returning its id
+    pub fn add(&mut self, mut program: IntcodeProgram) -> usize {
+        let queue: Queue = Default::default();
+        program.replace_input(Box::new(Mailbox{ queue: queue.clone() }));
+        self.push(program, Some(queue))
+    }
+
+    // Adds a machine keeping its own devices, which is polled while waiting for input
+    pub fn add_polled(&mut self, program: IntcodeProgram) -> usize {
+        self.push(program, None)
+    }
+
+    fn sender(&self, state: &State, id: usize) -> Result<MailboxSender> {
+        match state.slots.get(id).map(|slot| &slot.mailbox) {
+            Some(Some(queue)) => Ok(MailboxSender{ queue: queue.clone(), shared: self.shared.clone(), id }),
+            Some(None) => Err(From::from(format!("Machine {} has no mailbox", id))),
+            None => Err(From::from(format!("No machine {}", id))),
+        }
+    }
+
+    // Sends the outputs of machine `from` to the mailbox of machine `to`
+    pub fn connect(&mut self, from: usize, to: usize) -> Result<()> {
+        let sender = self.sender(&self.shared.state.lock().unwrap(), to)?;
+        // The replaced device is dropped outside the lock, since dropping a sender can take it
+        let mut program = match self.shared.state.lock().unwrap().slots.get_mut(from) {
+            Some(slot) => slot.program.take().unwrap(),
+            None => return Err(From::from(format!("No machine {}", from))),
+        };
+        program.replace_output(Box::new(sender));
+        self.shared.state.lock().unwrap().slots[from].program = Some(program);
+        Ok(())
+    }
+
+    // A handle for sending values to a machine from outside the scheduler, e.g. another thread
+    pub fn mailbox(&self, id: usize) -> Result<ExternalSender> {
+        let mut state = self.shared.state.lock().unwrap();
+        let sender = self.sender(&state, id)?;
+        state.external_senders += 1;
+        Ok(ExternalSender{ sender })
+    }
+
+    // Runs every machine until it exits, fails or can't make progress, reporting on each in id order
+    pub fn run(self) -> Vec<Report> {
+        let workers: Vec<thread::JoinHandle<()>> = (0..self.workers).map(|_| {
+            let shared = self.shared.clone();
+            let quantum = self.quantum;
+            thread::spawn(move || work(&shared, quantum))
+        }).collect();
+        for worker in workers { worker.join().expect("Scheduler worker panicked"); }
+
+        let mut state = self.shared.state.lock().unwrap();
+        state.slots.drain(..).map(|slot| Report{
+            outcome: slot.outcome.unwrap(),
+            executed: slot.executed,
+            program: slot.program.unwrap(),
+            unread: slot.mailbox.map_or_else(Vec::new, |queue| queue.lock().unwrap().drain(..).collect()),
+        }).collect()
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Scheduler {
+        Scheduler::new()
+    }
+}
+
+// Runs up to `quantum` instructions, returning how many executed and the event that stopped it early
+fn run_quantum(program: &mut IntcodeProgram, quantum: usize) -> (u64, std::result::Result<Option<Event>, String>) {
+    program.timed(|program| {
+        for executed in 0..quantum as u64 {
+            match program.step() {
+                Ok(None) | Ok(Some(Event::ProducedOutput)) => (),
+                Ok(Some(event)) => return (executed, Ok(Some(event))),
+                Err(e) => return (executed, Err(e.to_string())),
+            }
+        }
+        (quantum as u64, Ok(None))
+    })
+}
+
+fn finish(state: &mut State, id: usize, outcome: Outcome) {
+    state.slots[id].status = Status::Done;
+    state.slots[id].outcome = Some(outcome);
+    state.done += 1;
+}
+
+// Puts a machine back after a quantum, deciding whether it runs again, waits or is done
+fn reschedule(state: &mut State, id: usize, executed: u64, result: std::result::Result<Option<Event>, String>) {
+    let slot = &mut state.slots[id];
+    if executed > 0 { slot.idle_since = None; }
+    match result {
+        Ok(None) => {
+            slot.status = Status::Runnable;
+            state.run_queue.push_back(id);
+        },
+        Ok(Some(Event::InputRequired)) if slot.woken => {
+            slot.status = Status::Runnable;
+            state.run_queue.push_back(id);
+        },
+        Ok(Some(Event::InputRequired)) if slot.mailbox.is_some() => slot.status = Status::Parked,
+        Ok(Some(Event::InputRequired)) if slot.idle_since.get_or_insert_with(Instant::now).elapsed() >= MAX_IDLE_POLLING => {
+            finish(state, id, Outcome::Blocked)
+        },
+        Ok(Some(Event::InputRequired)) => {
+            slot.status = Status::Polled;
+            state.polled.push(id);
+        },
+        Ok(Some(Event::PeerClosed)) => finish(state, id, Outcome::PeerClosed),
+        Ok(Some(_)) => finish(state, id, Outcome::Exited),
+        Err(e) => finish(state, id, Outcome::Failed(e)),
+    }
+}
+
+fn work(shared: &Shared, quantum: usize) {
+    let mut guard = shared.state.lock().unwrap();
+    loop {
+        let state = &mut *guard;
+        if state.done == state.slots.len() {
+            shared.changed.notify_all();
+            return
+        }
+        if let Some(id) = state.run_queue.pop_front() {
+            let slot = &mut state.slots[id];
+            slot.status = Status::Running;
+            slot.woken = false;
+            let mut program = slot.program.take().unwrap();
+            state.running += 1;
+            drop(guard);
+
+            let (executed, result) = run_quantum(&mut program, quantum);
+
+            guard = shared.state.lock().unwrap();
+            let state = &mut *guard;
+            state.running -= 1;
+            state.slots[id].executed += executed;
+            state.slots[id].program = Some(program);
+            reschedule(state, id, executed, result);
+            if state.running == 0 || !state.run_queue.is_empty() { shared.changed.notify_all(); }
+        } else if !state.polled.is_empty() {
+            // Give the devices of polled machines a moment before retrying them
+            guard = shared.changed.wait_timeout(guard, Duration::from_millis(1)).unwrap().0;
+            let state = &mut *guard;
+            for id in state.polled.drain(..) {
+                state.slots[id].status = Status::Runnable;
+                state.run_queue.push_back(id);
+            }
+        } else if state.running == 0 && state.external_senders == 0 {
+            for id in 0..state.slots.len() {
+                if state.slots[id].status == Status::Parked { finish(state, id, Outcome::Blocked); }
+            }
+        } else {
+            guard = shared.changed.wait(guard).unwrap();
+        }
+    }
+}
+
+impl InputDevice for Mailbox {
+    fn put(&mut self, input: i64) { self.queue.lock().unwrap().push_back(input) }
+    fn get(&mut self) -> Result<i64> {
+        self.get_maybe().ok_or_else(|| From::from("Mailbox is empty"))
+    }
+    fn get_maybe(&mut self) -> Option<i64> {
+        self.queue.lock().unwrap().pop_front()
+    }
+}
+
+impl MailboxSender {
+    fn send(&self, value: i64) {
+        self.queue.lock().unwrap().push_back(value);
+        let mut state = self.shared.state.lock().unwrap();
+        let status = match state.slots.get_mut(self.id) {
+            Some(slot) if slot.status == Status::Running => { slot.woken = true; return },
+            Some(slot) => slot.status,
+            None => return,
+        };
+        if status == Status::Parked {
+            state.slots[self.id].status = Status::Runnable;
+            state.run_queue.push_back(self.id);
+            self.shared.changed.notify_one();
+        }
+    }
+}
+
+impl OutputDevice for MailboxSender {
+    fn put(&mut self, output: i64) { self.send(output) }
+    fn get(&mut self) -> Option<i64> { None }
+}
+
+impl ExternalSender {
+    pub fn send(&self, value: i64) { self.sender.send(value) }
+}
+
+impl Clone for ExternalSender {
+    fn clone(&self) -> ExternalSender {
+        self.sender.shared.state.lock().unwrap().external_senders += 1;
+        ExternalSender{ sender: self.sender.clone() }
+    }
+}
+
+impl Drop for ExternalSender {
+    fn drop(&mut self) {
+        self.sender.shared.state.lock().unwrap().external_senders -= 1;
+        // Idle workers may now be able to tell that parked machines are blocked
+        self.sender.shared.changed.notify_all();
+    }
+}
@@ -0,0 +1,73 @@
+// Execution statistics kept by every machine.
+//
+// Running machines only bump a counter per instruction and keep a running maximum of the
+// addresses, extended memory and relative base they use, so it's always on. Elapsed time
+// covers execute, execute_until_event, call and whatever runs under timed, since timing
+// single steps would cost more than the steps themselves.
+
+use std::fmt;
+use std::time::Duration;
+
+const MNEMONICS: [&str; 10] = ["hlt", "add", "mul", "in", "out", "jnz", "jez", "lt", "eq", "arb"];
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    // Instructions executed, indexed by opcode 1-9, with hlt counted at index 0
+    pub by_opcode: [u64; 10],
+    pub inputs: u64,
+    pub outputs: u64,
+    // Highest address an instruction has loaded from or stored to
+    pub highest_address: usize,
+    // Most extended memory cells in use at once, including any written by the host
+    pub peak_extended_memory: usize,
+    // Highest relative base reached. The base starts at 0, so this is 0 for a program that
+    // never moves it or only moves it below 0.
+    pub max_relative_base: i64,
+    pub elapsed: Duration,
+}
+
+impl Stats {
+    pub fn new() -> Stats {
+        Default::default()
+    }
+
+    pub fn executed(&self) -> u64 {
+        self.by_opcode.iter().sum()
+    }
+
+    // Instructions executed with the given opcode (99 for hlt)
+    pub fn executed_opcode(&self, opcode: i64) -> u64 {
+        match opcode {
+            1..=9 => self.by_opcode[opcode as usize],
+            99 => self.by_opcode[0],
+            _ => 0,
+        }
+    }
+
+    // Adds another run's statistics to these, e.g. to total up several machines
+    pub fn merge(&mut self, other: &Stats) {
+        for (count, other) in self.by_opcode.iter_mut().zip(other.by_opcode.iter()) { *count += other; }
+        self.inputs += other.inputs;
+        self.outputs += other.outputs;
+        self.highest_address = self.highest_address.max(other.highest_address);
+        self.peak_extended_memory = self.peak_extended_memory.max(other.peak_extended_memory);
+        self.max_relative_base = self.max_relative_base.max(other.max_relative_base);
+        self.elapsed += other.elapsed;
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let breakdown: Vec<String> = (1..10).chain(std::iter::once(0))
+            .filter(|&i| self.by_opcode[i] > 0)
+            .map(|i| format!("{} {}", MNEMONICS[i], self.by_opcode[i]))
+            .collect();
+        writeln!(f, "Instructions executed: {} ({})", self.executed(), breakdown.join(", "))?;
+        writeln!(f, "Inputs consumed: {}", self.inputs)?;
+        writeln!(f, "Outputs produced: {}", self.outputs)?;
+        writeln!(f, "Highest address in use: {}", self.highest_address)?;
+        writeln!(f, "Peak extended memory: {} cells", self.peak_extended_memory)?;
+        writeln!(f, "Maximum relative base: {}", self.max_relative_base)?;
+        write!(f, "Elapsed: {:?}", self.elapsed)
+    }
+}
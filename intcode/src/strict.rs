@@ -0,0 +1,75 @@
+// Strict decoding, which rejects instruction words the specification doesn't allow.
+//
+// By default decoding is lenient, as it always has been: any mode digit other than 0 or 1
+// means relative, an immediate-mode destination is written as if it were a position, and
+// digits beyond the last parameter's mode are ignored. Strict decoding reports each of
+// these as a fault instead, along with unknown opcodes.
+
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum DecodeFaultKind {
+    InvalidOpcode(i64),
+    // A mode digit other than 0, 1 or 2, for the given parameter (counting from 1)
+    InvalidMode { parameter: usize, digit: i64 },
+    // An immediate-mode parameter that the instruction writes to
+    ImmediateWrite { parameter: usize },
+    // Non-zero digits above the last parameter's mode, as they'd be written in the word
+    ExtraDigits(i64),
+}
+
+// An instruction word rejected by strict decoding. The program is left at the faulting
+// instruction and this can be downcast from the error returned by step or execute.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct DecodeFault {
+    pub kind: DecodeFaultKind,
+    pub address: usize,
+    pub word: i64,
+}
+
+impl fmt::Display for DecodeFault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            DecodeFaultKind::InvalidOpcode(opcode) => write!(f, "Invalid opcode {}", opcode)?,
+            DecodeFaultKind::InvalidMode{parameter, digit} =>
+                write!(f, "Invalid mode {} for parameter {}", digit, parameter)?,
+            DecodeFaultKind::ImmediateWrite{parameter} =>
+                write!(f, "Immediate mode for parameter {}, which is written to", parameter)?,
+            DecodeFaultKind::ExtraDigits(digits) =>
+                write!(f, "Extra digits {} above the parameter modes", digits)?,
+        }
+        write!(f, " in instruction {} at {}", self.word, self.address)
+    }
+}
+
+impl std::error::Error for DecodeFault {}
+
+// Checks an instruction word against the specification
+pub fn check(address: usize, word: i64) -> Result<(), DecodeFault> {
+    let fault = |kind| Err(DecodeFault{ kind, address, word });
+    let opcode = word % 100;
+    let (num_params, written) = match opcode {
+        1 | 2 | 7 | 8 => (3, Some(3)),
+        3 => (1, Some(1)),
+        4 | 9 => (1, None),
+        5 | 6 => (2, None),
+        99 => (0, None),
+        _ => return fault(DecodeFaultKind::InvalidOpcode(opcode)),
+    };
+
+    let mut modes = word / 100;
+    for parameter in 1..=num_params {
+        let digit = modes % 10;
+        if digit > 2 {
+            return fault(DecodeFaultKind::InvalidMode{ parameter, digit })
+        }
+        if digit == 1 && written == Some(parameter) {
+            return fault(DecodeFaultKind::ImmediateWrite{ parameter })
+        }
+        modes /= 10;
+    }
+    if modes != 0 {
+        return fault(DecodeFaultKind::ExtraDigits(modes * 10_i64.pow(num_params as u32 + 2)))
+    }
+    Ok(())
+}
@@ -0,0 +1,89 @@
+// Shadow memory recording which inputs (and chosen initial cells) each value was derived from.
+//
+// Every cell carries the set of sources its value depends on. Arithmetic and comparisons
+// give their destination the union of their operands' sources, an input instruction gives
+// its destination that input, and each output is logged with the sources of its value.
+// Only data flow is tracked: a value chosen by a branch on a tainted value isn't tainted,
+// and neither are cells addressed through a tainted relative base.
+
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum TaintSource {
+    // The nth input consumed, counting from 0
+    Input(usize),
+    // The initial value of a memory cell
+    Cell(usize),
+}
+
+pub type Taint = BTreeSet<TaintSource>;
+
+#[derive(Clone, Default)]
+pub struct TaintTracker {
+    cells: HashMap<usize, Taint>,
+    inputs: usize,
+    outputs: Vec<(i64, Taint)>,
+}
+
+// What executing an instruction does to the shadow memory
+pub(crate) enum TaintEffect {
+    Store(usize, Taint),
+    Input(usize),
+    Output(i64, Taint),
+    Nothing,
+}
+
+impl fmt::Display for TaintSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TaintSource::Input(n) => write!(f, "input {}", n),
+            TaintSource::Cell(address) => write!(f, "cell {}", address),
+        }
+    }
+}
+
+impl TaintTracker {
+    pub fn new() -> TaintTracker {
+        Default::default()
+    }
+
+    // Sources the value currently in a cell depends on
+    pub fn cell(&self, address: usize) -> Taint {
+        self.cells.get(&address).cloned().unwrap_or_default()
+    }
+
+    // Every output produced so far, with the sources it depends on
+    pub fn outputs(&self) -> &[(i64, Taint)] {
+        &self.outputs
+    }
+
+    pub fn inputs_consumed(&self) -> usize {
+        self.inputs
+    }
+
+    // Marks the cell's current value as a source in its own right
+    pub fn seed_cell(&mut self, address: usize) {
+        self.cells.insert(address, vec![TaintSource::Cell(address)].into_iter().collect());
+    }
+
+    fn store(&mut self, address: usize, taint: Taint) {
+        if taint.is_empty() {
+            self.cells.remove(&address);
+        } else {
+            self.cells.insert(address, taint);
+        }
+    }
+
+    pub(crate) fn apply(&mut self, effect: TaintEffect) {
+        match effect {
+            TaintEffect::Store(address, taint) => self.store(address, taint),
+            TaintEffect::Input(address) => {
+                self.store(address, vec![TaintSource::Input(self.inputs)].into_iter().collect());
+                self.inputs += 1;
+            },
+            TaintEffect::Output(value, taint) => self.outputs.push((value, taint)),
+            TaintEffect::Nothing => (),
+        }
+    }
+}
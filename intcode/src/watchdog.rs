@@ -0,0 +1,198 @@
+// Detects deadlocks between machines connected by channel devices.
+//
+// Channels created through the watchdog count the values in flight on each link and
+// record which machine a blocked reader is waiting on, or which a writer blocked on a
+// full bounded channel is waiting for. A machine that has waited for a while follows
+// the chain of waiting machines; if it comes back round to a machine it has already
+// seen, and no reader along the way has anything in flight, none of them can ever
+// proceed. Every machine in the cycle then fails with a report of who waits on whom.
+
+use super::io::{self, ChannelInputDevice, ChannelOutputDevice, ClosedPolicy};
+use std::cmp;
+use std::collections::HashSet;
+use std::sync::mpsc::{SyncSender, TrySendError};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+type Result<T> = std::result::Result<T, Box<dyn std::error::Error>>;
+
+struct Link {
+    from: usize,
+    to: usize,
+    in_flight: usize,
+}
+
+#[derive(Copy, Clone)]
+enum Blocked {
+    // Reading from an empty link
+    Reading(usize),
+    // Sending on a full bounded link
+    Sending(usize),
+}
+
+struct WatchState {
+    names: Vec<String>,
+    // The link each machine is blocked on, if any
+    waiting: Vec<Option<Blocked>>,
+    links: Vec<Link>,
+    deadlock: Option<String>,
+}
+
+pub struct Watchdog {
+    state: Mutex<WatchState>,
+    interval: Duration,
+}
+
+// One end of a watched link, held by a channel device
+pub(crate) struct Watch {
+    watchdog: Arc<Watchdog>,
+    link: usize,
+}
+
+impl Watchdog {
+    pub fn new() -> Arc<Watchdog> {
+        Watchdog::with_interval(Duration::from_millis(50))
+    }
+
+    // Blocked readers check for a deadlock every `interval`
+    pub fn with_interval(interval: Duration) -> Arc<Watchdog> {
+        Arc::new(Watchdog{
+            state: Mutex::new(WatchState{ names: vec![], waiting: vec![], links: vec![], deadlock: None }),
+            interval,
+        })
+    }
+
+    // Registers a machine, returning the id used to connect it
+    pub fn add_machine(&self, name: &str) -> usize {
+        let mut state = self.state.lock().unwrap();
+        state.names.push(String::from(name));
+        state.waiting.push(None);
+        state.names.len() - 1
+    }
+
+    // Like io::channel_pair, for outputs of machine `from` feeding the inputs of machine `to`
+    pub fn connect(self: &Arc<Self>, from: usize, to: usize, capacity: Option<usize>, policy: ClosedPolicy)
+        -> (Box<ChannelOutputDevice>, Box<ChannelInputDevice>) {
+        let link = {
+            let mut state = self.state.lock().unwrap();
+            state.links.push(Link{ from, to, in_flight: 0 });
+            state.links.len() - 1
+        };
+        let (mut output, mut input) = io::channel_pair(capacity, policy);
+        output.set_watch(Watch{ watchdog: self.clone(), link });
+        input.set_watch(Watch{ watchdog: self.clone(), link });
+        (output, input)
+    }
+
+    // The report of the deadlock found, if any
+    pub fn deadlock(&self) -> Option<String> {
+        self.state.lock().unwrap().deadlock.clone()
+    }
+}
+
+impl WatchState {
+    // Follows waiting machines from `machine`, returning the chain if it loops with nothing in flight
+    fn find_cycle(&self, machine: usize) -> Option<Vec<String>> {
+        let mut seen = HashSet::new();
+        let mut chain = vec![];
+        let mut current = machine;
+        while seen.insert(current) {
+            current = match self.waiting[current]? {
+                Blocked::Reading(link) => {
+                    let link = &self.links[link];
+                    if link.in_flight > 0 { return None }
+                    chain.push(format!("{} waits on {}", self.names[link.to], self.names[link.from]));
+                    link.from
+                },
+                Blocked::Sending(link) => {
+                    let link = &self.links[link];
+                    chain.push(format!("{} waits to send to {}", self.names[link.from], self.names[link.to]));
+                    link.to
+                },
+            };
+        }
+        Some(chain)
+    }
+}
+
+impl Watch {
+    pub(crate) fn interval(&self) -> Duration {
+        self.watchdog.interval
+    }
+
+    pub(crate) fn sending(&self) {
+        self.watchdog.state.lock().unwrap().links[self.link].in_flight += 1;
+    }
+
+    pub(crate) fn send_failed(&self) {
+        let link = &mut self.watchdog.state.lock().unwrap().links[self.link];
+        link.in_flight = link.in_flight.saturating_sub(1);
+    }
+
+    pub(crate) fn received(&self) {
+        let mut state = self.watchdog.state.lock().unwrap();
+        let link = &mut state.links[self.link];
+        link.in_flight = link.in_flight.saturating_sub(1);
+        let to = state.links[self.link].to;
+        state.waiting[to] = None;
+    }
+
+    pub(crate) fn stop_waiting(&self) {
+        let mut state = self.watchdog.state.lock().unwrap();
+        let to = state.links[self.link].to;
+        state.waiting[to] = None;
+    }
+
+    // Marks the reading machine as blocked, failing if that leaves it deadlocked
+    pub(crate) fn wait(&self) -> Result<()> {
+        let to = self.watchdog.state.lock().unwrap().links[self.link].to;
+        self.block(to, Blocked::Reading(self.link))
+    }
+
+    // Sends on a bounded channel, checking for a deadlock every interval while it's full.
+    // Returns whether the value was sent, or false if the reader has gone.
+    pub(crate) fn send_bounded(&self, channel: &SyncSender<i64>, mut value: i64) -> Result<bool> {
+        let poll = cmp::min(self.interval(), Duration::from_millis(1));
+        let mut waited = Duration::from_millis(0);
+        let from = self.watchdog.state.lock().unwrap().links[self.link].from;
+        let result = loop {
+            match channel.try_send(value) {
+                Ok(()) => break Ok(true),
+                Err(TrySendError::Disconnected(_)) => break Ok(false),
+                Err(TrySendError::Full(v)) => value = v,
+            }
+            thread::sleep(poll);
+            waited += poll;
+            if waited >= self.interval() {
+                waited = Duration::from_millis(0);
+                if let Err(e) = self.block(from, Blocked::Sending(self.link)) { break Err(e) }
+            }
+        };
+        self.watchdog.state.lock().unwrap().waiting[from] = None;
+        result
+    }
+
+    // Records what the machine is blocked on, failing if that leaves it deadlocked
+    fn block(&self, machine: usize, blocked: Blocked) -> Result<()> {
+        let mut state = self.watchdog.state.lock().unwrap();
+        state.waiting[machine] = Some(blocked);
+        let cycle = state.find_cycle(machine);
+        if let (Some(chain), None) = (&cycle, &state.deadlock) {
+            state.deadlock = Some(chain.join(", "));
+        }
+        match (cycle, &state.deadlock) {
+            (Some(_), Some(report)) => Err(From::from(format!("Deadlock: {}", report))),
+            _ => Ok(()),
+        }
+    }
+
+    // The error for a closed channel, which is likely due to a deadlocked peer giving up
+    pub(crate) fn closed_error(&self) -> String {
+        self.stop_waiting();
+        match self.watchdog.deadlock() {
+            Some(report) => format!("Input channel closed after deadlock: {}", report),
+            None => String::from("Input channel closed"),
+        }
+    }
+}
@@ -0,0 +1,135 @@
+use intcode::asm::{assemble, assemble_module, link};
+use intcode::program::IntcodeProgram;
+
+// The example from the top of asm.rs: prints a countdown from its input using the stack macros
+const COUNTDOWN: &str = "
+    .global main
+    .const NEWLINE 10
+main:
+    arb stack
+    in [count]
+loop:
+    push [count]
+    call print
+    pop [count]
+    add [count], -1, [count]
+    jnz [count], loop
+    hlt
+print:
+    out [rb - 2]
+    out NEWLINE
+    ret
+count: .data 0
+stack:
+";
+
+fn run(memory: Vec<i64>, inputs: &[i64]) -> Vec<i64> {
+    let mut program = IntcodeProgram::from_memory(memory);
+    for &input in inputs { program.give_input(input); }
+    program.execute().unwrap();
+    program.get_all_output()
+}
+
+fn error(source: &str) -> String {
+    assemble(source).expect_err("source should fail to assemble").to_string()
+}
+
+#[test]
+fn runs_macros_and_the_stack() {
+    assert_eq!(run(assemble(COUNTDOWN).unwrap(), &[3]), vec![3, 10, 2, 10, 1, 10]);
+}
+
+#[test]
+fn assembled_code_disassembles_cleanly() {
+    let linked = link(&[assemble_module("countdown.s", COUNTDOWN).unwrap()]).unwrap();
+    let program = IntcodeProgram::from_memory(linked.memory.clone());
+
+    // Decoding from the start visits exactly the assembled instructions, up to the data
+    let mut address = 0;
+    let mut listing = vec![];
+    for (&start, (module, _)) in &linked.source_map {
+        assert_eq!((start, module.as_str()), (address, "countdown.s"));
+        let instruction = program.decode(start).unwrap();
+        listing.push(instruction.to_string());
+        address += instruction.length();
+    }
+    let count = address as i64;
+    assert_eq!(linked.memory[address], 0, "count follows the code");
+    assert_eq!(listing[0], format!("arb {}", count + 1));
+    assert_eq!(listing[1], format!("in: [{}]", count));
+    assert!(listing.contains(&format!("add: [{}] <- [{}] + -1", count, count)));
+    assert_eq!(listing.last().unwrap(), "jez: [rb + 0] if not 0");
+
+    // Each instruction maps back to the line it came from, macros to the line using them
+    let lines: Vec<usize> = linked.source_map.values().map(|&(_, line)| line).collect();
+    assert_eq!(&lines[..4], &[5, 6, 8, 8]);
+}
+
+#[test]
+fn links_globals_across_modules() {
+    let main = assemble_module("main.s", "
+    .global main
+main:
+    arb stack
+    push 21
+    call double
+    pop [result]
+    out [result]
+    hlt
+result: .data 0
+").unwrap();
+    let double = assemble_module("double.s", "
+    .global double
+double:
+    mul [rb - 2], FACTOR, [rb - 2]
+    ret
+    .const FACTOR 2
+").unwrap();
+    // Linked last so the stack has nothing above it
+    let stack = assemble_module("stack.s", ".global stack\nstack:").unwrap();
+    let linked = link(&[main, double, stack]).unwrap();
+    assert_eq!(run(linked.memory, &[]), vec![42]);
+    assert!(linked.source_map.values().any(|(module, line)| module == "double.s" && *line == 4));
+}
+
+#[test]
+fn linking_reports_missing_and_clashing_globals() {
+    let caller = || assemble_module("caller.s", "call helper\nhlt").unwrap();
+    let helper = |name: &str| assemble_module(name, ".global helper\nhelper: ret").unwrap();
+    // Labels that aren't global stay private to their module
+    let private = assemble_module("private.s", "helper: ret").unwrap();
+
+    assert_eq!(link(&[caller(), private]).err().unwrap().to_string(), "caller.s:1: undefined symbol 'helper'");
+    assert_eq!(link(&[caller(), helper("a.s"), helper("b.s")]).err().unwrap().to_string(),
+        "b.s:1: global 'helper' is already defined in a.s");
+    assert_eq!(assemble_module("lonely.s", "hlt\n.global helper").err().unwrap().to_string(),
+        "lonely.s:2: global 'helper' is never defined");
+}
+
+#[test]
+fn errors_report_source_lines() {
+    assert_eq!(error("hlt\n\nfoo 1"), "<source>:3: unknown instruction or macro 'foo'");
+    assert_eq!(error("add 1, 2"), "<source>:1: 'add' takes 3 operand(s) but 2 were given");
+    assert_eq!(error("in 5"), "<source>:1: 'in' can't write to an immediate operand");
+    assert_eq!(error("out [3 +]"), "<source>:1: expression ends with an operator: 3 +");
+    assert_eq!(error("jmp nowhere"), "<source>:1: undefined symbol 'nowhere'");
+    assert_eq!(error("x: hlt\n\nx: hlt"), "<source>:3: duplicate symbol 'x'");
+    assert_eq!(error(".const A B\n.const B A\nout A"), "<source>:2: constant 'B' is defined in terms of itself");
+    assert_eq!(error("hlt\n.data 9223372036854775807 + 1"), "<source>:2: expression overflows a 64-bit word");
+    assert_eq!(error(".const BIG 9223372036854775807\n.const SUM BIG + BIG\nout SUM"),
+        "<source>:2: expression overflows a 64-bit word");
+}
+
+#[test]
+fn macros_check_their_arguments() {
+    let twice = ".macro twice x\n    out x\n    out x\n.endm\n";
+    assert_eq!(run(assemble(&format!("{}twice 7\nhlt", twice)).unwrap(), &[]), vec![7, 7]);
+    assert_eq!(error(&format!("{}twice 1, 2", twice)), "<source>:5: macro 'twice' takes 1 argument(s) but 2 were given");
+    assert_eq!(error("push 1, 2"), "<source>:1: macro 'push' takes 1 argument(s) but 2 were given");
+    assert_eq!(error("ret 1"), "<source>:1: macro 'ret' takes 0 argument(s) but 1 were given");
+    assert_eq!(error(".macro broken\nhlt"), "<source>:1: macro 'broken' is missing .endm");
+
+    // \@ gives each expansion its own labels
+    let skip = ".macro skip\n    jmp after\\@\n    out 1\nafter\\@:\n.endm\n";
+    assert_eq!(run(assemble(&format!("{}skip\nskip\nout 2\nhlt", skip)).unwrap(), &[]), vec![2]);
+}
@@ -0,0 +1,72 @@
+use std::collections::HashMap;
+use std::fs;
+use intcode::asm::{assemble_module, link};
+use intcode::compiler::compile_program;
+use intcode::program::{Convention, Event, IntcodeProgram};
+
+// Loads routines.s along with the addresses of its globals
+fn load() -> (IntcodeProgram, HashMap<String, usize>) {
+    let source = fs::read_to_string("tests/programs/routines.s").unwrap();
+    let linked = link(&[assemble_module("routines.s", &source).unwrap()]).unwrap();
+    (IntcodeProgram::from_memory(linked.memory), linked.symbols)
+}
+
+#[test]
+fn calls_routines_before_the_program_starts() {
+    let (mut program, symbols) = load();
+    let (fact, sub) = (symbols["fact"], symbols["sub"]);
+    assert_eq!(program.call(fact, &[0], Convention::Assembler).unwrap(), 1);
+    assert_eq!(program.call(fact, &[6], Convention::Assembler).unwrap(), 720);
+    assert_eq!(program.call(sub, &[10, 3], Convention::Assembler).unwrap(), 7);
+    assert_eq!((program.ip(), program.relative_base()), (0, 0));
+
+    // Nothing was overwritten, so the program still runs normally
+    program.execute().unwrap();
+    assert_eq!(program.get_all_output(), vec![120]);
+}
+
+#[test]
+fn keeps_memory_and_outputs_but_restores_registers() {
+    let (mut program, symbols) = load();
+    program.execute().unwrap();
+    let (ip, relative_base) = (program.ip(), program.relative_base());
+    program.get_all_output();
+
+    program.call(symbols["log"], &[4], Convention::Assembler).unwrap();
+    program.call(symbols["log"], &[5], Convention::Assembler).unwrap();
+    assert_eq!(program.get_all_output(), vec![4, 5]);
+    assert_eq!(program.load_position(symbols["calls"]), 2);
+    assert_eq!((program.ip(), program.relative_base()), (ip, relative_base));
+}
+
+#[test]
+fn reports_routines_that_never_return() {
+    let (mut program, symbols) = load();
+    let finish = symbols["finish"];
+    let error = program.call(finish, &[], Convention::Assembler).expect_err("hlt never returns").to_string();
+    assert_eq!(error, format!("Program exited at {} before the routine returned", finish));
+    assert_eq!((program.ip(), program.relative_base()), (0, 0));
+}
+
+#[test]
+fn calls_compiled_functions() {
+    let compiled = compile_program("
+        var calls = 0;
+        fn sum(a, b, c) { calls = calls + 1; return a + b * 10 + c * 100; }
+        fn answer() { return sum(2, 4, 0); }
+        fn main() { var a = 7; var b = input(); output(a + sum(b, 0, 0)); }
+    ").unwrap();
+    let convention = Convention::Compiled(compiled.return_cell);
+    let mut program = IntcodeProgram::from_memory(compiled.memory);
+    assert_eq!(program.call(compiled.symbols["sum"], &[1, 2, 3], convention).unwrap(), 321);
+    assert_eq!(program.call(compiled.symbols["answer"], &[], convention).unwrap(), 42);
+    assert_eq!((program.ip(), program.relative_base()), (0, 0));
+
+    // Paused inside main, whose frame a call mustn't overwrite
+    assert!(program.execute_until_event().unwrap() == Event::InputRequired);
+    assert_eq!(program.call(compiled.symbols["sum"], &[0, 0, 5], convention).unwrap(), 500);
+    program.give_input(1);
+    program.execute().unwrap();
+    assert_eq!(program.get_all_output(), vec![8]);
+    assert_eq!(program.load_position(compiled.symbols["calls"]), 4);
+}
@@ -0,0 +1,123 @@
+use intcode::cfg::{self, ControlFlowGraph, Exit};
+use intcode::program::IntcodeProgram;
+use std::collections::{BTreeMap, BTreeSet};
+
+// Counts down from its input, outputting each value, with the loop body at 2..11
+const LOOP: &str = "3,12,1001,12,-1,12,4,12,1005,12,2,99,0";
+// Outputs 1 if its input is nonzero, or 0 otherwise
+const BRANCH: &str = "3,13,1005,13,10,104,0,1105,1,12,104,1,99,0";
+// Jumps to the address it reads: 5 outputs 5 and 8 outputs 8
+const INDIRECT: &str = "3,11,105,1,11,104,5,99,104,8,99,0";
+
+fn graph(raw: &str) -> ControlFlowGraph {
+    cfg::build(&IntcodeProgram::from_raw_input(raw).unwrap(), &[])
+}
+
+// Each block's start, the addresses of its instructions and its successors
+fn shape(graph: &ControlFlowGraph) -> Vec<(usize, Vec<usize>, Vec<usize>)> {
+    graph.blocks.values().map(|block| {
+        (block.start, block.instructions.iter().map(|&(address, _)| address).collect(), block.successors())
+    }).collect()
+}
+
+#[test]
+fn a_loop_body_is_one_block() {
+    let graph = graph(LOOP);
+    assert_eq!(shape(&graph), vec![
+        (0, vec![0], vec![2]),
+        (2, vec![2, 6, 8], vec![2, 11]),
+        (11, vec![11], vec![]),
+    ]);
+    assert!(matches!(graph.blocks[&0].exit, Exit::Fallthrough(2)));
+    assert!(matches!(graph.blocks[&2].exit, Exit::Branch{ target: 2, fallthrough: 11, .. }));
+    assert!(matches!(graph.blocks[&11].exit, Exit::Halt));
+}
+
+#[test]
+fn a_branch_splits_and_joins() {
+    let graph = graph(BRANCH);
+    assert_eq!(shape(&graph), vec![
+        (0, vec![0, 2], vec![10, 5]),
+        (5, vec![5, 7], vec![12]),
+        (10, vec![10], vec![12]),
+        (12, vec![12], vec![]),
+    ]);
+    assert!(matches!(graph.blocks[&5].exit, Exit::Jump(12)));
+    assert_eq!(graph.functions.iter().cloned().collect::<Vec<usize>>(), vec![0]);
+}
+
+#[test]
+fn indirect_jumps_end_the_graph_until_observed() {
+    let graph = graph(INDIRECT);
+    assert_eq!(shape(&graph), vec![(0, vec![0, 2], vec![])]);
+    assert!(matches!(&graph.blocks[&0].exit, Exit::Indirect{ condition: None, fallthrough: None, target } if target.param == 11));
+
+    // Extra entry points are decoded, and entering decoded code only splits its block
+    let graph = cfg::build(&IntcodeProgram::from_raw_input(INDIRECT).unwrap(), &[8, 2]);
+    assert_eq!(shape(&graph), vec![
+        (0, vec![0], vec![2]),
+        (2, vec![2], vec![]),
+        (8, vec![8, 10], vec![]),
+    ]);
+}
+
+#[test]
+fn undecodable_words_end_a_block() {
+    let graph = graph("104,1,42");
+    assert_eq!(shape(&graph), vec![(0, vec![0], vec![])]);
+    assert!(matches!(graph.blocks[&0].exit, Exit::Invalid(2)));
+}
+
+#[test]
+fn observes_where_indirect_jumps_go() {
+    let observe = |inputs: &[i64], budget| {
+        let mut program = IntcodeProgram::from_raw_input(INDIRECT).unwrap();
+        for &input in inputs { program.give_input(input); }
+        cfg::observe_indirect_jumps(&mut program, budget).unwrap()
+    };
+    let targets = |observed: &BTreeMap<usize, BTreeSet<usize>>| -> Vec<(usize, Vec<usize>)> {
+        observed.iter().map(|(&from, to)| (from, to.iter().cloned().collect())).collect()
+    };
+    assert_eq!(targets(&observe(&[8], 100)), vec![(2, vec![8])]);
+    assert_eq!(targets(&observe(&[5], 100)), vec![(2, vec![5])]);
+    // Stops when it needs input or runs out of budget before the jump
+    assert!(observe(&[], 100).is_empty());
+    assert!(observe(&[8], 1).is_empty());
+
+    // A conditional jump through memory that falls through isn't recorded
+    let mut program = IntcodeProgram::from_raw_input("6,6,7,104,1,99,5,99").unwrap();
+    assert!(cfg::observe_indirect_jumps(&mut program, 100).unwrap().is_empty());
+    assert_eq!(program.get_output(), Some(1));
+}
+
+#[test]
+fn observed_targets_become_blocks_with_dashed_edges() {
+    let mut program = IntcodeProgram::from_raw_input(INDIRECT).unwrap();
+    program.give_input(8);
+    let observed = cfg::observe_indirect_jumps(&mut program, 100).unwrap();
+    let graph = ControlFlowGraph::with_observed(&program, &observed);
+    assert_eq!(shape(&graph), vec![(0, vec![0, 2], vec![]), (8, vec![8, 10], vec![])]);
+    assert_eq!(graph.to_dot(&observed), r#"digraph intcode {
+    node [shape=box, fontname="monospace"];
+    b0 [label="     0 : in: [11]\l     2 : jnz: [11] if 1\lindirect jump via [11]\l", color=red];
+    b0 -> b8 [style=dashed, label="observed"];
+    b8 [label="     8 : out: 8\l    10 : hlt\l"];
+}
+"#);
+}
+
+#[test]
+fn dot_labels_blocks_and_edges() {
+    assert_eq!(graph(LOOP).to_dot(&BTreeMap::new()), r#"digraph intcode {
+    node [shape=box, fontname="monospace"];
+    b0 [label="     0 : in: [12]\l", peripheries=2];
+    b0 -> b2 [label=""];
+    b2 [label="     2 : add: [12] <- [12] + -1\l     6 : out: [12]\l     8 : jnz: 2 if [12]\l"];
+    b2 -> b2 [label="taken"];
+    b2 -> b11 [label="fallthrough"];
+    b11 [label="    11 : hlt\l"];
+}
+"#);
+    let dot = graph("104,1,42").to_dot(&BTreeMap::new());
+    assert!(dot.contains(r#"b0 [label="     0 : out: 1\linvalid instruction at 2\l", peripheries=2];"#), "{}", dot);
+}
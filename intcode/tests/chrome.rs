@@ -0,0 +1,50 @@
+use intcode::chrome::ChromeTrace;
+use intcode::io::{self, ClosedPolicy};
+use intcode::program::IntcodeProgram;
+use serde_json::Value;
+
+// Reads a value and outputs it plus one
+const INCREMENT: &str = "3,9,1001,9,1,9,4,9,99,0";
+
+fn events(trace: &ChromeTrace) -> Vec<Value> {
+    let mut json = vec![];
+    trace.write(&mut json).unwrap();
+    let trace: Value = serde_json::from_slice(&json).unwrap();
+    trace["traceEvents"].as_array().unwrap().clone()
+}
+
+fn named(events: &[Value], tid: usize) -> Vec<String> {
+    events.iter().filter(|event| event["tid"] == tid && event["ph"] != "M")
+        .map(|event| format!("{} {}", event["ph"].as_str().unwrap(), event["name"].as_str().unwrap()))
+        .collect()
+}
+
+#[test]
+fn draws_arrows_between_connected_machines() {
+    let trace = ChromeTrace::new();
+    let (first, second) = (trace.add_machine("first"), trace.add_machine("second"));
+    let mut machines = [
+        IntcodeProgram::from_raw_input(INCREMENT).unwrap(),
+        IntcodeProgram::from_raw_input(INCREMENT).unwrap(),
+    ];
+    let (output, input) = trace.connect(first, second, None, ClosedPolicy::Error);
+    machines[0].replace_input(trace.wrap_input(first, io::DefaultInputDevice::new()));
+    machines[0].replace_output(output);
+    machines[1].replace_input(input);
+    machines[1].replace_output(trace.wrap_output(second, None, io::DefaultOutputDevice::new()));
+
+    machines[0].give_input(5);
+    for (machine, program) in machines.iter_mut().enumerate() {
+        trace.resume(machine);
+        program.execute().unwrap();
+        trace.pause(machine);
+    }
+    assert_eq!(machines[1].get_output(), Some(7));
+
+    let events = events(&trace);
+    assert_eq!(named(&events, first), vec!["X run", "i in 5", "X run", "i out 6", "s value", "X run"]);
+    assert_eq!(named(&events, second), vec!["X run", "i in 6", "f value", "X run", "i out 7", "X run"]);
+    let ids: Vec<&Value> = events.iter().filter(|event| event["cat"] == "io").map(|event| &event["id"]).collect();
+    assert_eq!(ids.len(), 2);
+    assert_eq!(ids[0], ids[1]);
+}
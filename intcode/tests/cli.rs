@@ -0,0 +1,79 @@
+use std::fs;
+use std::ops::Deref;
+use std::process::{Command, Output};
+
+// A program file, removed when the test using it finishes
+struct ProgramFile(String);
+
+impl Deref for ProgramFile {
+    type Target = str;
+    fn deref(&self) -> &str { &self.0 }
+}
+
+impl Drop for ProgramFile {
+    fn drop(&mut self) { let _ = fs::remove_file(&self.0); }
+}
+
+// Writes a program to a file of its own, so tests running in parallel don't collide
+fn program_file(name: &str, contents: &str) -> ProgramFile {
+    let path = std::env::temp_dir().join(format!("intcode-cli-{}-{}.txt", std::process::id(), name));
+    fs::write(&path, contents).unwrap();
+    ProgramFile(path.to_string_lossy().into_owned())
+}
+
+fn intcode(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_intcode")).args(args).output().unwrap()
+}
+
+fn stdout(output: &Output) -> String {
+    assert!(output.status.success(), "failed: {}", String::from_utf8_lossy(&output.stderr));
+    String::from_utf8(output.stdout.clone()).unwrap()
+}
+
+fn stderr(output: &Output) -> String {
+    assert!(!output.status.success());
+    String::from_utf8(output.stderr.clone()).unwrap()
+}
+
+#[test]
+fn patches_are_applied_before_running() {
+    // Outputs the cells at 5 and 9, the second past the end of the image
+    let file = program_file("patches", "4,5,4,9,99,7");
+    assert_eq!(stdout(&intcode(&["run", "-f", &file])), "7,0\n");
+    assert_eq!(stdout(&intcode(&["run", "-f", &file, "-p", "5=-3", "-p", " 9 = 12 "])), "-3,12\n");
+    assert!(stderr(&intcode(&["run", "-f", &file, "-p", "5:3"])).contains("Invalid patch, expected <address>=<value>: 5:3"));
+    assert!(stderr(&intcode(&["run", "-f", &file, "-p", "x=3"])).contains("Invalid patch"));
+
+    // Far patches go to extended memory rather than growing the image, and unusable addresses are rejected
+    assert_eq!(stdout(&intcode(&["run", "-f", &file, "-p", "3=100000000000", "-p", "100000000000=5"])), "7,5\n");
+    let max = usize::MAX.to_string();
+    assert!(stderr(&intcode(&["run", "-f", &file, "-p", &format!("{}=1", max)]))
+        .contains(&format!("Invalid patch {}=1: Address {} is out of range", max, max)));
+}
+
+#[test]
+fn inputs_and_output_formats() {
+    // Echoes two inputs
+    let file = program_file("formats", "3,0,4,0,3,0,4,0,99");
+    assert_eq!(stdout(&intcode(&["run", "-f", &file, "-i", "72,105"])), "72,105\n");
+    assert_eq!(stdout(&intcode(&["run", "-f", &file, "-i", "72 105", "-o", "json"])), "[72, 105]\n");
+    assert_eq!(stdout(&intcode(&["run", "-f", &file, "-i", "H", "-m", "ascii", "-o", "ascii"])), "H\n");
+    assert!(stderr(&intcode(&["run", "-f", &file, "-i", "1"])).contains("Program requested more input than was provided"));
+}
+
+#[test]
+fn budget_stops_runaway_programs() {
+    let file = program_file("budget", "1105,1,0");
+    assert!(stderr(&intcode(&["run", "-f", &file, "-b", "100"])).contains("Instruction budget of 100 exhausted"));
+
+    let file = program_file("within-budget", "104,1,99");
+    assert_eq!(stdout(&intcode(&["run", "-f", &file, "-b", "2"])), "1\n");
+}
+
+#[test]
+fn disassembles_instructions_and_data() {
+    let file = program_file("disassemble", "1002,4,3,4,33,104,-7,99");
+    let listing = stdout(&intcode(&["disassemble", "-f", &file, "-p", "2=5"]));
+    let lines: Vec<&str> = listing.lines().map(|line| line.split(';').nth(1).unwrap().trim()).collect();
+    assert_eq!(lines, vec!["mul: [4] <- [4] * 5", "data", "out: -7", "hlt"]);
+}
@@ -0,0 +1,68 @@
+use std::fs;
+use intcode::compiler::compile;
+use intcode::program::IntcodeProgram;
+
+fn run(name: &str, inputs: &[i64]) -> Vec<i64> {
+    let source = fs::read_to_string(format!("tests/programs/{}.ic", name)).unwrap();
+    let mut program = IntcodeProgram::from_memory(compile(&source).unwrap());
+    for input in inputs { program.give_input(*input); }
+    program.execute().unwrap();
+    program.get_all_output()
+}
+
+fn compile_error(source: &str) -> String {
+    compile(source).expect_err("source should fail to compile").to_string()
+}
+
+#[test]
+fn recursion_and_globals() {
+    assert_eq!(run("fib", &[10]), vec![0, 1, 1, 2, 3, 5, 8, 13, 21, 34, 55, 11]);
+}
+
+#[test]
+fn operators() {
+    assert_eq!(run("operators", &[7, 3]),
+        vec![10, 4, 21, -7, 5, 4, 0, 1, 0, 1, 0, 1, 0, 1, 1, 0, 0, 3]);
+    assert_eq!(run("operators", &[0, 0]),
+        vec![0, 0, 0, 0, 1, 0, 0, 0, 1, 1, 1, 0, 1, 0, 0, 0, 0, 3]);
+}
+
+#[test]
+fn calls_and_control_flow() {
+    assert_eq!(run("calls", &[]), vec![26, 10, -1, 0, 1, 0]);
+}
+
+#[test]
+fn locals_start_at_zero_on_every_call() {
+    let source = "fn f() { var x; x = x + 1; return x; }\nfn main() { output(f()); output(f()); }";
+    let mut program = IntcodeProgram::from_memory(compile(source).unwrap());
+    program.execute().unwrap();
+    assert_eq!(program.get_all_output(), vec![1, 1]);
+}
+
+#[test]
+fn locals_are_scoped_to_their_block() {
+    assert_eq!(compile_error("fn main() {\n  if (1) { var x = 1; }\n  output(x);\n}"),
+        "line 3: undefined variable 'x'");
+    assert_eq!(compile_error("fn main() {\n  var x;\n  while (1) { var x; }\n}"),
+        "line 3: 'x' is already declared in this function");
+    // Sibling blocks can reuse a name, and a local declared in a block hides a global only there
+    let source = "var x = 5;\nfn main() {\n  if (1) { var x = 1; output(x); } else { var x; }\n  output(x);\n}";
+    let mut program = IntcodeProgram::from_memory(compile(source).unwrap());
+    program.execute().unwrap();
+    assert_eq!(program.get_all_output(), vec![1, 5]);
+}
+
+#[test]
+fn errors_report_lines() {
+    assert!(compile_error("fn main() {\n  output(x);\n}").starts_with("line 2: undefined variable 'x'"));
+    assert!(compile_error("fn main() {\n\n  f(1);\n}").starts_with("line 3: undefined function 'f'"));
+    assert!(compile_error("fn f(a) {}\nfn main() { f(); }").starts_with("line 2: 'f' takes 1 argument(s)"));
+    assert!(compile_error("fn main() {\n  var x = 1\n}").starts_with("line 2: expected ';', found '}'"));
+    assert!(compile_error("fn main() {\n  output(1)\n\n  output(2);\n}").starts_with("line 2: expected ';'"));
+    assert!(compile_error("fn helper() {}").contains("no 'main'"));
+    // Tokens are quoted as they appear in the source
+    assert_eq!(compile_error("fn main() {\n  var 5 = 1;\n}"), "line 2: expected identifier, found '5'");
+    assert_eq!(compile_error("fn main() {\n  output(1) x;\n}"), "line 2: expected ';', found 'x'");
+    assert_eq!(compile_error("fn main() {"), "line 1: expected '}', found end of input");
+}
@@ -0,0 +1,281 @@
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::ops::Deref;
+use serde_json::{json, Value};
+use intcode::dap;
+
+// Frames each request as the client would, numbering them from 1
+fn record(requests: &[Value]) -> Vec<u8> {
+    let mut out = vec![];
+    for (seq, request) in requests.iter().enumerate() {
+        let mut request = request.clone();
+        request["seq"] = json!(seq + 1);
+        request["type"] = json!("request");
+        let body = request.to_string();
+        out.extend(format!("Content-Length: {}\r\n\r\n{}", body.len(), body).into_bytes());
+    }
+    out
+}
+
+fn replay(requests: &[Value]) -> Vec<Value> {
+    let mut output = vec![];
+    dap::serve(Cursor::new(record(requests)), &mut output).unwrap();
+
+    let mut reader = BufReader::new(Cursor::new(output));
+    let mut messages = vec![];
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).unwrap() == 0 { break }
+        let length: usize = header.trim().strip_prefix("Content-Length: ").unwrap().parse().unwrap();
+        reader.read_line(&mut String::new()).unwrap();
+        let mut body = vec![0u8; length];
+        reader.read_exact(&mut body).unwrap();
+        messages.push(serde_json::from_slice(&body).unwrap());
+    }
+    messages
+}
+
+// A line per message, e.g. "response launch" or "event stopped breakpoint"
+fn summary(messages: &[Value]) -> Vec<String> {
+    messages.iter().map(|m| match m["type"].as_str().unwrap() {
+        "response" => format!("response {}{}", m["command"].as_str().unwrap(), if m["success"] == true { "" } else { " failed" }),
+        _ => {
+            let detail = m["body"]["reason"].as_str().or_else(|| m["body"]["output"].as_str()).unwrap_or("");
+            format!("event {} {}", m["event"].as_str().unwrap(), detail).trim().to_string()
+        },
+    }).collect()
+}
+
+// An image in a temp file, removed when the test using it finishes
+struct TempImage(String);
+
+impl Deref for TempImage {
+    type Target = str;
+    fn deref(&self) -> &str { &self.0 }
+}
+
+impl Drop for TempImage {
+    fn drop(&mut self) { let _ = std::fs::remove_file(&self.0); }
+}
+
+// Writes an image to a temp file unique to this test run
+fn temp_image(name: &str, contents: &str) -> TempImage {
+    let path = std::env::temp_dir().join(format!("intcode-dap-{}-{}.txt", std::process::id(), name));
+    std::fs::write(&path, contents).unwrap();
+    TempImage(path.to_str().unwrap().to_string())
+}
+
+fn response(messages: &[Value], request_seq: usize) -> &Value {
+    messages.iter().find(|m| m["type"] == "response" && m["request_seq"] == request_seq).unwrap()
+}
+
+#[test]
+fn assembly_session() {
+    let messages = replay(&[
+        json!({ "command": "initialize", "arguments": { "adapterID": "intcode" } }),
+        json!({ "command": "launch", "arguments": { "program": "tests/programs/double.s", "input": [4] } }),
+        json!({ "command": "setBreakpoints", "arguments": {
+            "source": { "path": "tests/programs/double.s" }, "breakpoints": [{ "line": 6 }, { "line": 8 }, { "line": 10 }] } }),
+        json!({ "command": "configurationDone" }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+        json!({ "command": "next", "arguments": { "threadId": 1 } }),
+        json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+        json!({ "command": "evaluate", "arguments": { "expression": "0", "context": "repl" } }),
+        json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+        json!({ "command": "evaluate", "arguments": { "expression": "[15]", "context": "repl" } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": 2 } }),
+        json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+        json!({ "command": "disconnect" }),
+    ]);
+
+    assert_eq!(summary(&messages), vec![
+        "response initialize",
+        "response launch",
+        "event initialized",
+        "response setBreakpoints",
+        "response configurationDone",
+        "event stopped breakpoint",
+        "response stackTrace",
+        "response variables",
+        "response next",
+        "event output 8",
+        "event stopped step",
+        "response continue",
+        "event stopped pause",
+        "response evaluate",
+        "response continue",
+        "event stopped breakpoint",
+        "response evaluate",
+        "response variables",
+        "response continue",
+        "event exited",
+        "event terminated",
+        "response disconnect",
+    ]);
+
+    // Line 8 is a label, so its breakpoint moves to the instruction on the next line
+    let breakpoints = &response(&messages, 3)["body"]["breakpoints"];
+    assert_eq!(breakpoints[0]["line"], 6);
+    assert_eq!(breakpoints[0]["instructionReference"], "9");
+    assert_eq!(breakpoints[1]["line"], 9);
+    assert_eq!(breakpoints[2]["verified"], false);
+
+    let frame = &response(&messages, 5)["body"]["stackFrames"][0];
+    assert_eq!(frame["line"], 6);
+    assert_eq!(frame["instructionPointerReference"], "9");
+
+    let registers = &response(&messages, 6)["body"]["variables"];
+    assert_eq!(registers[0]["value"], "9");
+    assert_eq!(registers[1]["value"], "0");
+
+    assert_eq!(response(&messages, 11)["body"]["result"], "0");
+    assert_eq!(response(&messages, 12)["body"]["variables"][0]["name"], "[0..15]");
+}
+
+#[test]
+fn instruction_breakpoints_on_images() {
+    let path = temp_image("outputs", "104,1,104,2,104,3,99\n");
+    let messages = replay(&[
+        json!({ "command": "initialize" }),
+        json!({ "command": "launch", "arguments": { "program": &*path, "stopOnEntry": true } }),
+        json!({ "command": "setInstructionBreakpoints", "arguments": { "breakpoints": [{ "instructionReference": "4" }] } }),
+        json!({ "command": "configurationDone" }),
+        json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+        json!({ "command": "evaluate", "arguments": { "expression": "oops", "context": "repl" } }),
+        json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+    ]);
+
+    assert_eq!(summary(&messages), vec![
+        "response initialize",
+        "response launch",
+        "event initialized",
+        "response setInstructionBreakpoints",
+        "response configurationDone",
+        "event stopped entry",
+        "response continue",
+        "event output 1",
+        "event output 2",
+        "event stopped breakpoint",
+        "response evaluate failed",
+        "response continue",
+        "event output 3",
+        "event exited",
+        "event terminated",
+    ]);
+}
+
+fn line(messages: &[Value], request_seq: usize) -> &Value {
+    &response(messages, request_seq)["body"]["stackFrames"][0]["line"]
+}
+
+#[test]
+fn next_steps_over_calls() {
+    let messages = replay(&[
+        json!({ "command": "initialize" }),
+        json!({ "command": "launch", "arguments": { "program": "tests/programs/routines.s" } }),
+        json!({ "command": "setBreakpoints", "arguments": {
+            "source": { "path": "tests/programs/routines.s" }, "breakpoints": [{ "line": 28 }] } }),
+        json!({ "command": "configurationDone" }),
+        json!({ "command": "next", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "next", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+    ]);
+
+    // The whole recursive call on line 28 runs, then the pop macro on line 29
+    assert_eq!(line(&messages, 6), 29);
+    assert_eq!(line(&messages, 8), 30);
+    assert_eq!(summary(&messages)[5..], [
+        "event stopped breakpoint",
+        "response next",
+        "event stopped step",
+        "response stackTrace",
+        "response next",
+        "event stopped step",
+        "response stackTrace",
+        "response continue",
+        "event output 120",
+        "event exited",
+        "event terminated",
+    ]);
+}
+
+#[test]
+fn step_in_and_out_of_calls() {
+    let messages = replay(&[
+        json!({ "command": "initialize" }),
+        json!({ "command": "launch", "arguments": { "program": "tests/programs/routines.s" } }),
+        json!({ "command": "setBreakpoints", "arguments": {
+            "source": { "path": "tests/programs/routines.s" }, "breakpoints": [{ "line": 13 }] } }),
+        json!({ "command": "configurationDone" }),
+        json!({ "command": "stepOut", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+        json!({ "command": "stepOut", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "next", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stepIn", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": 1 } }),
+        json!({ "command": "disconnect" }),
+    ]);
+
+    // Stepping out of fact(0) returns to fact(1) just after its call
+    assert_eq!(line(&messages, 6), 9);
+    // And out of fact(1) to fact(2), a frame further down the stack
+    assert_eq!(line(&messages, 9), 9);
+    assert_eq!(line(&messages, 11), 10);
+    assert_eq!(line(&messages, 13), 11);
+    let rb = |seq| response(&messages, seq)["body"]["variables"][1]["value"].as_str().unwrap().parse::<i64>().unwrap();
+    // A frame takes two cells, the callee's argument and the return address, and line 9 pops one
+    assert_eq!(rb(14), rb(7) - 3);
+    assert!(summary(&messages).iter().all(|message| !message.contains("failed")));
+}
+
+#[test]
+fn pauses_a_running_program() {
+    // Loops forever
+    let path = temp_image("loop", "1105,1,0\n");
+    let messages = replay(&[
+        json!({ "command": "initialize" }),
+        json!({ "command": "launch", "arguments": { "program": &*path } }),
+        json!({ "command": "configurationDone" }),
+        json!({ "command": "pause", "arguments": { "threadId": 1 } }),
+        json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+        json!({ "command": "disconnect" }),
+    ]);
+    assert_eq!(summary(&messages), vec![
+        "response initialize",
+        "response launch",
+        "event initialized",
+        "response configurationDone",
+        "response pause",
+        "event stopped pause",
+        "response stackTrace",
+        "response disconnect",
+    ]);
+    assert_eq!(response(&messages, 5)["body"]["stackFrames"][0]["instructionPointerReference"], "0");
+}
+
+#[test]
+fn rejects_out_of_range_variable_references() {
+    let path = temp_image("halt", "99\n");
+    let messages = replay(&[
+        json!({ "command": "launch", "arguments": { "program": &*path } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": i64::MAX } }),
+        json!({ "command": "variables", "arguments": { "variablesReference": 1000 } }),
+    ]);
+    assert_eq!(response(&messages, 2)["message"], "Invalid variablesReference");
+    assert_eq!(response(&messages, 3)["body"]["variables"][0]["value"], "99");
+}
+
+#[test]
+fn rejects_oversized_messages() {
+    // The adapter stops rather than allocating whatever the header asks for
+    let mut output = vec![];
+    let error = dap::serve(Cursor::new(b"Content-Length: 100000000000\r\n\r\n{}".to_vec()), &mut output).unwrap_err();
+    assert_eq!(error.to_string(), "Message of 100000000000 bytes is longer than the limit of 1048576");
+    assert!(output.is_empty());
+}
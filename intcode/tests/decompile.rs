@@ -0,0 +1,89 @@
+use intcode::asm::assemble;
+use intcode::decompile::decompile;
+use intcode::program::IntcodeProgram;
+
+fn decompiled(raw: &str) -> String {
+    decompile(&IntcodeProgram::raw_to_memory(raw).unwrap()).unwrap()
+}
+
+#[test]
+fn structures_a_counting_loop() {
+    assert_eq!(decompiled("3,12,1001,12,-1,12,4,12,1005,12,2,99,0"), "\
+// rb is the relative base; rb[n] is the cell at rb + n
+int v12 = 0;
+
+void main() {
+    v12 = input();
+    do {
+        v12 = v12 - 1;
+        output(v12);
+    } while (v12);
+    halt();
+}
+");
+}
+
+#[test]
+fn tests_at_the_bottom_of_a_loop_spanning_several_blocks() {
+    let source = decompile(&assemble("
+    in [n]
+loop:
+    jez [n], skip
+    out [n]
+skip:
+    add [n], -1, [n]
+    lt 0, [n], [more]
+    jnz [more], loop
+    hlt
+n: .data 0
+more: .data 0
+").unwrap()).unwrap();
+    assert!(source.contains("
+    v19 = input();
+    do {
+        if (v19) {
+            output(v19);
+        }
+        v19 = v19 - 1;
+        v20 = 0 < v19;
+    } while (v20);
+    halt();
+"), "{}", source);
+}
+
+#[test]
+fn structures_a_branch_as_if_else() {
+    let source = decompiled("3,13,1005,13,10,104,0,1105,1,12,104,1,99,0");
+    assert!(source.contains("
+    v13 = input();
+    if (v13) {
+        output(1);
+    } else {
+        output(0);
+    }
+    halt();
+"), "{}", source);
+}
+
+#[test]
+fn leaves_indirect_jumps_as_computed_gotos() {
+    let source = decompiled("3,11,105,1,11,104,5,99,104,8,99,0");
+    assert!(source.contains("    v11 = input();\n    goto *v11;\n}"), "{}", source);
+}
+
+#[test]
+fn only_folds_constants_that_fit() {
+    let source = decompiled("1101,9223372036854775807,1,8,109,-9223372036854775808,99,0,0");
+    assert!(source.contains("v8 = 9223372036854775807 + 1;"), "{}", source);
+    assert!(source.contains("rb += -9223372036854775808;"), "{}", source);
+    let source = decompiled("1101,9223372036854775806,1,11,1102,-3,2,11,109,-4,99,0");
+    assert!(source.contains("v11 = 9223372036854775807;\n    v11 = -6;\n    rb -= 4;"), "{}", source);
+}
+
+#[test]
+fn shows_negative_addresses_as_invalid_accesses() {
+    // Outputs the cell at -5, which faults when run
+    let source = decompiled("4,-5,99");
+    assert!(source.contains("    output(mem[-5]);\n"), "{}", source);
+    assert!(!source.contains("int v"), "{}", source);
+}
@@ -0,0 +1,69 @@
+use intcode::explore::{explore, Strategy, Visit};
+use intcode::program::IntcodeProgram;
+use intcode::protect::Protection;
+
+// Echoes each input until it reads a zero, then exits
+const ECHO: &str = "3,11,1005,11,6,99,4,11,1105,1,0,0";
+
+// States are running totals of the inputs, pruned above 4
+fn totals(strategy: Strategy) -> Vec<Visit<i64>> {
+    let program = IntcodeProgram::from_raw_input(ECHO).unwrap();
+    explore(&program, 0, &[1, 2], strategy, |total, input, outputs| {
+        assert_eq!(outputs, &[input]);
+        Some(total + input).filter(|&total| total <= 4)
+    }, |&total| total).unwrap()
+}
+
+#[test]
+fn breadth_first_finds_shortest_paths() {
+    let visits = totals(Strategy::BreadthFirst);
+    let found: Vec<(i64, usize, Vec<i64>)> = visits.into_iter().map(|visit| (visit.state, visit.depth, visit.path)).collect();
+    assert_eq!(found, vec![
+        (0, 0, vec![]),
+        (1, 1, vec![1]),
+        (2, 1, vec![2]),
+        (3, 2, vec![1, 2]),
+        (4, 2, vec![2, 2]),
+    ]);
+}
+
+#[test]
+fn depth_first_expands_the_newest_state() {
+    let visits = totals(Strategy::DepthFirst);
+    let found: Vec<(i64, Vec<i64>)> = visits.into_iter().map(|visit| (visit.state, visit.path)).collect();
+    assert_eq!(found, vec![
+        (0, vec![]),
+        (1, vec![1]),
+        (2, vec![2]),
+        (3, vec![2, 1]),
+        (4, vec![2, 2]),
+    ]);
+}
+
+#[test]
+fn states_are_deduplicated_by_key_and_exits_are_not_expanded() {
+    let program = IntcodeProgram::from_raw_input(ECHO).unwrap();
+    // Paths as strings, but keyed by their last input, so only one path ends in each
+    let visits = explore(&program, String::new(), &[0, 1], Strategy::BreadthFirst, |path, input, _| {
+        Some(format!("{}{}", path, input)).filter(|path| path.len() <= 2)
+    }, |path| path.chars().last()).unwrap();
+    let found: Vec<(&str, bool)> = visits.iter().map(|visit| (visit.state.as_str(), visit.exited)).collect();
+    assert_eq!(found, vec![("", false), ("0", true), ("1", false)]);
+}
+
+#[test]
+fn forked_machines_keep_strict_decoding_and_protection() {
+    let echo = |program: &IntcodeProgram| explore(program, 0, &[1], Strategy::BreadthFirst,
+        |total, input, _| Some(total + input).filter(|&total| total <= 1), |&total| total);
+
+    // Storing the input writes to a read-only cell
+    let mut program = IntcodeProgram::from_raw_input(ECHO).unwrap();
+    program.protect(11..12, Protection::ReadOnly);
+    assert_eq!(echo(&program).unwrap_err().to_string(), "Store to 11 at 0 violates read-only region 11..12");
+
+    // Outputs the input through an instruction with an extra mode digit
+    let mut program = IntcodeProgram::from_raw_input("3,5,10004,5,99,0").unwrap();
+    assert_eq!(echo(&program).unwrap().len(), 2);
+    program.set_strict(true);
+    assert_eq!(echo(&program).unwrap_err().to_string(), "Extra digits 10000 above the parameter modes in instruction 10004 at 2");
+}
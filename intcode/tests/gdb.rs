@@ -0,0 +1,183 @@
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use intcode::gdb;
+use intcode::program::IntcodeProgram;
+
+struct Client {
+    stream: TcpStream,
+    ack: bool,
+    console: String,
+}
+
+fn start(memory: Vec<i64>) -> (Client, thread::JoinHandle<IntcodeProgram>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut program = IntcodeProgram::from_memory(memory);
+        let (stream, _) = listener.accept().unwrap();
+        stream.set_nodelay(true).unwrap();
+        gdb::serve(&mut program, stream).unwrap();
+        program
+    });
+    let stream = TcpStream::connect(address).unwrap();
+    stream.set_nodelay(true).unwrap();
+    (Client{ stream, ack: true, console: String::new() }, server)
+}
+
+fn hex_string(text: &str) -> String {
+    text.bytes().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn cell(value: i64) -> String {
+    value.to_le_bytes().iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+impl Client {
+    fn read_byte(&mut self) -> u8 {
+        let mut byte = [0u8];
+        self.stream.read_exact(&mut byte).unwrap();
+        byte[0]
+    }
+
+    fn read_packet(&mut self) -> String {
+        while self.read_byte() != b'$' {}
+        let mut data = vec![];
+        loop {
+            match self.read_byte() {
+                b'#' => break,
+                b => data.push(b),
+            }
+        }
+        let sum = [self.read_byte(), self.read_byte()];
+        let expected = data.iter().fold(0u8, |acc, b| acc.wrapping_add(*b));
+        assert_eq!(u8::from_str_radix(std::str::from_utf8(&sum).unwrap(), 16).unwrap(), expected);
+        if self.ack { self.stream.write_all(b"+").unwrap(); }
+        String::from_utf8(data).unwrap()
+    }
+
+    fn send(&mut self, command: &str) {
+        let sum = command.bytes().fold(0u8, |acc, b| acc.wrapping_add(b));
+        self.stream.write_all(format!("${}#{:02x}", command, sum).as_bytes()).unwrap();
+        if self.ack { assert_eq!(self.read_byte(), b'+'); }
+    }
+
+    // Sends a command and returns its reply, collecting console output on the way
+    fn request(&mut self, command: &str) -> String {
+        self.send(command);
+        loop {
+            let reply = self.read_packet();
+            match reply.strip_prefix('O') {
+                Some(hex) if !hex.is_empty() && reply != "OK" => {
+                    let bytes: Vec<u8> = (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect();
+                    self.console.push_str(&String::from_utf8(bytes).unwrap());
+                },
+                _ => return reply,
+            }
+        }
+    }
+}
+
+#[test]
+fn registers_and_memory() {
+    let (mut client, server) = start(vec![1101, 2, 3, 7, 99, 0, 0, 0]);
+    assert!(client.request("qSupported:multiprocess+").contains("qXfer:features:read+"));
+    assert_eq!(client.request("?"), "S05");
+    assert_eq!(client.request("g"), format!("{}{}", cell(0), cell(0)));
+    assert_eq!(client.request("m0,10"), format!("{}{}", cell(1101), cell(2)));
+
+    // Overwrite the first operand and point rb at the last cell
+    assert_eq!(client.request(&format!("M8,8:{}", cell(40))), "OK");
+    assert_eq!(client.request(&format!("P1={}", cell(56))), "OK");
+    assert_eq!(client.request("p1"), cell(56));
+    assert_eq!(client.request(&format!("P0={}", cell(3))), "E01"); // Not cell aligned
+
+    assert_eq!(client.request("s"), "S05");
+    assert_eq!(client.request("p0"), cell(32));
+    assert_eq!(client.request("m38,8"), cell(43));
+    assert_eq!(client.request("s"), "W00");
+    assert_eq!(client.request("D"), "OK");
+
+    let program = server.join().unwrap();
+    assert_eq!(program.load_position(7), 43);
+    assert_eq!(program.relative_base(), 7);
+}
+
+#[test]
+fn breakpoints_input_and_output() {
+    // Reads a number and outputs it doubled, twice
+    let memory = vec![3, 13, 1002, 13, 2, 13, 4, 13, 1105, 1, 0, 99, 99, 0];
+    let (mut client, server) = start(memory);
+    assert_eq!(client.request("QStartNoAckMode"), "OK");
+    client.ack = false;
+
+    assert_eq!(client.request("c"), "S15"); // Waiting for input
+    assert_eq!(client.request(&format!("qRcmd,{}", hex_string("input 5 6"))), "OK");
+    assert_eq!(client.request("Z0,30,1"), "OK");
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.request("p0"), cell(6 * 8));
+    assert_eq!(client.console, "");
+
+    assert_eq!(client.request("c"), "S05");
+    assert_eq!(client.console, "output: 10\n");
+    assert_eq!(client.request("z0,30,1"), "OK");
+    assert_eq!(client.request("c"), "S15");
+    assert_eq!(client.console, "output: 10\noutput: 12\n");
+    client.send("k");
+    server.join().unwrap();
+}
+
+#[test]
+fn interrupts_a_running_program() {
+    // Loops forever
+    let (mut client, server) = start(vec![1105, 1, 0]);
+    client.send("c");
+    thread::sleep(Duration::from_millis(50));
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.read_packet(), "S02");
+    assert_eq!(client.request("p0"), cell(0));
+
+    // The program can carry on afterwards
+    client.send("c");
+    client.stream.write_all(&[0x03]).unwrap();
+    assert_eq!(client.read_packet(), "S02");
+    client.send("k");
+    server.join().unwrap();
+}
+
+#[test]
+fn rejects_memory_ranges_past_the_end_of_the_address_space() {
+    let (mut client, server) = start(vec![99]);
+    assert_eq!(client.request("mfffffffffffffff8,10"), "E01");
+    assert_eq!(client.request(&format!("Mfffffffffffffff8,10:{}{}", cell(1), cell(2))), "E01");
+    assert_eq!(client.request("m0,8"), cell(99));
+    client.send("k");
+    server.join().unwrap();
+}
+
+#[test]
+fn reports_bad_instructions_on_the_console() {
+    let (mut client, server) = start(vec![1101, 2, 3, 5, 33]);
+    assert_eq!(client.request("c"), "S04");
+    assert_eq!(client.console, "error: Invalid opcode: 33\n");
+    assert_eq!(client.request("p0"), cell(4 * 8));
+    client.send("k");
+    server.join().unwrap();
+}
+
+#[test]
+fn rejects_packets_over_the_size_limit() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let mut program = IntcodeProgram::from_memory(vec![99]);
+        gdb::serve(&mut program, listener.accept().unwrap().0).map_err(|e| e.to_string())
+    });
+
+    // The stub gives up long before the packet would end
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream.write_all(b"$").unwrap();
+    let _ = stream.write_all(&vec![b'0'; 0x10000]);
+    assert_eq!(server.join().unwrap().unwrap_err(), "Packet is longer than the limit of 16384 bytes");
+}
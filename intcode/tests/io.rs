@@ -0,0 +1,276 @@
+use intcode::io::{self, ClosedPolicy, InputDevice, OutputDevice};
+use intcode::program::{Event, IntcodeProgram};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::io::{Cursor, Write};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+// Outputs 1 and 2, then exits
+const TWO_OUTPUTS: &str = "104,1,104,2,99";
+// Reads a value into 0, then exits
+const ONE_INPUT: &str = "3,0,99";
+// Outputs the sum of two inputs
+const SUM: &str = "3,0,3,1,1,0,1,0,4,0,99";
+
+// A writer whose contents can be looked at after it's been given away
+#[derive(Clone, Default)]
+struct Shared(Arc<Mutex<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> { self.0.lock().unwrap().write(buf) }
+    fn flush(&mut self) -> std::io::Result<()> { Ok(()) }
+}
+
+impl Shared {
+    fn contents(&self) -> String { String::from_utf8(self.0.lock().unwrap().clone()).unwrap() }
+}
+
+fn events(program: &mut IntcodeProgram) -> Vec<Event> {
+    let mut events = vec![];
+    loop {
+        let event = program.execute_until_event().unwrap();
+        let done = event != Event::ProducedOutput;
+        events.push(event);
+        if done { return events }
+    }
+}
+
+#[test]
+fn bounded_channels_block_writers_until_read() {
+    for capacity in 0..3 {
+        let (mut output, mut input) = io::channel_pair(Some(capacity), ClosedPolicy::Error);
+        let sent = Arc::new(AtomicUsize::new(0));
+        let writer = {
+            let sent = sent.clone();
+            thread::spawn(move || {
+                for v in 0..5 {
+                    output.put(v);
+                    sent.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+        };
+
+        // The writer gets as far as the capacity and no further until values are read
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(sent.load(Ordering::SeqCst), capacity, "capacity {}", capacity);
+        let read: Vec<i64> = (0..5).map(|_| input.get().unwrap()).collect();
+        writer.join().unwrap();
+        assert_eq!((read, sent.load(Ordering::SeqCst)), (vec![0, 1, 2, 3, 4], 5));
+    }
+}
+
+#[test]
+fn unbounded_channels_never_block() {
+    let (mut output, mut input) = io::channel_pair(None, ClosedPolicy::Error);
+    for v in 0..1000 { output.put(v); }
+    assert_eq!(input.get_maybe(), Some(0));
+}
+
+#[test]
+fn closed_policies_decide_what_happens_to_outputs() {
+    let run = |policy| {
+        let (output, input) = io::channel_pair(None, policy);
+        drop(input);
+        let mut program = IntcodeProgram::from_raw_input(TWO_OUTPUTS).unwrap();
+        program.replace_output(output);
+        (events(&mut program), program.get_all_output())
+    };
+
+    // An error stops the program, which is reported as the peer closing
+    assert!(run(ClosedPolicy::Error) == (vec![Event::PeerClosed], vec![]));
+    // Dropped outputs are lost, and the first one reports the peer closing
+    assert!(run(ClosedPolicy::Drop) == (vec![Event::PeerClosed], vec![]));
+    // Buffered outputs can still be collected
+    assert!(run(ClosedPolicy::Buffer) == (vec![Event::PeerClosed], vec![1]));
+
+    // Every policy reports the peer closing once. Error keeps the program at the output,
+    // while Drop and Buffer have taken it, so running on finishes the program.
+    for (policy, outputs) in [(ClosedPolicy::Error, None), (ClosedPolicy::Drop, Some(vec![])), (ClosedPolicy::Buffer, Some(vec![1, 2]))] {
+        let (output, input) = io::channel_pair(None, policy);
+        drop(input);
+        let mut program = IntcodeProgram::from_raw_input(TWO_OUTPUTS).unwrap();
+        program.replace_output(output);
+        assert!(program.execute_until_event().unwrap() == Event::PeerClosed);
+        match outputs {
+            None => {
+                assert!(program.execute_until_event().unwrap() == Event::PeerClosed);
+                assert_eq!(program.ip(), 0);
+            },
+            Some(outputs) => {
+                assert!(events(&mut program) == vec![Event::ProducedOutput, Event::Exited]);
+                assert_eq!(program.get_all_output(), outputs);
+            },
+        }
+    }
+
+    // Only Error fails try_put itself, but every policy marks the device closed
+    for (policy, fails) in [(ClosedPolicy::Error, true), (ClosedPolicy::Drop, false), (ClosedPolicy::Buffer, false)] {
+        let (mut output, input) = io::channel_pair(Some(1), policy);
+        drop(input);
+        assert_eq!(output.try_put(1).is_err(), fails);
+        assert!(output.is_closed());
+    }
+}
+
+#[test]
+fn readers_see_the_peer_close_once_drained() {
+    let (mut output, input) = io::channel_pair(None, ClosedPolicy::Error);
+    output.put(5);
+    drop(output);
+    let mut program = IntcodeProgram::from_raw_input(ONE_INPUT).unwrap();
+    program.replace_input(input);
+    assert!(program.execute_until_event().unwrap() == Event::Exited);
+    assert_eq!(program.load_position(0), 5);
+
+    let mut program = IntcodeProgram::from_raw_input(ONE_INPUT).unwrap();
+    let (output, input) = io::channel_pair(None, ClosedPolicy::Error);
+    drop(output);
+    program.replace_input(input);
+    assert!(program.execute_until_event().unwrap() == Event::PeerClosed);
+    assert_eq!(program.execute().unwrap_err().to_string(), "Input channel closed");
+}
+
+#[test]
+fn scripts_are_consumed_in_order_then_close() {
+    let mut device = io::DefaultInputDevice::script(&[1, 2]);
+    assert!(!device.is_closed());
+    assert_eq!((device.get().unwrap(), device.get_maybe()), (1, Some(2)));
+    assert!(device.is_closed());
+    assert_eq!(device.get().unwrap_err().to_string(), "Program requested more input than was provided");
+
+    let mut program = IntcodeProgram::from_raw_input(SUM).unwrap();
+    program.replace_input(io::DefaultInputDevice::script(&[3, 4]));
+    program.execute().unwrap();
+    assert_eq!(program.get_output(), Some(7));
+}
+
+#[test]
+fn exhausted_scripts_close_the_peer() {
+    let mut program = IntcodeProgram::from_raw_input(SUM).unwrap();
+    program.replace_input(io::DefaultInputDevice::script(&[3]));
+    assert!(program.execute_until_event().unwrap() == Event::PeerClosed);
+}
+
+#[test]
+fn exhausted_strict_input_asks_for_more_when_stepping() {
+    // Strict devices only fail reads that would block, so a driver can still supply input on events
+    let mut program = IntcodeProgram::from_raw_input(SUM).unwrap();
+    program.replace_input(io::DefaultInputDevice::strict());
+    assert!(program.execute_until_event().unwrap() == Event::InputRequired);
+    program.give_input(3);
+    assert!(program.step().unwrap().is_none());
+    assert!(program.step().unwrap() == Some(Event::InputRequired));
+    program.give_input(4);
+    assert!(program.execute_until_event().unwrap() == Event::ProducedOutput);
+    assert_eq!(program.get_output(), Some(7));
+
+    let mut program = IntcodeProgram::from_raw_input(SUM).unwrap();
+    program.replace_input(io::DefaultInputDevice::strict());
+    assert_eq!(program.execute().unwrap_err().to_string(), "Program requested more input than was provided");
+}
+
+#[test]
+fn reads_lines_and_writes_prompts() {
+    let prompts = Shared::default();
+    let mut program = IntcodeProgram::from_raw_input(SUM).unwrap();
+    program.replace_input(io::DefaultInputDevice::with_io(Cursor::new("3\n 4 \n"), prompts.clone()));
+    program.execute().unwrap();
+    assert_eq!(program.get_output(), Some(7));
+    assert_eq!(prompts.contents(), "Enter program input: Enter program input: ");
+
+    // Queued input comes first, and prompts can be changed or turned off
+    let prompts = Shared::default();
+    let mut device = io::DefaultInputDevice::with_io(Cursor::new("2\n3\nx\n"), prompts.clone());
+    device.put(1);
+    device.set_prompt(Some("> "));
+    assert_eq!((device.get().unwrap(), device.get().unwrap()), (1, 2));
+    device.set_prompt(None);
+    assert_eq!(device.get().unwrap(), 3);
+    assert_eq!(prompts.contents(), "> ");
+    assert_eq!(device.get().unwrap_err().to_string(), "Invalid input \"x\": invalid digit found in string");
+    assert_eq!(device.get().unwrap_err().to_string(), "Reached end of input");
+}
+
+#[test]
+fn chains_read_each_source_until_it_closes() {
+    let mut device = io::ChainInputDevice::new(vec![io::DefaultInputDevice::script(&[1, 2]), io::DefaultInputDevice::script(&[]), io::DefaultInputDevice::script(&[3])]);
+    device.put(0);
+    let read: Vec<i64> = (0..4).map(|_| device.get().unwrap()).collect();
+    assert_eq!(read, vec![0, 1, 2, 3]);
+    assert!(device.is_closed());
+    assert_eq!(device.get().unwrap_err().to_string(), "All input sources are closed");
+
+    // A source that's merely empty makes the program wait rather than skipping it
+    let (mut output, input) = io::channel_pair(None, ClosedPolicy::Error);
+    let mut program = IntcodeProgram::from_raw_input(SUM).unwrap();
+    program.replace_input(io::ChainInputDevice::new(vec![io::DefaultInputDevice::script(&[3]), input, io::DefaultInputDevice::script(&[9])]));
+    assert!(program.execute_until_event().unwrap() == Event::InputRequired);
+    output.put(4);
+    assert!(events(&mut program) == vec![Event::ProducedOutput, Event::Exited]);
+    assert_eq!(program.get_output(), Some(7));
+
+    // Once the channel closes, the chain moves on to the next source
+    let (output, input) = io::channel_pair(None, ClosedPolicy::Error);
+    let mut program = IntcodeProgram::from_raw_input(SUM).unwrap();
+    program.replace_input(io::ChainInputDevice::new(vec![io::DefaultInputDevice::script(&[3]), input, io::DefaultInputDevice::script(&[9])]));
+    drop(output);
+    program.execute().unwrap();
+    assert_eq!(program.get_output(), Some(12));
+}
+
+#[test]
+fn fallbacks_fill_in_when_nothing_is_waiting() {
+    let (mut output, input) = io::channel_pair(None, ClosedPolicy::Error);
+    let mut device = io::FallbackInputDevice::new(input, -1);
+    assert_eq!(device.get().unwrap(), -1);
+    output.put(5);
+    device.put(4);
+    assert_eq!((device.get().unwrap(), device.get_maybe(), device.get_maybe()), (4, Some(5), Some(-1)));
+
+    // So a program never has to wait for input
+    let mut program = IntcodeProgram::from_raw_input(SUM).unwrap();
+    program.replace_input(io::FallbackInputDevice::new(io::DefaultInputDevice::script(&[3]), -1));
+    assert!(events(&mut program) == vec![Event::ProducedOutput, Event::Exited]);
+    assert_eq!(program.get_output(), Some(2));
+}
+
+#[test]
+fn merges_take_values_in_arrival_order() {
+    let (first, second) = (mpsc::channel(), mpsc::channel());
+    let (tx_first, tx_second) = (first.0, second.0);
+    let mut device = io::merge(vec![first.1, second.1]);
+    for (tx, v) in [(&tx_second, 1), (&tx_first, 2), (&tx_second, 3)] {
+        tx.send(v).unwrap();
+        assert_eq!(device.get().unwrap(), v);
+    }
+
+    // Each receiver's values stay in order, however they're interleaved
+    for v in 0..100 {
+        tx_first.send(v).unwrap();
+        tx_second.send(100 + v).unwrap();
+    }
+    let read: Vec<i64> = (0..200).map(|_| device.get().unwrap()).collect();
+    assert_eq!(read.iter().cloned().filter(|&v| v < 100).collect::<Vec<i64>>(), (0..100).collect::<Vec<i64>>());
+    assert_eq!(read.iter().cloned().filter(|&v| v >= 100).collect::<Vec<i64>>(), (100..200).collect::<Vec<i64>>());
+}
+
+#[test]
+fn merges_close_once_every_sender_has_gone() {
+    let (first, second) = (mpsc::channel(), mpsc::channel());
+    let (tx_first, tx_second) = (first.0, second.0);
+    let mut device = io::merge(vec![first.1, second.1]);
+    tx_first.send(1).unwrap();
+    drop(tx_first);
+    assert_eq!(device.get().unwrap(), 1);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(device.get_maybe(), None);
+    assert!(!device.is_closed());
+
+    // Values sent before the last sender goes are still delivered
+    tx_second.send(2).unwrap();
+    drop(tx_second);
+    assert_eq!(device.get().unwrap(), 2);
+    assert_eq!(device.get().unwrap_err().to_string(), "Input channel closed");
+    assert!(device.is_closed());
+}
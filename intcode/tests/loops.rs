@@ -0,0 +1,67 @@
+use intcode::loops::InfiniteLoop;
+use intcode::program::IntcodeProgram;
+
+// Counts a cell down from 100000 without any I/O, then exits
+const COUNTDOWN: &str = "1101,100000,0,12,1001,12,-1,12,1005,12,4,99,0";
+
+// Runs with loop detection checking every `interval` instructions, returning the loop found
+fn detect(raw: &str, interval: usize) -> Option<InfiniteLoop> {
+    let mut program = IntcodeProgram::from_raw_input(raw).unwrap();
+    program.detect_loops(Some(interval));
+    match program.execute() {
+        Ok(()) => None,
+        Err(e) => Some(e.downcast_ref::<InfiniteLoop>().expect("only loops should fail").clone()),
+    }
+}
+
+#[test]
+fn detects_a_jump_to_itself() {
+    // States are only compared every interval, so the period found is a multiple of it
+    for &interval in &[1, 2, 7] {
+        let found = detect("1105,1,0", interval).expect("jumping to itself loops forever");
+        assert_eq!((found.period, found.lowest, found.highest), (interval as u64, 0, 0));
+    }
+    assert_eq!(detect("1105,1,0", 1).unwrap().to_string(),
+        "Infinite loop: the machine's state repeats every 1 instructions, executing addresses 0 to 0");
+}
+
+#[test]
+fn detects_longer_cycles_after_io() {
+    // Outputs 7, then moves the relative base up and back down forever
+    let mut program = IntcodeProgram::from_raw_input("104,7,109,1,109,-1,1105,1,2").unwrap();
+    program.detect_loops(Some(1));
+    let error = program.execute().expect_err("the relative base keeps returning to 0");
+    let found = error.downcast_ref::<InfiniteLoop>().unwrap();
+    assert_eq!((found.period, found.lowest, found.highest), (3, 2, 6));
+    assert_eq!(program.get_all_output(), vec![7]);
+}
+
+#[test]
+fn lets_long_running_programs_finish() {
+    for &interval in &[1, 7, 1000] {
+        assert_eq!(detect(COUNTDOWN, interval), None);
+    }
+}
+
+#[test]
+fn ignores_loops_doing_io() {
+    // Outputs 1 forever, so its state repeats but each time round is observable
+    let mut program = IntcodeProgram::from_raw_input("104,1,1105,1,0").unwrap();
+    program.detect_loops(Some(1));
+    for _ in 0..10000 { program.step().unwrap(); }
+    assert_eq!(program.get_all_output().len(), 5000);
+
+    // Echoes its input, going back to the same state each time a value is read
+    let mut program = IntcodeProgram::from_raw_input("3,7,4,7,1105,1,0,0").unwrap();
+    program.detect_loops(Some(1));
+    for _ in 0..1000 { program.give_input(5); }
+    for _ in 0..3000 { program.step().unwrap(); }
+    assert_eq!(program.get_all_output(), vec![5; 1000]);
+}
+
+#[test]
+fn is_off_by_default() {
+    let mut program = IntcodeProgram::from_raw_input("1105,1,0").unwrap();
+    for _ in 0..10000 { program.step().unwrap(); }
+    assert_eq!(program.ip(), 0);
+}
@@ -0,0 +1,74 @@
+use intcode::memmap::{record_frames, Heat, Region, Snapshot};
+use intcode::program::IntcodeProgram;
+
+// Sets rb to 20, writes 5 to the stack at rb + 0 and 7 to extended memory at 30, then exits
+const STORES: &str = "109,20,21101,5,0,0,1101,7,0,30,99";
+
+fn frames(raw: &str, interval: usize, budget: usize, limit: usize) -> Vec<Snapshot> {
+    let mut program = IntcodeProgram::from_raw_input(raw).unwrap();
+    record_frames(&mut program, interval, budget, limit).unwrap()
+}
+
+fn header(image: &[u8]) -> String {
+    let end = image.iter().enumerate().filter(|&(_, &b)| b == b'\n').nth(2).unwrap().0;
+    String::from_utf8(image[..=end].to_vec()).unwrap()
+}
+
+#[test]
+fn classifies_regions() {
+    let snapshot = frames(STORES, 0, 100, 1000).pop().unwrap();
+    assert_eq!((snapshot.instructions, snapshot.image_len, snapshot.cells.len()), (3, 11, 31));
+    assert_eq!((snapshot.cells[20], snapshot.cells[30]), (5, 7));
+
+    // Only the cells instructions were fetched from count as code
+    let regions: Vec<Region> = (0..11).map(|address| snapshot.region(address)).collect();
+    let (code, image) = (Region::Code, Region::Image);
+    assert_eq!(regions, vec![code, image, code, image, image, image, code, image, image, image, code]);
+    assert_eq!(snapshot.region(20), Region::Stack);
+    assert_eq!(snapshot.region(30), Region::Extended);
+    assert_eq!(snapshot.region(25), Region::Extended);
+
+    // The limit truncates what's captured
+    assert_eq!(frames(STORES, 0, 100, 15).pop().unwrap().cells.len(), 15);
+}
+
+#[test]
+fn images_have_a_pixel_per_scaled_cell() {
+    let snapshot = frames(STORES, 0, 100, 1000).pop().unwrap();
+    // 31 cells 8 wide is 4 rows, each cell 2x2 pixels
+    let ppm = snapshot.to_ppm(8, 2);
+    assert_eq!(header(&ppm), "P6\n16 8\n255\n");
+    let pixels = &ppm[header(&ppm).len()..];
+    assert_eq!(pixels.len(), 16 * 8 * 3);
+    let pixel = |x: usize, y: usize| &pixels[(y * 16 + x) * 3..(y * 16 + x + 1) * 3];
+    assert_eq!(pixel(1, 1), &[70, 130, 180], "code at 0");
+    assert_eq!(pixel(8, 0), &[36, 28, 54], "zero image cell at 4 is dimmed");
+    assert_eq!(pixel(8, 5), &[60, 179, 113], "stack at 20");
+    assert_eq!(pixel(15, 7), &[0, 0, 0], "past the end");
+
+    let pgm = snapshot.to_pgm(8, 1, Heat::Executions);
+    assert_eq!(header(&pgm), "P5\n8 4\n255\n");
+    let levels = &pgm[header(&pgm).len()..];
+    assert_eq!(levels.len(), 8 * 4);
+    let executed: Vec<usize> = (0..levels.len()).filter(|&i| levels[i] > 0).collect();
+    assert_eq!(executed, vec![0, 2, 6, 10]);
+    assert!(executed.iter().all(|&i| levels[i] == 255));
+}
+
+#[test]
+fn frames_are_captured_at_intervals_and_the_end() {
+    let instructions = |frames: Vec<Snapshot>| -> Vec<usize> { frames.iter().map(|f| f.instructions).collect() };
+    // The hlt isn't counted, as the run ends when it's reached
+    assert_eq!(instructions(frames(STORES, 2, 100, 1000)), vec![2, 3]);
+    assert_eq!(instructions(frames(STORES, 3, 100, 1000)), vec![3]);
+    assert_eq!(instructions(frames(STORES, 0, 100, 1000)), vec![3]);
+    assert_eq!(instructions(frames(STORES, 1, 2, 1000)), vec![1, 2]);
+    // Waiting for input ends the run
+    assert_eq!(instructions(frames("3,0,99", 1, 100, 1000)), vec![0]);
+
+    // Each frame shows memory as it was then
+    let frames = frames(STORES, 1, 100, 1000);
+    assert_eq!(frames[0].cells.get(20), None);
+    assert_eq!((frames[1].cells.len(), frames[1].cells[20]), (21, 5));
+    assert_eq!(frames[2].cells.len(), 31);
+}
@@ -0,0 +1,56 @@
+use intcode::network::{Network, Policy, Quantum};
+use intcode::program::{Event, IntcodeProgram};
+
+// Outputs 1, 2 and 3, then exits
+const COUNT: &str = "104,1,104,2,104,3,99";
+
+// The outputs of three counting machines, tagged with the machine, in the order they were produced
+fn outputs(policy: Policy) -> Vec<(usize, i64)> {
+    let mut network = Network::new(policy);
+    for _ in 0..3 { network.add(IntcodeProgram::from_raw_input(COUNT).unwrap()); }
+    let mut outputs = vec![];
+    while !network.finished() {
+        network.tick(|id, program, event| {
+            if event == Event::ProducedOutput { outputs.push((id, program.get_output().unwrap())); }
+            Ok(())
+        }).unwrap();
+    }
+    outputs
+}
+
+#[test]
+fn round_robin_takes_turns_in_id_order() {
+    let expected: Vec<(usize, i64)> = (1..=3).flat_map(|value| (0..3).map(move |id| (id, value))).collect();
+    assert_eq!(outputs(Policy::round_robin()), expected);
+}
+
+#[test]
+fn seeds_reproduce_interleavings() {
+    for seed in 0..10 {
+        assert_eq!(outputs(Policy::random(seed, 4)), outputs(Policy::random(seed, 4)));
+    }
+    let interleavings: Vec<Vec<(usize, i64)>> = (0..10).map(|seed| outputs(Policy::random(seed, 4))).collect();
+    assert!(interleavings.iter().any(|outputs| *outputs != interleavings[0]));
+
+    // Each machine's own outputs stay in order whatever the interleaving
+    for outputs in interleavings {
+        for id in 0..3 {
+            let own: Vec<i64> = outputs.iter().filter(|&&(from, _)| from == id).map(|&(_, value)| value).collect();
+            assert_eq!(own, vec![1, 2, 3]);
+        }
+    }
+}
+
+#[test]
+fn short_quanta_spread_a_machine_over_several_ticks() {
+    let mut network = Network::new(Policy{ quantum: Quantum::Fixed(1), ..Policy::round_robin() });
+    // An add, which raises no event, then an output and hlt
+    network.add(IntcodeProgram::from_raw_input("1101,1,1,0,104,1,99").unwrap());
+    let mut events = 0;
+    while !network.finished() {
+        network.tick(|_, _, _| { events += 1; Ok(()) }).unwrap();
+    }
+    assert_eq!((network.ticks(), events), (3, 2));
+    // Stepping through quanta is still timed
+    assert!(network.programs()[0].stats().elapsed > std::time::Duration::ZERO);
+}
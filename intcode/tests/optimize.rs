@@ -0,0 +1,118 @@
+use intcode::compiler::compile;
+use intcode::optimize::{differential_check, optimize, Optimized, Options};
+use intcode::program::IntcodeProgram;
+use std::fs;
+
+fn optimized(image: &[i64]) -> Optimized {
+    optimize(image, &Options::default()).unwrap()
+}
+
+fn outputs(image: &[i64], inputs: &[i64]) -> Vec<i64> {
+    let mut program = IntcodeProgram::from_memory(image.to_vec());
+    for &input in inputs { program.give_input(input); }
+    program.execute().unwrap();
+    program.get_all_output()
+}
+
+#[test]
+fn folds_constant_operands() {
+    // Outputs [9] * 3, where 9 is never written
+    let image = vec![1002, 9, 3, 10, 4, 10, 99, 0, 0, 7, 0];
+    let result = optimized(&image);
+    assert_eq!((result.folded, result.dead_stores, result.jumps_threaded), (1, 0, 0));
+    assert_eq!(&result.memory[..4], &[1101, 21, 0, 10]);
+    assert_eq!(outputs(&result.memory, &[]), vec![21]);
+
+    // Constant jumps become unconditional or are disabled
+    let image = vec![1006, 7, 6, 104, 1, 99, 99, 0];
+    let result = optimized(&image);
+    assert_eq!(&result.memory[..3], &[1105, 1, 6]);
+    assert_eq!(outputs(&result.memory, &[]), outputs(&image, &[]));
+}
+
+#[test]
+fn instructions_already_constant_are_left_alone() {
+    // A store of 0 + 14, which is already as folded as it gets, and a jump on a constant
+    let image = vec![1101, 0, 14, 10, 1106, 0, 8, 99, 4, 10, 99];
+    let result = optimized(&image);
+    assert_eq!(result.folded, 0);
+    assert_eq!(result.memory, image);
+}
+
+#[test]
+fn removes_dead_stores_when_asked() {
+    // Stores to 9, which nothing reads
+    let image = vec![1101, 1, 2, 9, 104, 5, 99, 0, 0, 0];
+    assert_eq!(optimized(&image).dead_stores, 0);
+    let result = optimize(&image, &Options{ remove_dead_stores: true, ..Default::default() }).unwrap();
+    assert_eq!((result.folded, result.dead_stores, result.dead_cells.clone()), (0, 1, vec![9]));
+    assert_eq!(&result.memory[..4], &[1105, 1, 4, 0]);
+    assert_eq!(outputs(&result.memory, &[]), vec![5]);
+}
+
+#[test]
+fn keeps_stores_the_host_reads_after_running() {
+    // Like aoc_2, leaves its answer in memory rather than outputting it: [10] <- [9] * 3
+    let image = vec![1002, 9, 3, 10, 99, 0, 0, 0, 0, 14, 0];
+    let answer = |image: &[i64]| {
+        let mut program = IntcodeProgram::from_memory(image.to_vec());
+        program.execute().unwrap();
+        program.load_position(10)
+    };
+    let result = optimized(&image);
+    assert_eq!((result.folded, result.dead_stores), (1, 0));
+    assert_eq!(answer(&result.memory), 42);
+    differential_check(&image, &result.memory, &[], &[vec![]], 1000).unwrap();
+
+    // Removing the store loses the answer, which the differential check sees unless told to ignore the cell
+    let result = optimize(&image, &Options{ remove_dead_stores: true, ..Default::default() }).unwrap();
+    assert_eq!(answer(&result.memory), 0);
+    let error = differential_check(&image, &result.memory, &[], &[vec![]], 1000).unwrap_err().to_string();
+    assert_eq!(error, "Input set 0 differs: cell 10 ends as 42 in the original and 0 optimised");
+    differential_check(&image, &result.memory, &result.dead_cells, &[vec![]], 1000).unwrap();
+
+    // Cells the host reads keep their stores
+    let options = Options{ remove_dead_stores: true, host_reads: vec![10], ..Default::default() };
+    let result = optimize(&image, &options).unwrap();
+    assert_eq!(result.dead_stores, 0);
+    assert_eq!(answer(&result.memory), 42);
+}
+
+#[test]
+fn threads_jumps_to_jumps() {
+    let image = vec![1105, 1, 3, 1105, 1, 6, 104, 1, 99];
+    let result = optimized(&image);
+    assert_eq!(result.jumps_threaded, 1);
+    assert_eq!(&result.memory[..3], &[1105, 1, 6]);
+    assert_eq!(outputs(&result.memory, &[]), vec![1]);
+}
+
+#[test]
+fn leaves_jumps_that_are_rewritten_at_runtime() {
+    // Stores 14 over the target of the jump at 7 before jumping to it, so it outputs 2, not 1
+    let image = vec![1101, 0, 14, 9, 1105, 1, 7, 1105, 1, 11, 99, 104, 1, 99, 104, 2, 99];
+    let result = optimized(&image);
+    assert_eq!(result.jumps_threaded, 0);
+    assert_eq!(outputs(&result.memory, &[]), vec![2]);
+    differential_check(&image, &result.memory, &[], &[vec![]], 1000).unwrap();
+
+    // Threading it anyway is caught by the differential check
+    let mut threaded = image.clone();
+    threaded[6] = 11;
+    let error = differential_check(&image, &threaded, &[], &[vec![]], 1000).unwrap_err().to_string();
+    assert_eq!(error, "Input set 0 differs: original output [2] and exited, optimised output [1] and exited");
+}
+
+#[test]
+fn compiled_programs_behave_the_same_optimised() {
+    let options = Options{ assume_stack_outside_image: true, remove_dead_stores: true, ..Default::default() };
+    for file in ["calls", "fib", "operators"] {
+        let image = compile(&fs::read_to_string(format!("tests/programs/{}.ic", file)).unwrap()).unwrap();
+        let result = optimize(&image, &options).unwrap();
+        let inputs: Vec<Vec<i64>> = (0..8).map(|n| vec![n, n + 1, n + 2]).collect();
+        differential_check(&image, &result.memory, &result.dead_cells, &inputs, 1_000_000).unwrap();
+    }
+    // Without assuming where the stack is, relative operands could reach anything
+    let image = compile(&fs::read_to_string("tests/programs/fib.ic").unwrap()).unwrap();
+    assert!(optimize(&image, &Options::default()).is_err());
+}
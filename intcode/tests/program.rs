@@ -0,0 +1,82 @@
+use intcode::program::{Event, IntcodeInstruction, IntcodeProgram, ParameterMode};
+
+#[test]
+fn decodes_modes_without_moving_the_ip() {
+    let program = IntcodeProgram::from_raw_input("1002,4,3,4,33,204,-7,99").unwrap();
+    match program.decode(0).unwrap() {
+        IntcodeInstruction::Mul{o1, o2, dest} => {
+            assert_eq!((o1.param, o1.mode), (4, ParameterMode::Position));
+            assert_eq!((o2.param, o2.mode), (3, ParameterMode::Immediate));
+            assert_eq!((dest.param, dest.mode), (4, ParameterMode::Position));
+        },
+        other => panic!("Expected mul, got {}", other),
+    }
+    let output = program.decode(5).unwrap();
+    assert_eq!((output.length(), output.to_string()), (2, String::from("out: [rb + -7]")));
+    assert_eq!(program.decode(0).unwrap().to_string(), "mul: [4] <- [4] * 3");
+    assert_eq!(program.decode(4).unwrap_err().to_string(), "Invalid opcode: 33");
+    assert_eq!(program.ip(), 0);
+}
+
+#[test]
+fn steps_report_events_and_leave_the_ip_at_blocking_instructions() {
+    // Reads a value, outputs it, then exits
+    let mut program = IntcodeProgram::from_raw_input("3,0,4,0,99").unwrap();
+    assert!(program.step().unwrap() == Some(Event::InputRequired));
+    assert_eq!(program.ip(), 0);
+
+    program.give_input(42);
+    assert!(program.step().unwrap().is_none());
+    assert_eq!(program.ip(), 2);
+    assert!(program.step().unwrap() == Some(Event::ProducedOutput));
+    assert_eq!(program.get_output(), Some(42));
+
+    // Exiting leaves the ip at the hlt, so stepping again exits again
+    assert!(program.step().unwrap() == Some(Event::Exited));
+    assert_eq!(program.ip(), 4);
+    assert!(program.step().unwrap() == Some(Event::Exited));
+}
+
+#[test]
+fn sets_registers_within_range() {
+    // Moves the relative base by 5 and outputs the cell 5 below it
+    let mut program = IntcodeProgram::from_raw_input("109,5,204,-5,99").unwrap();
+    program.set_ip(2).unwrap();
+    program.set_relative_base(7).unwrap();
+    assert!(program.step().unwrap() == Some(Event::ProducedOutput));
+    assert_eq!(program.get_output(), Some(204));
+    assert_eq!((program.ip(), program.relative_base()), (4, 7));
+
+    assert_eq!(program.set_ip(usize::MAX).unwrap_err().to_string(), format!("Address {} is out of range", usize::MAX));
+    assert_eq!(program.set_relative_base(-1).unwrap_err().to_string(), "Relative base -1 is out of range");
+    assert_eq!((program.ip(), program.relative_base()), (4, 7));
+}
+
+#[test]
+fn reads_and_writes_cells_in_and_past_the_image() {
+    let mut program = IntcodeProgram::from_raw_input("1,2,3").unwrap();
+    program.write(1, 20).unwrap();
+    program.write(10, 7).unwrap();
+    program.write(12, 0).unwrap();
+    assert_eq!((program.read(1).unwrap(), program.read(10).unwrap(), program.read(11).unwrap()), (20, 7, 0));
+    assert_eq!(program.image_len(), 3);
+    assert_eq!(program.read_range(1..5).unwrap(), vec![20, 3, 0, 0]);
+    assert!(program.read_range(2..2).unwrap().is_empty());
+    assert_eq!(program.image_slice(0..3).unwrap(), &[1, 20, 3]);
+    // Only non-zero cells beyond the image are listed
+    assert_eq!(program.extended_cells(), vec![(10, 7)]);
+}
+
+#[test]
+fn rejects_invalid_addresses_and_ranges() {
+    let mut program = IntcodeProgram::from_raw_input("1,2,3").unwrap();
+    let far = i64::MAX as usize + 1;
+    assert_eq!(program.read(far).unwrap_err().to_string(), format!("Address {} is out of range", far));
+    assert_eq!(program.write(far, 1).unwrap_err().to_string(), format!("Address {} is out of range", far));
+    let (start, end) = (3, 1);
+    assert_eq!(program.read_range(start..end).unwrap_err().to_string(), "Invalid memory range 3..1");
+    assert_eq!(program.read_range(0..far + 1).unwrap_err().to_string(), format!("Address {} is out of range", far));
+    // Ranges of valid addresses are still capped in length
+    assert_eq!(program.read_range(0..1 << 30).unwrap_err().to_string(), "Range 0..1073741824 is longer than 16777216 cells");
+    assert_eq!(program.image_slice(1..4).unwrap_err().to_string(), "Range 1..4 extends past the image of 3 cells");
+}
@@ -0,0 +1,10 @@
+; Outputs each input doubled until it reads 0
+main:
+    in [value]
+    jez [value], done
+    mul [value], 2, [value]
+    out [value]
+    jmp main
+done:
+    hlt
+value: .data 0
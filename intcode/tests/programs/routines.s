@@ -0,0 +1,40 @@
+; Routines following the assembler's stack convention, for calling from the host
+    jmp main
+; n -> n!, left in n's slot
+fact:
+    jez [rb - 2], fact_base
+    add [rb - 2], -1, [rb]
+    arb 1
+    call fact
+    arb -1
+    mul [rb], [rb - 2], [rb - 2]
+    ret
+fact_base:
+    add 1, 0, [rb - 2]
+    ret
+; a, b -> a - b, left in b's slot
+sub:
+    mul [rb - 2], -1, [rb - 2]
+    add [rb - 3], [rb - 2], [rb - 2]
+    ret
+; Prints its argument, and counts how often it was called
+log:
+    out [rb - 2]
+    add [calls], 1, [calls]
+    ret
+main:
+    arb stack
+    push 5
+    call fact
+    pop [result]
+    out [result]
+finish: hlt
+result: .data 0
+calls: .data 0
+stack:
+; Exported so the host can find the routines
+    .global fact
+    .global sub
+    .global log
+    .global finish
+    .global calls
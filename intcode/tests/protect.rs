@@ -0,0 +1,68 @@
+use intcode::program::IntcodeProgram;
+use intcode::protect::{Fault, FaultKind, Protection};
+
+fn fault(program: &mut IntcodeProgram) -> Fault {
+    let error = program.execute().expect_err("program should fault");
+    error.downcast_ref::<Fault>().expect("error should be a fault").clone()
+}
+
+#[test]
+fn read_only_regions_fault_on_stores() {
+    let mut program = IntcodeProgram::from_raw_input("1101,1,2,0,99").unwrap();
+    program.protect(0..5, Protection::ReadOnly);
+    let fault = fault(&mut program);
+    assert_eq!(fault, Fault{ kind: FaultKind::Write, address: 0, ip: 0, region: Some((0..5, Protection::ReadOnly)) });
+    assert_eq!(fault.to_string(), "Store to 0 at 0 violates read-only region 0..5");
+    // The program is left at the faulting instruction, with memory untouched
+    assert_eq!((program.ip(), program.load_position(0)), (0, 1101));
+}
+
+#[test]
+fn no_execute_and_code_regions_fault_on_fetches() {
+    let mut program = IntcodeProgram::from_raw_input("1105,1,4,0,99").unwrap();
+    program.protect(4..5, Protection::NoExecute);
+    assert_eq!(fault(&mut program).to_string(), "Instruction fetch from 4 at 4 violates no-execute region 4..5");
+
+    // Once there's a code region, fetching from anywhere else faults
+    let mut program = IntcodeProgram::from_raw_input("1105,1,3,99").unwrap();
+    program.protect(0..3, Protection::Code);
+    let fault = fault(&mut program);
+    assert_eq!((fault.kind, fault.address, &fault.region), (FaultKind::Execute, 3, &None));
+    assert_eq!(fault.to_string(), "Instruction fetch from 3 at 3 is outside every code region");
+}
+
+#[test]
+fn later_regions_take_priority() {
+    // Stores to 9, inside a read-only region but also a later no-execute one
+    let mut program = IntcodeProgram::from_raw_input("1101,1,2,9,99,0,0,0,0,0").unwrap();
+    program.protect(0..10, Protection::ReadOnly);
+    program.protect(9..10, Protection::NoExecute);
+    program.execute().unwrap();
+    assert_eq!(program.load_position(9), 3);
+    assert_eq!(program.memory_protection().unwrap().region(9), Some(&(9..10, Protection::NoExecute)));
+}
+
+#[test]
+fn reports_instructions_executed_after_being_written() {
+    // Overwrites the opcode at 4 with 104, turning it into an output of 7
+    let mut program = IntcodeProgram::from_raw_input("1101,104,0,4,1,7,99").unwrap();
+    program.detect_self_modification(true);
+    program.execute().unwrap();
+    assert_eq!(program.get_output(), Some(7));
+    let modifications: Vec<String> = program.memory_protection().unwrap().self_modifications().map(|m| m.to_string()).collect();
+    assert_eq!(modifications, vec!["4 written at 0, executed at 4"]);
+}
+
+#[test]
+fn detecting_self_modification_keeps_regions() {
+    let mut program = IntcodeProgram::from_raw_input("1101,1,2,0,99").unwrap();
+    program.protect(0..1, Protection::ReadOnly);
+    program.detect_self_modification(true);
+    assert_eq!(program.memory_protection().unwrap().regions(), &[(0..1, Protection::ReadOnly)]);
+    assert_eq!(fault(&mut program).kind, FaultKind::Write);
+
+    program.detect_self_modification(false);
+    assert!(program.memory_protection().is_none());
+    program.execute().unwrap();
+    assert_eq!(program.load_position(0), 3);
+}
@@ -0,0 +1,111 @@
+use intcode::asm::assemble;
+use intcode::program::{Event, IntcodeProgram};
+use intcode::scan::{Filter, MemorySnapshot, Scanner};
+
+// Counts up by one and by two, mirroring the count in extended memory and outputting it each time
+const COUNTERS: &str = "
+loop:
+    add [count], 1, [count]
+    add [double], 2, [double]
+    add [count], 0, [1000]
+    out [count]
+    jmp loop
+count: .data 0
+double: .data 0
+";
+const COUNT: usize = 17;
+const DOUBLE: usize = 18;
+
+fn next_output(program: &mut IntcodeProgram) -> i64 {
+    assert!(program.execute_until_event().unwrap() == Event::ProducedOutput);
+    program.get_output().unwrap()
+}
+
+#[test]
+fn locates_a_counter() {
+    let mut program = IntcodeProgram::from_memory(assemble(COUNTERS).unwrap());
+    let mut scanner = Scanner::new(&program);
+    assert_eq!(scanner.candidates(), None);
+
+    // The mirror in extended memory only appears once the program runs, but is still a candidate
+    assert_eq!(next_output(&mut program), 1);
+    assert_eq!(scanner.scan(&program, Filter::Increased), 3);
+    assert_eq!(scanner.candidates(), Some(&[COUNT, DOUBLE, 1000][..]));
+
+    assert_eq!(next_output(&mut program), 2);
+    assert_eq!(scanner.scan(&program, Filter::IncreasedBy(1)), 2);
+    assert_eq!(scanner.scan(&program, Filter::Unchanged), 2);
+    assert_eq!(scanner.scan(&program, Filter::Equals(2)), 2);
+    assert_eq!(scanner.values(&program), vec![(COUNT, 2), (1000, 2)]);
+
+    assert_eq!(next_output(&mut program), 3);
+    assert_eq!(scanner.scan(&program, Filter::Decreased), 0);
+    scanner.reset(&program);
+    assert_eq!(scanner.candidates(), None);
+}
+
+#[test]
+fn narrows_every_cell_on_the_first_scan() {
+    let mut program = IntcodeProgram::from_memory(assemble(COUNTERS).unwrap());
+    let mut scanner = Scanner::new(&program);
+    next_output(&mut program);
+    // Everything but the two counters and the mirror stays the same
+    assert_eq!(scanner.scan(&program, Filter::Unchanged), program.image_len() - 2);
+    assert_eq!(scanner.scan(&program, Filter::Changed), 0);
+}
+
+#[test]
+fn finds_extended_cells_appearing_after_the_first_scan() {
+    let mut program = IntcodeProgram::from_memory(assemble("
+    out [count]
+    add [count], 1, [count]
+    add [count], 0, [2000]
+    out [count]
+    hlt
+count: .data 0
+").unwrap());
+    let count = program.image_len() - 1;
+    let mut scanner = Scanner::new(&program);
+    assert_eq!(next_output(&mut program), 0);
+    assert_eq!(scanner.scan(&program, Filter::Unchanged), program.image_len());
+
+    // The mirror wasn't in either snapshot of the first scan but is picked up by the next
+    assert_eq!(next_output(&mut program), 1);
+    assert_eq!(scanner.scan(&program, Filter::Increased), 2);
+    assert_eq!(scanner.candidates(), Some(&[count, 2000][..]));
+}
+
+#[test]
+fn cells_appearing_later_must_have_passed_earlier_scans() {
+    let source = "
+    out [count]
+    add [count], 1, [2000]
+    out [count]
+    hlt
+count: .data 7
+";
+    let mut program = IntcodeProgram::from_memory(assemble(source).unwrap());
+    let mut scanner = Scanner::new(&program);
+    next_output(&mut program);
+    assert_eq!(scanner.scan(&program, Filter::Equals(7)), 1);
+
+    // The mirror was 0 when the count was found to be 7, so it can't be a candidate now
+    next_output(&mut program);
+    assert_eq!(scanner.scan(&program, Filter::Changed), 0);
+    assert_eq!(scanner.candidates(), Some(&[][..]));
+}
+
+#[test]
+fn snapshots_read_past_the_image() {
+    let mut program = IntcodeProgram::from_memory(assemble(COUNTERS).unwrap());
+    next_output(&mut program);
+    let snapshot = MemorySnapshot::capture(&program);
+    assert_eq!((snapshot.get(COUNT), snapshot.get(DOUBLE), snapshot.get(1000), snapshot.get(2000)), (1, 2, 1, 0));
+}
+
+#[test]
+fn filters_compare_before_and_after() {
+    assert!(Filter::IncreasedBy(-3).matches(5, 2));
+    assert!(!Filter::IncreasedBy(1).matches(i64::MAX, i64::MIN));
+    assert!(Filter::Changed.matches(1, 2) && !Filter::Changed.matches(2, 2));
+}
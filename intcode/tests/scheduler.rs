@@ -0,0 +1,170 @@
+use intcode::asm::assemble;
+use intcode::io::{ChannelInputDevice, ChannelOutputDevice, DefaultInputDevice};
+use intcode::program::IntcodeProgram;
+use intcode::scheduler::{Outcome, Scheduler};
+use std::io::Cursor;
+use std::sync::mpsc::channel;
+use std::thread;
+use std::time::Duration;
+
+// Reads a value and outputs it, then exits
+const ECHO: &str = "3,0,4,0,99";
+// Echoes every value it reads, forever
+const ECHO_ALL: &str = "3,7,4,7,1105,1,0,0";
+
+// Passes on each value it reads plus one, until it reads `limit`, which it passes on unchanged before exiting
+fn increment(limit: i64) -> IntcodeProgram {
+    let source = format!("
+    loop:
+        in [x]
+        lt [x], {}, [t]
+        jez [t], done
+        add [x], 1, [x]
+        out [x]
+        jmp loop
+    done:
+        out [x]
+        hlt
+    x: .data 0
+    t: .data 0
+    ", limit);
+    IntcodeProgram::from_memory(assemble(&source).unwrap())
+}
+
+fn add(scheduler: &mut Scheduler, source: &str) -> usize {
+    scheduler.add(IntcodeProgram::from_raw_input(source).unwrap())
+}
+
+// Machines in a ring passing a value from 0 up to `limit`, seeded from outside
+fn ring(machines: usize, limit: i64, scheduler: &mut Scheduler) {
+    let ids: Vec<usize> = (0..machines).map(|_| scheduler.add(increment(limit))).collect();
+    for (i, &id) in ids.iter().enumerate() {
+        scheduler.connect(id, ids[(i + 1) % machines]).unwrap();
+    }
+    scheduler.mailbox(ids[0]).unwrap().send(0);
+}
+
+#[test]
+fn rings_pass_values_until_every_machine_exits() {
+    let mut scheduler = Scheduler::with_workers(2, 3);
+    ring(5, 50, &mut scheduler);
+    let reports = scheduler.run();
+
+    assert!(reports.iter().all(|report| report.outcome == Outcome::Exited));
+    assert!(reports.iter().all(|report| report.program.stats().elapsed > std::time::Duration::ZERO));
+    // The machine that first reads 50 has exited by the time the one before it passes 50 on
+    let unread: Vec<Vec<i64>> = reports.iter().map(|report| report.unread.clone()).collect();
+    assert_eq!(unread, vec![vec![50], vec![], vec![], vec![], vec![]]);
+}
+
+#[test]
+fn runs_a_large_number_of_machines() {
+    let mut scheduler = Scheduler::with_workers(4, 10);
+    ring(1000, 2500, &mut scheduler);
+    let reports = scheduler.run();
+
+    assert_eq!(reports.len(), 1000);
+    assert!(reports.iter().all(|report| report.outcome == Outcome::Exited));
+    let unread: Vec<usize> = reports.iter().enumerate().filter(|(_, report)| !report.unread.is_empty()).map(|(id, _)| id).collect();
+    assert_eq!(unread, vec![500]);
+    // Six instructions to pass on each value below the limit, then four to pass on the limit and exit
+    let executed: u64 = reports.iter().map(|report| report.executed).sum();
+    assert_eq!(executed, 2500 * 6 + 1000 * 4);
+}
+
+#[test]
+fn reports_machines_waiting_on_each_other_as_blocked() {
+    let mut scheduler = Scheduler::with_workers(2, 100);
+    let (a, b) = (add(&mut scheduler, ECHO), add(&mut scheduler, ECHO));
+    scheduler.connect(a, b).unwrap();
+    scheduler.connect(b, a).unwrap();
+    let done = add(&mut scheduler, "104,1,99");
+    let reports = scheduler.run();
+
+    let outcomes: Vec<Outcome> = reports.iter().map(|report| report.outcome.clone()).collect();
+    assert_eq!(outcomes, vec![Outcome::Blocked, Outcome::Blocked, Outcome::Exited]);
+    assert_eq!((reports[a].executed, reports[b].executed, reports[done].executed), (0, 0, 1));
+}
+
+#[test]
+fn parked_machines_wait_for_external_senders() {
+    let mut scheduler = Scheduler::with_workers(2, 100);
+    let id = add(&mut scheduler, ECHO);
+    let sender = scheduler.mailbox(id).unwrap();
+    let later = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        sender.clone().send(7);
+    });
+    let mut reports = scheduler.run();
+    later.join().unwrap();
+
+    assert!(reports[0].outcome == Outcome::Exited);
+    assert_eq!(reports[0].program.get_all_output(), vec![7]);
+}
+
+#[test]
+fn dropping_external_senders_lets_blocked_machines_be_reported() {
+    let mut scheduler = Scheduler::with_workers(2, 100);
+    let id = add(&mut scheduler, ECHO);
+    let sender = scheduler.mailbox(id).unwrap();
+    let later = thread::spawn(move || {
+        thread::sleep(Duration::from_millis(20));
+        drop(sender);
+    });
+    let reports = scheduler.run();
+    later.join().unwrap();
+
+    assert!(reports[0].outcome == Outcome::Blocked);
+}
+
+#[test]
+fn polls_machines_with_their_own_devices() {
+    let mut scheduler = Scheduler::with_workers(2, 100);
+    let (input, receiver) = channel();
+    let (output, outputs) = channel();
+    let mut program = IntcodeProgram::from_raw_input(ECHO_ALL).unwrap();
+    program.replace_input(ChannelInputDevice::new(receiver));
+    program.replace_output(ChannelOutputDevice::new(output));
+    scheduler.add_polled(program);
+    // Polled machines have no mailbox
+    assert_eq!(scheduler.mailbox(0).err().unwrap().to_string(), "Machine 0 has no mailbox");
+    assert_eq!(scheduler.mailbox(1).err().unwrap().to_string(), "No machine 1");
+
+    let feeder = thread::spawn(move || {
+        for value in 1..=3 {
+            thread::sleep(Duration::from_millis(5));
+            input.send(value).unwrap();
+        }
+    });
+    let reports = scheduler.run();
+    feeder.join().unwrap();
+
+    // Once the values run out and the sender has gone, the machine's input reports its peer closed
+    assert!(reports[0].outcome == Outcome::PeerClosed);
+    assert_eq!(outputs.try_iter().collect::<Vec<i64>>(), vec![1, 2, 3]);
+}
+
+#[test]
+fn gives_up_on_polled_machines_whose_devices_stay_empty() {
+    // Reading from an exhausted stream never produces input, but never reports closing either
+    let mut scheduler = Scheduler::with_workers(2, 100);
+    let mut program = IntcodeProgram::from_raw_input(ECHO).unwrap();
+    program.replace_input(DefaultInputDevice::with_io(Cursor::new(""), std::io::sink()));
+    scheduler.add_polled(program);
+    let echo = add(&mut scheduler, ECHO);
+    scheduler.mailbox(echo).unwrap().send(5);
+
+    let reports = scheduler.run();
+    assert!(reports[0].outcome == Outcome::Blocked);
+    assert_eq!(reports[0].executed, 0);
+    assert!(reports[1].outcome == Outcome::Exited);
+}
+
+#[test]
+fn reports_failures() {
+    let mut scheduler = Scheduler::with_workers(1, 100);
+    add(&mut scheduler, "1101,1,1,0,42");
+    let reports = scheduler.run();
+    assert!(matches!(reports[0].outcome, Outcome::Failed(_)));
+    assert_eq!(reports[0].executed, 1);
+}
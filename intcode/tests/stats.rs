@@ -0,0 +1,79 @@
+use intcode::program::{Event, IntcodeProgram};
+use intcode::stats::Stats;
+
+// Reads x, writes 3x + 1 to extended memory and outputs it through the relative base, then
+// outputs 3x and moves the relative base below 0 before exiting
+const KNOWN: &str = "3,20,1002,20,3,21,1001,21,1,1000,109,5,204,995,4,21,109,-10,99,0,0,0";
+
+fn run(raw: &str, inputs: &[i64]) -> IntcodeProgram {
+    let mut program = IntcodeProgram::from_raw_input(raw).unwrap();
+    for &input in inputs { program.give_input(input); }
+    program.execute().unwrap();
+    program
+}
+
+#[test]
+fn counts_a_known_run() {
+    let mut program = run(KNOWN, &[4]);
+    assert_eq!(program.get_all_output(), vec![13, 12]);
+    let stats = program.stats();
+    let counts: Vec<u64> = [1, 2, 3, 4, 9, 99, 5].iter().map(|&opcode| stats.executed_opcode(opcode)).collect();
+    assert_eq!(counts, vec![1, 1, 1, 2, 2, 1, 0]);
+    assert_eq!(stats.executed(), 8);
+    assert_eq!((stats.inputs, stats.outputs), (1, 2));
+    assert_eq!(stats.highest_address, 1000);
+    assert_eq!(stats.peak_extended_memory, 1);
+    // The highest base reached, not where it ended up
+    assert_eq!((stats.max_relative_base, program.relative_base()), (5, -5));
+}
+
+#[test]
+fn max_relative_base_stays_at_zero_when_the_base_only_goes_down() {
+    let program = run("109,-3,99", &[]);
+    assert_eq!(program.stats().max_relative_base, 0);
+}
+
+#[test]
+fn merges_totals_and_maximums() {
+    let mut total = Stats::new();
+    total.merge(&run(KNOWN, &[4]).stats());
+    total.merge(&run("109,7,104,1,1101,0,0,50,99", &[]).stats());
+
+    assert_eq!(total.executed(), 8 + 4);
+    assert_eq!((total.executed_opcode(9), total.executed_opcode(1), total.executed_opcode(99)), (3, 2, 2));
+    assert_eq!((total.inputs, total.outputs), (1, 3));
+    assert_eq!((total.highest_address, total.peak_extended_memory, total.max_relative_base), (1000, 1, 7));
+
+    // Merging empty statistics changes nothing
+    let before = total.clone();
+    total.merge(&Stats::new());
+    assert_eq!(total, before);
+}
+
+#[test]
+fn reset_starts_counting_again() {
+    let mut program = run(KNOWN, &[4]);
+    program.reset_stats();
+    let stats = program.stats();
+    assert_eq!((stats.executed(), stats.inputs, stats.outputs, stats.max_relative_base), (0, 0, 0, 0));
+    // Extended memory still in use counts towards the peak, but nothing has been touched since
+    assert_eq!((stats.highest_address, stats.peak_extended_memory), (0, 1));
+}
+
+#[test]
+fn highest_address_is_the_highest_one_touched() {
+    // Immediate operands and the instructions themselves don't count
+    let program = run("104,1,99", &[]);
+    assert_eq!((program.stats().highest_address, program.stats().peak_extended_memory), (0, 0));
+    // A load through the relative base, well past the image but never stored to
+    let program = run("109,100,204,400,4,7,99,0", &[]);
+    assert_eq!((program.stats().highest_address, program.stats().peak_extended_memory), (500, 0));
+}
+
+#[test]
+fn stepping_an_exited_machine_counts_hlt_once() {
+    let mut program = run("104,1,99", &[]);
+    assert!(program.step().unwrap() == Some(Event::Exited));
+    assert!(program.execute_until_event().unwrap() == Event::Exited);
+    assert_eq!((program.stats().executed_opcode(99), program.stats().executed()), (1, 2));
+}
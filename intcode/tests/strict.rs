@@ -0,0 +1,39 @@
+use intcode::program::IntcodeProgram;
+use intcode::strict::{DecodeFault, DecodeFaultKind};
+
+fn fault(memory: Vec<i64>) -> DecodeFault {
+    let mut program = IntcodeProgram::from_memory(memory);
+    program.set_strict(true);
+    let error = program.execute().expect_err("strict decoding should fault");
+    assert_eq!(program.ip(), 4, "left at the faulting instruction");
+    *error.downcast::<DecodeFault>().unwrap()
+}
+
+#[test]
+fn rejects_what_lenient_decoding_accepts() {
+    // Each faulting instruction follows an add that writes 5 to cell 0
+    let cases = vec![
+        (vec![1101, 2, 3, 0, 704, 0, 99], DecodeFaultKind::InvalidMode{ parameter: 1, digit: 7 }),
+        (vec![1101, 2, 3, 0, 11101, 0, 0, 0, 99], DecodeFaultKind::ImmediateWrite{ parameter: 3 }),
+        (vec![1101, 2, 3, 0, 103, 0, 99], DecodeFaultKind::ImmediateWrite{ parameter: 1 }),
+        (vec![1101, 2, 3, 0, 10104, 0, 99], DecodeFaultKind::ExtraDigits(10000)),
+        (vec![1101, 2, 3, 0, 199, 99], DecodeFaultKind::ExtraDigits(100)),
+        (vec![1101, 2, 3, 0, 42, 99], DecodeFaultKind::InvalidOpcode(42)),
+    ];
+    for (memory, kind) in cases {
+        let word = memory[4];
+        assert_eq!(fault(memory), DecodeFault{ kind, address: 4, word });
+    }
+}
+
+#[test]
+fn lenient_decoding_is_the_default() {
+    let mut program = IntcodeProgram::from_memory(vec![109, 10, 704, -10, 10104, 5, 99]);
+    program.execute().unwrap();
+    assert_eq!(program.get_all_output(), vec![109, 5]);
+
+    let mut program = IntcodeProgram::from_memory(vec![109, 4, 204, 0, 99]);
+    program.set_strict(true);
+    program.execute().unwrap();
+    assert_eq!(program.get_all_output(), vec![99]);
+}
@@ -0,0 +1,82 @@
+use intcode::asm::assemble;
+use intcode::program::IntcodeProgram;
+use intcode::taint::{Taint, TaintSource};
+
+const FLOWS: &str = "
+    in [a]
+    in [b]
+    in [c]
+    add [a], [b], [sum]
+    out [sum]
+    mul [c], 3, [product]
+    out [product]
+    lt [a], [c], [less]
+    out [less]
+    eq [b], 5, [equal]
+    out [equal]
+    ; Through memory, addressed relative to rb
+    arb copy
+    add [sum], 0, [rb + 1]
+    out [rb + 1]
+    ; Overwriting with constants clears the taint
+    add 1, 2, [sum]
+    out [sum]
+    out [seed]
+    ; Branching on an input doesn't taint what's chosen
+    jnz [a], skip
+    out 6
+skip:
+    out 7
+    hlt
+a: .data 0
+b: .data 0
+c: .data 0
+sum: .data 0
+product: .data 0
+less: .data 0
+equal: .data 0
+seed: .data 42
+copy: .data 0, 0
+";
+
+fn sources(sources: &[TaintSource]) -> Taint {
+    sources.iter().cloned().collect()
+}
+
+#[test]
+fn outputs_carry_the_sources_of_their_values() {
+    let memory = assemble(FLOWS).unwrap();
+    let seed = memory.len() - 3;
+    let mut program = IntcodeProgram::from_memory(memory);
+    program.track_taint(true);
+    program.taint_cell(seed);
+    for input in [1, 5, 2] { program.give_input(input); }
+    program.execute().unwrap();
+
+    let (i0, i1, i2) = (TaintSource::Input(0), TaintSource::Input(1), TaintSource::Input(2));
+    let tracker = program.taint().unwrap();
+    assert_eq!(tracker.inputs_consumed(), 3);
+    assert_eq!(tracker.outputs(), &[
+        (6, sources(&[i0, i1])),
+        (6, sources(&[i2])),
+        (1, sources(&[i0, i2])),
+        (1, sources(&[i1])),
+        (6, sources(&[i0, i1])),
+        (3, sources(&[])),
+        (42, sources(&[TaintSource::Cell(seed)])),
+        (7, sources(&[])),
+    ]);
+
+    // Cells keep the sources of what was last stored in them
+    assert_eq!(tracker.cell(seed + 2), sources(&[i0, i1]));
+    assert_eq!(tracker.cell(seed - 4), sources(&[]));
+    assert_eq!(tracker.cell(seed - 7), sources(&[i0]));
+}
+
+#[test]
+fn tracking_is_off_until_asked_for() {
+    let mut program = IntcodeProgram::from_memory(assemble(FLOWS).unwrap());
+    for input in [1, 5, 2] { program.give_input(input); }
+    program.execute().unwrap();
+    assert!(program.taint().is_none());
+}
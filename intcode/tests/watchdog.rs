@@ -0,0 +1,74 @@
+use intcode::io::ClosedPolicy;
+use intcode::program::IntcodeProgram;
+use intcode::watchdog::Watchdog;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+// Reads a value and outputs it, then exits
+const ECHO: &str = "3,0,4,0,99";
+// Outputs 1, 2 and 3, then reads a value and exits
+const SEND_THEN_READ: &str = "104,1,104,2,104,3,3,0,99";
+// Counts down from 200000 before outputting 1 and exiting, which takes a good few intervals
+const SLOW: &str = "1101,200000,0,14,1001,14,-1,14,1005,14,4,104,1,99,0";
+// Counts down like SLOW, then reads three values and outputs 7
+const SLOW_READER: &str = "1101,200000,0,20,1001,20,-1,20,1005,20,4,3,0,3,0,3,0,104,7,99,0";
+
+// Connects the machines in a ring, each outputting to the next, and runs them to completion
+fn ring(watchdog: &Arc<Watchdog>, sources: &[&str], capacity: Option<usize>, first_input: Option<i64>) -> Vec<Result<(), String>> {
+    let ids: Vec<usize> = (0..sources.len()).map(|i| watchdog.add_machine(&format!("m{}", i))).collect();
+    let mut programs: Vec<IntcodeProgram> = sources.iter().map(|source| IntcodeProgram::from_raw_input(source).unwrap()).collect();
+    for (i, &id) in ids.iter().enumerate() {
+        let next = (i + 1) % ids.len();
+        let (output, input) = watchdog.connect(id, ids[next], capacity, ClosedPolicy::Drop);
+        programs[i].replace_output(output);
+        programs[next].replace_input(input);
+    }
+    if let Some(input) = first_input { programs[0].give_input(input); }
+
+    let threads: Vec<_> = programs.into_iter().map(|mut program| {
+        thread::spawn(move || program.execute().map_err(|e| e.to_string()))
+    }).collect();
+    threads.into_iter().map(|thread| thread.join().unwrap()).collect()
+}
+
+fn watchdog() -> Arc<Watchdog> {
+    Watchdog::with_interval(Duration::from_millis(10))
+}
+
+#[test]
+fn finds_readers_waiting_on_each_other() {
+    let watchdog = watchdog();
+    let results = ring(&watchdog, &[ECHO, ECHO, ECHO], None, None);
+    let report = watchdog.deadlock().unwrap();
+    // One machine finds the deadlock, and the others fail as their inputs close
+    assert!(results.iter().all(|result| result.as_ref().unwrap_err().ends_with(&format!("deadlock: {}", report)) ||
+        result.as_ref().unwrap_err() == &format!("Deadlock: {}", report)), "{:?}", results);
+    for machine in 0..3 {
+        assert!(report.contains(&format!("m{} waits on m{}", (machine + 1) % 3, machine)), "{}", report);
+    }
+}
+
+#[test]
+fn finds_writers_blocked_on_full_channels() {
+    let watchdog = watchdog();
+    let results = ring(&watchdog, &[SEND_THEN_READ, SEND_THEN_READ], Some(1), None);
+    let report = watchdog.deadlock().unwrap();
+    // Once one fails the other may go on to finish, reading the value that got through
+    assert!(results.iter().any(|result| result.as_ref().err() == Some(&format!("Deadlock: {}", report))), "{:?}", results);
+    assert!(report.contains("m0 waits to send to m1") && report.contains("m1 waits to send to m0"), "{}", report);
+}
+
+#[test]
+fn leaves_machines_that_can_proceed_alone() {
+    // A value makes its way round the ring
+    let watchdog = watchdog();
+    assert!(ring(&watchdog, &[ECHO, ECHO, ECHO], None, Some(5)).iter().all(|result| result.is_ok()));
+    // A reader waits on a machine that's busy computing rather than waiting itself
+    assert!(ring(&watchdog, &[SLOW, ECHO], None, None).iter().all(|result| result.is_ok()));
+    // Channels with room for everything sent never block
+    assert!(ring(&watchdog, &[SEND_THEN_READ, SEND_THEN_READ], Some(3), None).iter().all(|result| result.is_ok()));
+    // A writer blocked on a full channel is unblocked by a slow reader
+    assert!(ring(&watchdog, &[SEND_THEN_READ, SLOW_READER], Some(0), None).iter().all(|result| result.is_ok()));
+    assert_eq!(watchdog.deadlock(), None);
+}